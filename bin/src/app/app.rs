@@ -4,6 +4,20 @@ pub fn gen_app() -> App<'static> {
     let app = App::new("NotesManager")
         .setting(AppSettings::ArgRequiredElseHelp)
         .version(env!("CARGO_PKG_VERSION"))
+        .arg(Arg::new("profile")
+            .long("profile")
+            .takes_value(true)
+            .required(false)
+            .about("Name of the config profile that should be used, uses the default profile if not set")
+        )
+        .arg(Arg::new("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .global(true)
+            .required(false)
+            .about("Output format, json is supported by list, print, history, diff, sync and for errors")
+        )
         .subcommand(App::new("list")
             .about("Lists all available notes")
             .arg(Arg::new("uuid")
@@ -24,6 +38,12 @@ pub fn gen_app() -> App<'static> {
                 .about("Prints deleted notes only")
                 .required(false)
             )
+            .arg(Arg::new("all-profiles")
+                .short('a')
+                .long("all-profiles")
+                .about("Lists the notes of all profiles")
+                .required(false)
+            )
         )
         .about("Interface for interacting with Apple Notes on Linux")
         .subcommand(App::new("edit")
@@ -40,8 +60,17 @@ pub fn gen_app() -> App<'static> {
                 .required(false)
                 .short('n')
                 .takes_value(false)
-                .about("Dry run")
+                .about("Dry run, prints the planned actions without changing anything")
             )
+            .arg(Arg::new("json")
+                .long("json")
+                .required(false)
+                .takes_value(false)
+                .about("Prints the planned or processed actions as json, same as --format json")
+            )
+        )
+        .subcommand(App::new("watch")
+            .about("Keeps a connection to the imap server open and syncs every time notes change remotely")
         )
         .subcommand(App::new("delete")
             .about("Flags a note as deleted")
@@ -67,6 +96,65 @@ pub fn gen_app() -> App<'static> {
                 .about("Subject or UUID of the note that should be merged")
             )
         )
+        .subcommand(App::new("move")
+            .about("Moves a note into another folder")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .about("Subject or UUID of the note that should be moved")
+            )
+            .arg(Arg::new("folder")
+                .required(false)
+                .takes_value(true)
+                .about("Subfolder the note should be moved to. Uses default folder, if not used")
+            )
+        )
+        .subcommand(App::new("config")
+            .about("Checks or converts the config file")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("check")
+                .about("Validates the config file and lists its profiles")
+            )
+            .subcommand(App::new("migrate")
+                .about("Converts the old key=value config file into config.toml")
+            )
+        )
+        .subcommand(App::new("folder")
+            .about("Manages note folders")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("list")
+                .about("Lists all local and remote folders")
+            )
+            .subcommand(App::new("create")
+                .about("Creates a new folder, nested folders are separated with \"/\"")
+                .arg(Arg::new("name")
+                    .required(true)
+                    .takes_value(true)
+                    .about("Name of the new folder")
+                )
+            )
+            .subcommand(App::new("rename")
+                .about("Renames a folder including all of its subfolders")
+                .arg(Arg::new("old")
+                    .required(true)
+                    .takes_value(true)
+                    .about("Current name of the folder")
+                )
+                .arg(Arg::new("new")
+                    .required(true)
+                    .takes_value(true)
+                    .about("New name of the folder")
+                )
+            )
+            .subcommand(App::new("delete")
+                .about("Deletes an empty folder")
+                .arg(Arg::new("name")
+                    .required(true)
+                    .takes_value(true)
+                    .about("Name of the folder that should be deleted")
+                )
+            )
+        )
         .subcommand(App::new("print")
             .about("Prints note content")
             .arg(Arg::new("path")
@@ -75,8 +163,78 @@ pub fn gen_app() -> App<'static> {
                 .about("Subject or UUID of the note that should be printed")
             )
         )
+        .subcommand(App::new("history")
+            .about("Lists the previous versions of a note")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .about("Subject or UUID of the note")
+            )
+        )
+        .subcommand(App::new("diff")
+            .about("Shows the changes between a previous version and the current text of a note")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .about("Subject or UUID of the note")
+            )
+            .arg(Arg::new("rev")
+                .required(true)
+                .takes_value(true)
+                .validator(|rev: &str| rev.parse::<usize>())
+                .about("Number of the version, as listed by history")
+            )
+        )
+        .subcommand(App::new("restore")
+            .about("Restores a previous version of a note, it gets uploaded with the next sync")
+            .arg(Arg::new("path")
+                .required(true)
+                .takes_value(true)
+                .about("Subject or UUID of the note")
+            )
+            .arg(Arg::new("rev")
+                .required(true)
+                .takes_value(true)
+                .validator(|rev: &str| rev.parse::<usize>())
+                .about("Number of the version, as listed by history")
+            )
+        )
+        .subcommand(App::new("search")
+            .about("Searches the content of all notes")
+            .arg(Arg::new("query")
+                .required(true)
+                .takes_value(true)
+                .multiple(true)
+                .about("Words that should occur inside the note, matches word prefixes")
+            )
+        )
+        .subcommand(App::new("todo")
+            .about("Lists unchecked checklist items of all notes")
+        )
+        .subcommand(App::new("export")
+            .about("Exports all notes as markdown files into a directory")
+            .arg(Arg::new("dir")
+                .required(true)
+                .takes_value(true)
+                .about("Directory the notes should be written to, gets created if it does not exist")
+            )
+        )
+        .subcommand(App::new("import")
+            .about("Imports all markdown and text files inside a directory as new notes")
+            .arg(Arg::new("dir")
+                .required(true)
+                .takes_value(true)
+                .about("Directory that contains the files, subdirectories are used as folders")
+            )
+        )
         .subcommand(App::new("backup")
             .about("Duplicates current note tree on the imap server")
+            .arg(Arg::new("list")
+                .short('l')
+                .long("list")
+                .about("Lists existing backups instead of creating a new one")
+                .required(false)
+            )
         )
         .subcommand(App::new("new")
             .about("Creates a new note")
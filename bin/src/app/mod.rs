@@ -1,3 +1,2 @@
 pub mod app;
-
-
+pub mod output;
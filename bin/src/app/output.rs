@@ -0,0 +1,92 @@
+extern crate serde_json;
+
+use clap::ArgMatches;
+use self::serde_json::{json, Value};
+use apnotes_lib::SyncResult;
+use apnotes_lib::error::ErrorCode;
+use apnotes_lib::notes::localnote::LocalNote;
+use apnotes_lib::notes::revision::Revision;
+use apnotes_lib::notes::traits::identifyable_note::{IdentifiableNote, Subject};
+
+/// Output format selected with the global --format argument
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json
+}
+
+impl OutputFormat {
+    pub fn from_matches(matches: &ArgMatches) -> OutputFormat {
+        match format_value(matches) {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Text
+        }
+    }
+}
+
+/// Global arguments that follow a subcommand are only part of the matches of the subcommand
+fn format_value(matches: &ArgMatches) -> Option<&str> {
+    matches.subcommand()
+        .and_then(|(_, sub_matches)| format_value(sub_matches))
+        .or(matches.value_of("format"))
+}
+
+/// Prints a json document to stdout, log messages go to stderr in json mode
+pub fn print_json(value: &Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+pub fn note_json(note: &LocalNote) -> Value {
+    json!({
+        "uuid": note.metadata.uuid,
+        "folder": note.metadata.folder(),
+        "subject": note.first_subject(),
+        "date": note.metadata.date,
+        "subjects": note.body.iter().map(|body| body.subject()).collect::<Vec<String>>(),
+        "needs_merge": note.needs_merge(),
+        "locally_deleted": note.metadata.locally_deleted,
+        "new": note.metadata.new,
+        "edited": note.metadata.edited
+    })
+}
+
+/// Note including its text, notes that need to be merged can not be printed
+pub fn note_content_json(note: &LocalNote) -> Value {
+    let mut value = note_json(note);
+    value["text"] = json!(note.body[0].text.clone().unwrap_or_default());
+    value
+}
+
+pub fn revision_json(revision: &Revision) -> Value {
+    json!({
+        "revision": revision.number,
+        "date": revision.date,
+        "source": revision.source,
+        "text": revision.text
+    })
+}
+
+pub fn sync_result_json(result: &SyncResult) -> Value {
+    let mut value = json!({
+        "action": result.action,
+        "subject": result.subject,
+        "folder": result.folder,
+        "reason": result.reason,
+        "ok": result.result.is_ok()
+    });
+    if let Err(e) = &result.result {
+        value["error"] = json!(e.to_string());
+        value["error_code"] = json!(e.error_code());
+    }
+    value
+}
+
+pub fn error_json(error: &Box<dyn ErrorCode>) -> Value {
+    json!({
+        "error": {
+            "code": error.error_code(),
+            "message": error.human_readable_error_message(),
+            "details": error.to_string()
+        }
+    })
+}
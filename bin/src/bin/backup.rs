@@ -1,23 +1,47 @@
 extern crate apnotes_lib;
+extern crate clap;
+#[macro_use]
 extern crate log;
+extern crate flexi_logger;
 
-fn main() {
-    //simple_logger::init().unwrap();
-    /*let mut session = apple_imap::login();
-    let folders = list_note_folders(&mut session);
-    info!("Loaded {} folders", folders.len());
-    folders.iter().for_each(|folder| {
-        let uids = get_uids(&mut session, folder.to_string());
-        uids.iter().for_each( |folder_uid_pairs| {
-            let backup_folder = "Backup_Notes.".to_owned() + folder_uid_pairs.0 + "_backup";
-            create_folder(&mut session, &backup_folder);
-            folder_uid_pairs.1.iter().for_each( |id| {
-                info!("{} {}", folder_uid_pairs.0, id.unwrap_or(0));
-                if let Some(id) = id {
-                    copy_uid(&mut session, id.to_string().as_ref(), &backup_folder);
-                }
-            })
-        })
-    })*/
+use clap::{App, Arg};
+use flexi_logger::Logger;
 
-}
\ No newline at end of file
+/// Copies all note folders into a new backup folder on the imap server,
+/// same as "apnotes backup"
+#[tokio::main]
+async fn main() {
+    let matches = App::new("apnotes-backup")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(Arg::new("profile")
+            .long("profile")
+            .takes_value(true)
+            .required(false)
+            .about("Name of the config profile that should be used, uses the default profile if not set")
+        )
+        .get_matches();
+
+    Logger::try_with_env_or_str("info").unwrap().log_to_stdout().start().unwrap();
+
+    let profile = match ::apnotes_lib::get_user_profile(matches.value_of("profile")) {
+        Ok(profile) => profile,
+        Err(e) => {
+            error!("Could not load profile: {}", e.to_string());
+            std::process::exit(e.error_code());
+        }
+    };
+
+    let apple_notes = ::apnotes_lib::AppleNotes::for_profile(profile);
+
+    let result = apple_notes.backup(|folder, copied, done, total| {
+        info!("[{}/{}] Copied {} notes from {}", done, total, copied, folder);
+    }).await;
+
+    match result {
+        Ok(backup) => info!("Created backup {}", backup),
+        Err(e) => {
+            error!("Backup failed: {} - ({})", e.to_string(), e.error_code().to_string());
+            std::process::exit(e.error_code());
+        }
+    }
+}
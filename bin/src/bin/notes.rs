@@ -12,11 +12,13 @@ extern crate serde_json;
 use clap::{ArgMatches};
 use colored::Colorize;
 use itertools::*;
-use apnotes_lib::AppleNotes;
-use apnotes_lib::notes::traits::identifyable_note::IdentifiableNote;
+use apnotes_lib::{AppleNotes, SyncResult};
+use apnotes_lib::notes::localnote::LocalNote;
+use apnotes_lib::notes::traits::identifyable_note::{IdentifiableNote, Subject};
 use flexi_logger::{Logger, Record, DeferredNow};
 use apnotes_bin::app::app::gen_app;
-use apnotes_lib::error::Result;
+use apnotes_bin::app::output::{OutputFormat, print_json, note_json, note_content_json, revision_json, sync_result_json, error_json};
+use apnotes_lib::error::{Result, ErrorCode, NoteError};
 
 //Minimal println like formatting for flexi_logger
 pub fn default_format(
@@ -34,41 +36,64 @@ pub fn default_format(
 #[tokio::main]
 pub async fn main() {
 
-    Logger::try_with_env_or_str("info, html5ever=error").unwrap().log_to_stdout().format(default_format).start().unwrap();
-
     let app = gen_app();
 
-    let db_connection= ::apnotes_lib::db::SqliteDBConnection::new();
-
     let matches = app.get_matches();
 
-    match ::apnotes_lib::get_user_profile() {
+    // sync --json is a shorthand for --format json
+    let format = match matches.subcommand_matches("sync") {
+        Some(sync) if sync.is_present("json") => OutputFormat::Json,
+        _ => OutputFormat::from_matches(&matches)
+    };
+
+    // Json output goes to stdout, the log messages to stderr
+    let logger = Logger::try_with_env_or_str("info, html5ever=error").unwrap().format(default_format);
+    if format == OutputFormat::Json {
+        logger.start().unwrap();
+    } else {
+        logger.log_to_stdout().start().unwrap();
+    }
+
+    // The config commands have to work without a valid profile
+    if let Some(("config", sub_matches)) = matches.subcommand() {
+        if let Err(e) = manage_config(sub_matches) {
+            exit_with_error(e, format);
+        }
+        return;
+    }
+
+    match ::apnotes_lib::get_user_profile(matches.value_of("profile")) {
         Ok(profile) => {
-            let apple_notes = ::apnotes_lib::AppleNotes::new(
-                profile,
-                Box::new(db_connection)
-            );
+            let apple_notes = ::apnotes_lib::AppleNotes::for_profile(profile);
 
             let result = match matches.subcommand() {
                 Some(("new",  sub_matches)) => new(sub_matches,&apple_notes),
-                Some(("sync", sub_matches)) => sync_notes(sub_matches, &apple_notes).await.map(|_| ()),
-                Some(("list", sub_matches)) => list_notes(sub_matches,&apple_notes),
+                Some(("sync", sub_matches)) => sync_notes(sub_matches, &apple_notes, format).await,
+                Some(("watch", _)) => watch_notes(&apple_notes).await,
+                Some(("list", sub_matches)) => list_notes(sub_matches,&apple_notes, format),
                 Some(("edit", sub_matches)) => edit_passed_note(sub_matches,&apple_notes),
                 Some(("merge", sub_matches)) => merge_note(sub_matches,&apple_notes),
+                Some(("move", sub_matches)) => move_note(sub_matches,&apple_notes),
                 Some(("delete", sub_matches)) => delete_note(sub_matches,&apple_notes),
                 Some(("undelete", sub_matches)) => undelete_note(sub_matches,&apple_notes),
-                Some(("print", sub_matches)) => print_note(sub_matches, &apple_notes),
+                Some(("folder", sub_matches)) => manage_folders(sub_matches, &apple_notes).await,
+                Some(("print", sub_matches)) => print_note(sub_matches, &apple_notes, format),
+                Some(("history", sub_matches)) => note_history(sub_matches, &apple_notes, format),
+                Some(("diff", sub_matches)) => diff_note(sub_matches, &apple_notes, format),
+                Some(("restore", sub_matches)) => restore_note(sub_matches, &apple_notes),
+                Some(("search", sub_matches)) => search_notes(sub_matches, &apple_notes),
+                Some(("todo", _)) => list_todos(&apple_notes),
+                Some(("export", sub_matches)) => export_notes(sub_matches, &apple_notes),
+                Some(("import", sub_matches)) => import_notes(sub_matches, &apple_notes),
+                Some(("backup", sub_matches)) => backup_notes(sub_matches, &apple_notes).await,
                 _ => unreachable!(),
             };
 
-            match result {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("Error: {}\n{} - ({})", e.human_readable_error_message(), e.to_string(), e.error_code().to_string());
-                    std::process::exit(e.error_code());
-                },
+            if let Err(e) = result {
+                exit_with_error(e, format);
             }
         }
+        Err(e) if format == OutputFormat::Json => exit_with_error(e, format),
         Err(e) => {
             error!("Could not load profile: {}", e.to_string());
         }
@@ -78,9 +103,115 @@ pub async fn main() {
 
 }
 
-fn print_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+/// Reports the error and exits with its error code
+fn exit_with_error(e: Box<dyn ErrorCode>, format: OutputFormat) -> ! {
+    match format {
+        OutputFormat::Json => print_json(&error_json(&e)),
+        OutputFormat::Text => error!("Error: {}\n{} - ({})", e.human_readable_error_message(), e.to_string(), e.error_code().to_string())
+    }
+    std::process::exit(e.error_code());
+}
+
+fn manage_config(sub_matches: &ArgMatches) -> Result<()> {
+    match sub_matches.subcommand() {
+        Some(("check", _)) => {
+            ::apnotes_lib::check_config().map(|config| {
+                let format = if config.legacy { " (old key=value format)" } else { "" };
+                info!("{} is valid{}", config.path.display(), format);
+                config.profiles.iter().for_each(|profile| {
+                    if config.default_profile.as_ref() == Some(profile) {
+                        info!("  {} <<default>>", profile);
+                    } else {
+                        info!("  {}", profile);
+                    }
+                });
+            })
+        }
+        Some(("migrate", _)) => {
+            ::apnotes_lib::migrate_config().map(|path| info!("Created {}, the old config file can be deleted", path.display()))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn print_note(sub_matches: &ArgMatches, app: &AppleNotes, format: OutputFormat) -> Result<()> {
     let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
-    app.print(&uuid_or_name)
+    match format {
+        OutputFormat::Text => app.print(&uuid_or_name),
+        OutputFormat::Json => app.find_note(&uuid_or_name).and_then(|note| {
+            if note.needs_merge() {
+                return Err(NoteError::NeedsMerge.into());
+            }
+            print_json(&note_content_json(&note));
+            Ok(())
+        })
+    }
+}
+
+fn note_history(sub_matches: &ArgMatches, app: &AppleNotes, format: OutputFormat) -> Result<()> {
+    let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
+    app.history(&uuid_or_name).map(|revisions| {
+        if format == OutputFormat::Json {
+            let revisions: Vec<serde_json::Value> = revisions.iter().map(revision_json).collect();
+            print_json(&serde_json::json!({ "revisions": revisions }));
+            return;
+        }
+
+        if revisions.is_empty() {
+            info!("No previous versions");
+        }
+
+        revisions.iter().rev().for_each(|revision| {
+            let subject = revision.text.lines().next().unwrap_or("");
+            info!("{:>3}  {}  {:<13}  [{}]", revision.number, revision.date, revision.source, subject);
+        });
+    })
+}
+
+fn diff_note(sub_matches: &ArgMatches, app: &AppleNotes, format: OutputFormat) -> Result<()> {
+    let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
+    // The validator of the argument makes sure that it is a number
+    let revision = sub_matches.value_of("rev").unwrap().parse::<usize>().unwrap();
+    app.diff(&uuid_or_name, revision).map(|diff| {
+        if format == OutputFormat::Json {
+            print_json(&serde_json::json!({ "revision": revision, "diff": diff }));
+            return;
+        }
+
+        diff.lines().for_each(|line| {
+            if line.starts_with('-') {
+                info!("{}", line.red());
+            } else if line.starts_with('+') {
+                info!("{}", line.green());
+            } else {
+                info!("{}", line);
+            }
+        });
+    })
+}
+
+fn restore_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
+    let revision = sub_matches.value_of("rev").unwrap().parse::<usize>().unwrap();
+    app.restore(&uuid_or_name, revision)
+        .map(|_| info!("Restored version {}, it gets uploaded with the next sync", revision))
+}
+
+fn search_notes(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    let query = sub_matches.values_of("query").unwrap().join(" ");
+
+    app.search(&query)
+        .map(|results| {
+            let max_len = results.iter()
+                .map(|result| format!("{} {}", result.note.metadata.uuid, result.note.metadata.folder()).len())
+                .max()
+                .unwrap_or(0);
+
+            results.iter().for_each(|result| {
+                let formatted_uuid_folder = format!("{} {}", result.note.metadata.uuid, result.note.metadata.folder());
+                info!("{:<width$}  [{}] {}", formatted_uuid_folder, result.note.first_subject(), result.snippet, width = max_len);
+            });
+        })
 }
 
 fn undelete_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
@@ -93,6 +224,35 @@ fn delete_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
     app.delete_note(&uuid_or_name)
 }
 
+fn move_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
+    let folder = sub_matches.value_of("folder").unwrap_or("");
+    app.move_note(&uuid_or_name, folder)
+}
+
+async fn manage_folders(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    match sub_matches.subcommand() {
+        Some(("list", _)) => {
+            app.list_folders().await.map(|folders| {
+                folders.iter().for_each(|folder| {
+                    let name = if folder.name.is_empty() { "<default>".to_string() } else { folder.name.clone() };
+                    if folder.remote {
+                        info!("{:<30} {} notes", name, folder.note_count);
+                    } else {
+                        info!("{:<30} {} notes {}", name, folder.note_count, "<<local only>>".yellow());
+                    }
+                })
+            })
+        }
+        Some(("create", folder_matches)) => app.create_folder(folder_matches.value_of("name").unwrap()).await,
+        Some(("rename", folder_matches)) => {
+            app.rename_folder(folder_matches.value_of("old").unwrap(), folder_matches.value_of("new").unwrap()).await
+        }
+        Some(("delete", folder_matches)) => app.delete_folder(folder_matches.value_of("name").unwrap()).await,
+        _ => unreachable!(),
+    }
+}
+
 fn merge_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
     let uuid_or_name = sub_matches.value_of("path").unwrap().to_string();
     app.merge(&uuid_or_name)
@@ -105,34 +265,62 @@ fn edit_passed_note(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
         .and_then(|note| app.update_note(&note).map_err(|e| e.into()))
 }
 
-fn list_notes(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()>{
+fn list_notes(sub_matches: &ArgMatches, app: &AppleNotes, format: OutputFormat) -> Result<()>{
     let _show_uuid = sub_matches.is_present("uuid");
     let print_names_only = sub_matches.is_present("names");
     let show_only_deleted = sub_matches.is_present("deleted");
+    let all_profiles = sub_matches.is_present("all-profiles");
+
+    let notes_per_profile = if all_profiles {
+        ::apnotes_lib::get_notes_of_all_profiles()
+    } else {
+        app.get_notes().map(|notes| vec![(app.profile_name().to_string(), notes)])
+    };
+
+    notes_per_profile
+        .and_then(|notes_per_profile| {
+
+            let notes: Vec<(String, LocalNote)> = notes_per_profile.into_iter()
+                .flat_map(|(profile, notes)| notes.into_iter().map(move |note| (profile.clone(), note)))
+                .filter(|(_, note)| show_only_deleted == false || note.metadata.locally_deleted)
+                .collect();
+
+            if format == OutputFormat::Json {
+                let notes: Vec<serde_json::Value> = notes.iter()
+                    .sorted_by_key(|(profile, note)| format!("{}_{}_{}", profile, &note.metadata.subfolder, &note.body[0].subject()))
+                    .map(|(profile, note)| {
+                        let mut value = note_json(note);
+                        value["profile"] = serde_json::json!(profile);
+                        value
+                    })
+                    .collect();
+                print_json(&serde_json::json!({ "notes": notes }));
+                return Ok(());
+            }
 
-    app.get_notes()
-        .and_then(|notes| {
-
-            let notes = if show_only_deleted {
-                notes.into_iter().filter(|note| note.metadata.locally_deleted).collect()
-            } else {
-                notes
+            // Notes of all profiles are prefixed with the name of their profile
+            let uuid_folder = |profile: &str, note: &LocalNote| {
+                if all_profiles {
+                    format!("{} {} {}", profile, note.metadata.uuid, note.metadata.folder())
+                } else {
+                    format!("{} {}", note.metadata.uuid, note.metadata.folder())
+                }
             };
 
             let max_len = notes.iter()
-                .map(|note| format!("{} {}", note.metadata.uuid, note.metadata.folder()).len())
+                .map(|(profile, note)| uuid_folder(profile, note).len())
                 .max()
                 .unwrap_or(0);
 
             notes.iter().
-                sorted_by_key(|note| format!("{}_{}",&note.metadata.subfolder, &note.body[0].subject()))
-                .for_each(|ee| {
+                sorted_by_key(|(profile, note)| format!("{}_{}_{}", profile, &note.metadata.subfolder, &note.body[0].subject()))
+                .for_each(|(profile, ee)| {
                     let titles = ee.body.iter()
                         .map(|body| body.subject())
                         .join(",");
 
 
-                    let formatted_uuid_folder = format!("{} {}", ee.metadata.uuid, ee.metadata.folder());
+                    let formatted_uuid_folder = uuid_folder(profile, ee);
 
                     let formatted_string = if print_names_only {
                         let subject = ee.body.iter().last().unwrap().subject();
@@ -168,7 +356,81 @@ fn new(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
         .map_err(|e| e.into())
 }
 
-async fn sync_notes(sub_matches: &ArgMatches, app:&AppleNotes) -> Result<()> {
+async fn sync_notes(sub_matches: &ArgMatches, app:&AppleNotes, format: OutputFormat) -> Result<()> {
     let is_dry_run = sub_matches.is_present("dry-run");
-    app.sync_notes(is_dry_run).await.map(|_| ())
-}
\ No newline at end of file
+
+    app.sync_notes(is_dry_run).await.map(|results| {
+        if format == OutputFormat::Json {
+            let actions: Vec<serde_json::Value> = results.iter().map(sync_result_json).collect();
+            print_json(&serde_json::json!({ "dry_run": is_dry_run, "actions": actions }));
+        } else if is_dry_run {
+            print_sync_plan(&results);
+        }
+    })
+}
+
+/// Prints the planned actions of a dry run as table
+fn print_sync_plan(results: &Vec<SyncResult>) {
+    if results.is_empty() {
+        info!("Nothing to sync");
+        return;
+    }
+
+    let width = |title: &str, column: fn(&SyncResult) -> &String| {
+        results.iter().map(|result| column(result).chars().count()).max().unwrap_or(0).max(title.len())
+    };
+    let action_width = width("ACTION", |result| &result.action);
+    let subject_width = width("SUBJECT", |result| &result.subject);
+    let folder_width = width("FOLDER", |result| &result.folder);
+
+    info!("{:<aw$}  {:<sw$}  {:<fw$}  {}", "ACTION".bold(), "SUBJECT".bold(), "FOLDER".bold(), "REASON".bold(),
+          aw = action_width, sw = subject_width, fw = folder_width);
+    results.iter().for_each(|result| {
+        info!("{:<aw$}  {:<sw$}  {:<fw$}  {}", result.action, result.subject, result.folder, result.reason,
+              aw = action_width, sw = subject_width, fw = folder_width);
+    });
+}
+
+fn list_todos(app: &AppleNotes) -> Result<()> {
+    app.todos()
+        .map(|todos| {
+            todos.iter().for_each(|todo| {
+                info!("{} {}  [{}]", todo.note.metadata.uuid, todo.note.metadata.folder(), todo.note.first_subject().bold());
+                todo.open_items.iter().for_each(|item| info!("  - [ ] {}", item));
+            });
+        })
+}
+
+fn export_notes(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    let dir = std::path::Path::new(sub_matches.value_of("dir").unwrap());
+    app.export(dir)
+        .map(|count| info!("Exported {} notes to {}", count, dir.display()))
+}
+
+fn import_notes(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    let dir = std::path::Path::new(sub_matches.value_of("dir").unwrap());
+    app.import(dir)
+        .map(|notes| info!("Imported {} notes, they get uploaded with the next sync", notes.len()))
+}
+
+async fn backup_notes(sub_matches: &ArgMatches, app: &AppleNotes) -> Result<()> {
+    if sub_matches.is_present("list") {
+        return app.list_backups().await.map(|backups| {
+            backups.iter().for_each(|backup| info!("{}", backup));
+        });
+    }
+
+    app.backup(|folder, copied, done, total| {
+        info!("[{}/{}] Copied {} notes from {}", done, total, copied, folder);
+    }).await
+        .map(|backup| info!("Created backup {}", backup.green()))
+}
+
+async fn watch_notes(app: &AppleNotes) -> Result<()> {
+    info!("Watching for remote changes, press Ctrl-C to stop");
+    app.watch(|result| {
+        if let Err(e) = result {
+            error!("Sync failed: {} - ({})", e.to_string(), e.error_code().to_string());
+        }
+    }).await
+}
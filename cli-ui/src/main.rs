@@ -7,6 +7,7 @@ extern crate regex;
 extern crate tui;
 extern crate crossterm;
 extern crate futures;
+extern crate clap;
 
 use futures::executor::block_on;
 
@@ -15,7 +16,6 @@ mod ui;
 use std::sync::{mpsc, Mutex, Arc};
 use std::time::{Duration};
 use std::{thread};
-use apnotes_lib::db::{SqliteDBConnection};
 use std::thread::{sleep, JoinHandle};
 use crate::Outcome::{Success, Failure, End, Busy};
 use apnotes_lib::AppleNotes;
@@ -63,12 +63,10 @@ struct App {
 
 impl App {
 
-    pub fn new(action_receiver: Receiver<Task>, event_sender: Sender<Event<KeyEvent>>) -> App {
+    pub fn new(profile_name: Option<&str>, action_receiver: Receiver<Task>, event_sender: Sender<Event<KeyEvent>>) -> App {
 
-        let profile = apnotes_lib::get_user_profile();
-        let db_connection = SqliteDBConnection::new();
-        let connection = Box::new(db_connection);
-        let app = apnotes_lib::AppleNotes::new(profile.unwrap(), connection);
+        let profile = apnotes_lib::get_user_profile(profile_name);
+        let app = apnotes_lib::AppleNotes::for_profile(profile.unwrap());
 
         let app = App {
             apple_notes: Arc::new(Mutex::new(app)),
@@ -116,7 +114,7 @@ impl App {
                             match next_action {
                                 Task::NewNote(name) => {
                                     let d = app_lock.lock().unwrap();
-                                    let result = match name.split_once("/") {
+                                    let result = match name.rsplit_once("/") {
                                         Some((folder, name)) => d.create_new_note(name, folder),
                                         None => d.create_new_note(&name, &String::new())
                                     };
@@ -169,15 +167,56 @@ impl App {
 
 }
 
+/// Name of the config profile passed with "--profile <name>"
+fn profile_name() -> Option<String> {
+    let matches = clap::App::new("apnotes-tui")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(clap::Arg::new("profile")
+            .long("profile")
+            .takes_value(true)
+            .required(false)
+            .about("Name of the config profile that should be used, uses the default profile if not set")
+        )
+        .get_matches();
+
+    matches.value_of("profile").map(|name| name.to_string())
+}
+
 fn main() {
 
+    let profile_name = profile_name();
+
     let (event_sender, event_receiver) = mpsc::channel();
     let (action_tx, action_rx) = mpsc::channel::<Task>();
 
-    let app = App::new(action_rx, event_sender.clone());
+    let app = App::new(profile_name.as_deref(), action_rx, event_sender.clone());
 
     let handle = app.start_action_event_loop();
 
+    let watch_sender = action_tx.clone();
+    let watch_events = event_sender.clone();
+    thread::spawn(move || {
+        let profile = match apnotes_lib::get_user_profile(profile_name.as_deref()) {
+            Ok(profile) => profile,
+            Err(e) => {
+                let _ = watch_events.send(Event::OutCome(Failure(format!("Could not watch for remote changes: {}", e))));
+                return;
+            }
+        };
+        // The ui triggers the initial sync by itself
+        let mut connected_before = false;
+        let result = block_on(apnotes_lib::watch_remote_changes(&profile, || {
+            if connected_before == false {
+                connected_before = true;
+                return true;
+            }
+            watch_sender.send(Task::Sync).is_ok()
+        }));
+        if let Err(e) = result {
+            let _ = watch_events.send(Event::OutCome(Failure(format!("Stopped watching for remote changes: {}", e))));
+        }
+    });
+
     let ui_state = UiState {
         action_sender: action_tx,
         event_receiver,
@@ -198,7 +237,9 @@ fn main() {
         text: "".to_string(),
         scroll_amount: 0,
         in_search_mode: false,
-        new_note_mode: false
+        new_note_mode: false,
+        move_note_mode: false,
+        folder_input: String::new()
     };
 
     ui.run().unwrap();
@@ -1,5 +1,4 @@
 use crate::{Outcome, Event, Task};
-use apnotes_lib::db::DatabaseService;
 use apnotes_lib::notes::localnote::LocalNote;
 use tui::widgets::{Wrap, Borders, Block, Paragraph, ListState, ListItem, List};
 use tui::style::{Style, Color, Modifier};
@@ -47,7 +46,10 @@ pub struct Ui<'u> {
     pub text: String,
     pub scroll_amount: u16,
     pub in_search_mode: bool,
-    pub new_note_mode: bool
+    pub new_note_mode: bool,
+    pub move_note_mode: bool,
+    /// Folder name typed in move mode, kept apart from keyword so that the search stays active
+    pub folder_input: String
 }
 
 impl<'u> Ui<'u> {
@@ -208,6 +210,48 @@ impl<'u> Ui<'u> {
                     }
                     _ => {}
                 }
+            } else if self.move_note_mode {
+                match received_keystroke {
+                    Event::Input(event) => match event.code {
+                        KeyCode::Char(c) => {
+                            self.folder_input.push(c);
+                            self.status = format!("Move to folder: {}", self.folder_input);
+                        }
+                        KeyCode::Backspace => {
+                            self.folder_input.pop();
+                            self.status = format!("Move to folder: {}", self.folder_input);
+                        }
+                        KeyCode::Esc => {
+                            self.status = "".to_string();
+                            self.color = Color::White;
+                            self.move_note_mode = false;
+                            self.folder_input.clear();
+                        }
+                        KeyCode::Enter => {
+                            let folder = std::mem::take(&mut self.folder_input);
+                            self.move_note_mode = false;
+
+                            if let Some(uuid) = self.get_old_selected_entry_uuid() {
+                                let result = self.app.lock().unwrap().move_note(&uuid, &folder);
+                                match result {
+                                    Ok(_) => {
+                                        self.color = Color::Green;
+                                        self.status = "Note will be moved with the next sync".to_string();
+                                        self.refresh();
+                                        self.select_entry(Some(uuid));
+                                        self.reload_text();
+                                    }
+                                    Err(e) => {
+                                        self.color = Color::Red;
+                                        self.status = e.to_string();
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    _ => {}
+                }
             } else {
                 match received_keystroke {
                     Event::Input(event) => match event.code {
@@ -244,6 +288,12 @@ impl<'u> Ui<'u> {
                             self.color = Color::Cyan;
                             self.new_note_mode = true;
                         },
+                        KeyCode::Char('M') => {
+                            self.folder_input.clear();
+                            self.status = "Move to folder: ".to_string();
+                            self.color = Color::Cyan;
+                            self.move_note_mode = true;
+                        },
                         KeyCode::Char('m') => {
                             let note = self.entries.get(self.note_list_state.selected().unwrap()).unwrap();
                             let result =  {
@@ -293,8 +343,7 @@ impl<'u> Ui<'u> {
                             let mut note = self.entries.get(self.note_list_state.selected().unwrap()).unwrap().clone();
                             note.metadata.locally_deleted = !note.metadata.locally_deleted ;
 
-                            let db_connection = apnotes_lib::db::SqliteDBConnection::new();
-                            db_connection.update(&note).unwrap();
+                            self.app.lock().unwrap().update_note(&note).unwrap();
 
                             self.refresh();
 
@@ -427,20 +476,13 @@ impl<'u> Ui<'u> {
         }
 
         self.entries.iter()
-            .filter(|entry| {
-                if self.keyword.is_some() {
-                    entry.body[0].text.as_ref().unwrap().to_lowercase().contains(&self.keyword.as_ref().unwrap().to_lowercase())
-                } else {
-                    return true
-                }
-            })
             .map(|e| {
                 let folder =e.metadata.folder();
                 let folder = NOTES_REGEX.replace_all(&folder,"");
                 let folder = if folder.trim().len() > 0 { format!("{}.",folder) } else { "".to_string() };
                 if e.needs_merge() {
                     gen_item(folder, e.first_subject(), Style::default().fg(Color::LightBlue))
-                } else if e.content_changed_locally() {
+                } else if e.content_changed_locally() || e.metadata.moved_from.is_some() {
                     gen_item(folder, e.first_subject(), Style::default().fg(Color::LightYellow))
                 } else if e.metadata.locally_deleted {
                     gen_item(folder, e.first_subject(), Style::default().fg(Color::LightRed))
@@ -453,7 +495,13 @@ impl<'u> Ui<'u> {
     }
 
     fn refresh(&mut self) {
-        self.entries = self.refetch_notes(&self.app.lock().unwrap(), &self.keyword);
+        match self.refetch_notes(&self.app.lock().unwrap(), &self.keyword) {
+            Ok(entries) => self.entries = entries,
+            Err(e) => {
+                self.color = Color::Red;
+                self.status = e.to_string();
+            }
+        }
         self.items = self.generate_list_items( );
         self.list = self.gen_list();
     }
@@ -512,18 +560,23 @@ impl<'u> Ui<'u> {
         old_uuid
     }
 
-    fn refetch_notes(&self, app: &AppleNotes, filter_word: &Option<String>) -> Vec<LocalNote> {
-        app.get_notes().unwrap()
-            .into_iter()
-            .filter(|entry| {
-                if filter_word.is_some() {
-                    entry.body[0].text.as_ref().unwrap().to_lowercase().contains(&filter_word.as_ref().unwrap().to_lowercase())
-                } else {
-                    return true
-                }
-            })
-            .sorted_by_key(|note| note.metadata.timestamp())
-            .rev()
-            .collect()
+    /// Returns all notes ordered by date, if a filter word is present
+    /// the full-text index gets queried and the notes are ordered by relevance
+    fn refetch_notes(&self, app: &AppleNotes, filter_word: &Option<String>) -> Result<Vec<LocalNote>, Box<dyn ErrorCode>> {
+        match filter_word {
+            Some(word) if word.trim().is_empty() == false => {
+                Ok(app.search(word)?
+                    .into_iter()
+                    .map(|result| result.note)
+                    .collect())
+            }
+            _ => {
+                Ok(app.get_notes()?
+                    .into_iter()
+                    .sorted_by_key(|note| note.metadata.timestamp())
+                    .rev()
+                    .collect())
+            }
+        }
     }
-}
\ No newline at end of file
+}
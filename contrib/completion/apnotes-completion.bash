@@ -46,30 +46,63 @@ _apnotes() {
             delete)
                 cmd+="__delete"
                 ;;
+            diff)
+                cmd+="__diff"
+                ;;
             edit)
                 cmd+="__edit"
                 ;;
+            export)
+                cmd+="__export"
+                ;;
+            config)
+                cmd+="__config"
+                ;;
+            folder)
+                cmd+="__folder"
+                ;;
             help)
                 cmd+="__help"
                 ;;
+            history)
+                cmd+="__history"
+                ;;
+            import)
+                cmd+="__import"
+                ;;
             list)
                 cmd+="__list"
                 ;;
             merge)
                 cmd+="__merge"
                 ;;
+            move)
+                cmd+="__move"
+                ;;
             new)
                 cmd+="__new"
                 ;;
             print)
                 cmd+="__print"
                 ;;
+            restore)
+                cmd+="__restore"
+                ;;
+            search)
+                cmd+="__search"
+                ;;
             sync)
                 cmd+="__sync"
                 ;;
+            todo)
+                cmd+="__todo"
+                ;;
             undelete)
                 cmd+="__undelete"
                 ;;
+            watch)
+                cmd+="__watch"
+                ;;
             *)
                 ;;
         esac
@@ -77,13 +110,22 @@ _apnotes() {
 
     case "${cmd}" in
         apnotes)
-            opts=" -h -V  --help --version  list edit sync delete undelete merge print backup new help"
+            opts=" -h -V  --help --version --profile --format  list edit sync delete undelete merge move config folder export import print history diff restore search todo watch backup new help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
             fi
             case "${prev}" in
-                
+                --profile)
+                    local config_dir="${XDG_CONFIG_HOME:-$HOME/.config}/apnotes"
+                    local profiles=$(sed -n 's/^\s*\[profiles\.\(.*\)\]\s*$/\1/p' "${config_dir}/config.toml" 2>/dev/null || sed -n 's/^\s*\[\(.*\)\]\s*$/\1/p' "${config_dir}/config" 2>/dev/null)
+                    COMPREPLY=( $(compgen -W "${profiles}" -- "${cur}") )
+                    return 0
+                    ;;
+                --format)
+                    COMPREPLY=( $(compgen -W "text json" -- "${cur}") )
+                    return 0
+                    ;;
                 *)
                     COMPREPLY=()
                     ;;
@@ -93,7 +135,7 @@ _apnotes() {
             ;;
         
         apnotes__backup)
-            opts=" -h -V  --help --version  "
+            opts=" -l -h -V  --list --help --version  "
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -111,10 +153,40 @@ _apnotes() {
             _apnotes_get_notes delete
             return 0  
             ;;
+        apnotes__diff)
+            _apnotes_get_notes diff
+            return 0
+            ;;
         apnotes__edit)
             _apnotes_get_notes edit
             return 0
             ;;
+        apnotes__export)
+            COMPREPLY=($(compgen -d "${cur}"))
+            return 0
+            ;;
+        apnotes__import)
+            COMPREPLY=($(compgen -d "${cur}"))
+            return 0
+            ;;
+        apnotes__config)
+            opts=" -h --help check migrate "
+            if [[ ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            COMPREPLY=()
+            return 0
+            ;;
+        apnotes__folder)
+            opts=" -h --help list create rename delete "
+            if [[ ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            COMPREPLY=()
+            return 0
+            ;;
         apnotes__help)
             opts=" -h -V  --help --version  "
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
@@ -130,8 +202,12 @@ _apnotes() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        apnotes__history)
+            _apnotes_get_notes history
+            return 0
+            ;;
         apnotes__list)
-            opts="  --names --deleted  "
+            opts="  --names --deleted --all-profiles  "
             notes=$(apnotes list --names 2>&1 )
             if [[ ${cur} == -*  ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
@@ -143,6 +219,10 @@ _apnotes() {
             _apnotes_get_notes merge
             return 0
             ;;
+        apnotes__move)
+            _apnotes_get_notes move
+            return 0
+            ;;
         apnotes__new)
             opts=" -f -h -V  --folder --help --version  <title> "
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
@@ -170,8 +250,21 @@ _apnotes() {
             _apnotes_get_notes print
             return 0
             ;;
+        apnotes__restore)
+            _apnotes_get_notes restore
+            return 0
+            ;;
+        apnotes__search)
+            opts=" -h -V  --help --version  <query> "
+            if [[ ${cur} == -* ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            COMPREPLY=()
+            return 0
+            ;;
         apnotes__sync)
-            opts=" -h -V  --help --version  "
+            opts=" -n -h -V  --json --help --version  "
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -185,6 +278,24 @@ _apnotes() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        apnotes__watch)
+            opts=" -h -V  --help --version  "
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            COMPREPLY=()
+            return 0
+            ;;
+        apnotes__todo)
+            opts=" -h -V  --help --version  "
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            COMPREPLY=()
+            return 0
+            ;;
         apnotes__undelete)
             _apnotes_get_notes undelete
             return 0
@@ -1,5 +1,4 @@
 extern crate imap;
-extern crate native_tls;
 extern crate mailparse;
 extern crate log;
 extern crate regex;
@@ -12,14 +11,19 @@ extern crate serde;
 extern crate mockall;
 
 use self::log::{info, warn, debug};
-use self::imap::Session;
-use std::net::TcpStream;
-use self::native_tls::TlsStream;
+use self::imap::{Client, Session};
+use self::imap::extensions::idle::SetReadTimeout;
 use self::imap::types::{Fetch};
 use crate::model::{NotesMetadata};
 use crate::converter::convert_to_html;
 use crate::imap::types::Mailbox;
 use crate::error::Result;
+use crate::config::{PasswordType, Security};
+use crate::oauth2::XOAuth2;
+use crate::imap_stream::{ImapStream, RecordingStream, Recorder};
+use crate::attachment;
+use crate::attachment::AttachmentData;
+use crate::notes::note_content::NoteContent;
 
 #[cfg(test)]
 use crate::mockall::{automock, predicate::*};
@@ -28,39 +32,73 @@ use crate::notes::localnote::LocalNote;
 use crate::notes::remote_note_metadata::RemoteNoteMetaData;
 use crate::notes::traits::identifyable_note::IdentifiableNote;
 use crate::profile::Profile;
-
-pub trait ImapSession<S> {
-
-}
-
-pub struct TlsImapSession {
-    session: Session<TlsStream<TcpStream>>
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+use regex::Regex;
+
+lazy_static! {
+    static ref STATUS_ITEM_REGEX: Regex = Regex::new(r"\b(MESSAGES|UIDNEXT|UIDVALIDITY|HIGHESTMODSEQ) (\d+)").unwrap();
+    static ref FETCH_UID_REGEX: Regex = Regex::new(r"(?m)^\* \d+ FETCH \(.*?\bUID (\d+)").unwrap();
+    static ref APPENDUID_REGEX: Regex = Regex::new(r"\[APPENDUID \d+ (\d+)\]").unwrap();
 }
 
-impl TlsImapSession {
+/// Logs in with the credentials of the profile, the greeting of the server has to be read already
+async fn login<S: Read + Write>(client: Client<S>, profile: &Profile) -> Result<Session<S>> {
+    info!("Imap login");
 
-    async fn login(profile: &Profile) -> Result<Session<TlsStream<TcpStream>>> {
-        let domain = profile.imap_server.as_str();
-        info!("Imap login");
-        info!("Connecting to {}", domain);
+    let password = &profile.get_password().await?;
 
-        let tls = native_tls::TlsConnector::builder().build().unwrap();
-
-        // we pass in the domain twice to check that the server's TLS
-        // certificate is valid for the domain we're connecting to.
+    match profile.password_type {
+        PasswordType::OAuth2 => {
+            let authenticator = XOAuth2 { user: &profile.username, access_token: password };
+            client.authenticate("XOAUTH2", &authenticator).map_err(|e| e.0.into())
+        },
+        _ => client.login(&profile.username, password).map_err(|e| e.0.into())
+    }
+}
 
-        let password = &profile.get_password().await?;
+/// Current state of an imap folder, as reported by the STATUS command
+#[derive(Clone,Debug,PartialEq)]
+pub struct FolderStatus {
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub messages: u32,
+    /// Only present if the server supports CONDSTORE
+    pub highest_modseq: Option<u64>
+}
 
-        imap::connect((domain, 993), domain, &tls)
-            .map_err(|e| e.into())
-            .and_then(|client| client.login(&profile.username, password).map_err(|e| e.0.into()))
-    }
+/// Parses the untagged STATUS response of a folder
+fn parse_status_response(response: &str) -> Option<FolderStatus> {
+    let value = |item: &str| {
+        STATUS_ITEM_REGEX.captures_iter(response)
+            .find(|captures| &captures[1] == item)
+            .and_then(|captures| captures[2].parse::<u64>().ok())
+    };
+
+    Some(FolderStatus {
+        uid_validity: value("UIDVALIDITY")? as u32,
+        uid_next: value("UIDNEXT")? as u32,
+        messages: value("MESSAGES")? as u32,
+        highest_modseq: value("HIGHESTMODSEQ")
+    })
 }
 
-impl ImapSession<Session<TlsStream<TcpStream>>> for TlsImapSession {
+/// Parses the uids out of the untagged FETCH responses of an "UID FETCH" command
+fn parse_fetched_uids(response: &str) -> HashSet<u32> {
+    FETCH_UID_REGEX.captures_iter(response)
+        .filter_map(|captures| captures[1].parse::<u32>().ok())
+        .collect()
+}
 
+/// Parses the uid of the appended message out of the tagged APPEND response of
+/// an UIDPLUS server, e.g. "a5 OK [APPENDUID 38505 3955] APPEND completed"
+fn parse_appenduid_response(response: &str) -> Option<u32> {
+    APPENDUID_REGEX.captures(response)
+        .and_then(|captures| captures[1].parse::<u32>().ok())
 }
 
+/// Imap operations needed for notes, T is the stream the imap session runs on
 #[cfg_attr(test, automock)]
 pub trait MailService<T> {
     /// Iterates through all Note-Imap folders and fetches the mail header content plus
@@ -69,56 +107,135 @@ pub trait MailService<T> {
     /// The generated dataset can be used to check for duplicated notes that needs
     /// to be merged
     fn fetch_headers(&mut self) -> Result<RemoteNoteHeaderCollection>;
+    /// Returns the names of all imap folders that contain notes
+    fn list_note_folders(&mut self) -> Result<Vec<String>>;
+    /// Returns UIDVALIDITY, UIDNEXT, the message count and, if the
+    /// server supports CONDSTORE, the HIGHESTMODSEQ of a folder
+    fn folder_status(&mut self, folder: &str) -> Result<FolderStatus>;
+    /// Fetches the headers of all messages inside folder with an uid >= min_uid
+    fn fetch_headers_since(&mut self, folder: &str, min_uid: u32) -> Result<Vec<RemoteNoteMetaData>>;
+    /// Returns the uids of all messages inside folder
+    fn fetch_uids(&mut self, folder: &str) -> Result<HashSet<u32>>;
+    /// Returns the uids of all messages inside folder that changed since the passed
+    /// modseq, the server has to support CONDSTORE
+    fn fetch_changed_uids(&mut self, folder: &str, modseq: u64) -> Result<HashSet<u32>>;
+    /// Fetches the headers of the messages with the passed uids inside folder
+    fn fetch_headers_by_uid(&mut self, folder: &str, uids: &HashSet<u32>) -> Result<Vec<RemoteNoteMetaData>>;
     /// Creates a new Subfolder for storing notes
     fn create_mailbox(&mut self, note: &NotesMetadata) -> Result<()>;
+    /// Creates the passed imap folder, fails if it already exists
+    fn create_folder(&mut self, folder: &str) -> Result<()>;
+    /// Renames an imap folder, subfolders are renamed by the server as well
+    fn rename_folder(&mut self, old: &str, new: &str) -> Result<()>;
+    /// Deletes an imap folder
+    fn delete_folder(&mut self, folder: &str) -> Result<()>;
+    /// Returns the names of all backup folders, including their subfolders
+    fn list_backup_folders(&mut self) -> Result<Vec<String>>;
+    /// Copies all messages of folder into target, returns the count of copied messages
+    fn copy_folder(&mut self, folder: &str, target: &str) -> Result<u32>;
     /// Fetches mail headers for passed uuid
     fn fetch_header(&mut self, subfolder: &str, uid: i64) -> Result<RemoteNoteMetaData>;
-    /// Fetches the actual content from a note
-    fn fetch_note_content(&mut self, subfolder: &str, uid: i64) -> Result<String>;
-    /// Exposes the active imap connection
-    fn get_session(&self) -> T;
+    /// Fetches the actual content from a note, including its attachments
+    fn fetch_note_content(&mut self, subfolder: &str, uid: i64) -> Result<NoteContent>;
     /// Updates a local message, either if it got updated or if it is a new localnote
     /// This App should only support "merged" notes, notes that only have one body.
     ///
     /// If the passed localnote has >1 bodies it will reject it.
-    fn update_message(&mut self, localnote: &LocalNote) -> Result<u32>;
+    /// Attachments are embedded into the uploaded message
+    fn update_message(&mut self, localnote: &LocalNote, attachments: &Vec<AttachmentData>) -> Result<u32>;
     // Deletes the passed message
     fn delete_message(&mut self, localnote: &LocalNote) -> Result<()>;
+    /// Moves the message of a locally moved note from its remote folder into the
+    /// folder of the local note, returns the uid the message got in the new folder
+    fn move_message(&mut self, localnote: &LocalNote) -> Result<u32>;
+    /// Returns the uid of the message with the passed Message-ID inside folder,
+    /// None if the message or the folder does not exist
+    fn find_message(&mut self, folder: &str, message_id: &str) -> Result<Option<u32>>;
+    /// Deletes all messages of the note with the passed uuid inside all note folders,
+    /// except the message with uid_to_keep inside folder
+    fn delete_duplicates(&mut self, uuid: &str, folder: &str, uid_to_keep: u32) -> Result<()>;
     /// Selects a specific subfolder
     fn select(&mut self, folder: &str) -> Result<Mailbox>;
     fn logout(&mut self) -> Result<()>;
 }
 
-pub struct MailServiceImpl<'a> {
-    session: TlsImapSession,
-    profile: &'a Profile
+/// Imap client for notes, generic over the stream the imap session runs on
+pub struct MailServiceImpl<'a, S: Read + Write = ImapStream> {
+    session: Session<RecordingStream<S>>,
+    /// Captures the tagged APPEND responses, see RecordingStream
+    recorder: Recorder,
+    profile: &'a Profile,
+    /// Cached result of the CONDSTORE capability check
+    condstore: Option<bool>,
+    /// Cached result of the UIDPLUS capability check
+    uidplus: Option<bool>
+}
+
+/// Logs into the imap server, runs f with the open session and logs out afterwards
+pub async fn with_session<R, F>(profile: &Profile, f: F) -> Result<R>
+    where F: FnOnce(&mut MailServiceImpl) -> Result<R>
+{
+    let mut imap_service = MailServiceImpl::new_with_login(profile).await?;
+    let result = f(&mut imap_service);
+    let logout = imap_service.logout();
+    let result = result?;
+    logout.map(|_| result)
 }
 
-impl <'a>MailServiceImpl<'a> {
+impl <'a>MailServiceImpl<'a, ImapStream> {
+    /// Connects to the imap server of the profile and logs in
     pub async fn new_with_login(profile: &Profile) -> Result<MailServiceImpl> {
-        match TlsImapSession::login(profile).await {
-            Ok(session) => {
-                Ok(
-                    MailServiceImpl {
-                        session: TlsImapSession {
-                            session: session
-                        },
-                        profile
-                    }
-                )
-            }
-            Err(e) => {
-                Err(e)
-            }
+        let (stream, recorder) = RecordingStream::new(ImapStream::connect(profile)?);
+        let mut client = Client::new(stream);
+        // With STARTTLS the greeting got read before the connection was upgraded
+        if profile.security != Security::StartTls {
+            client.read_greeting()?;
         }
 
+        Ok(MailServiceImpl::new(login(client, profile).await?, recorder, profile))
+    }
+}
+
+/// IDLE needs a read timeout, so that it can be restarted
+impl <'a, S: Read + Write + SetReadTimeout>MailServiceImpl<'a, S> {
+    /// Selects folder and waits with IMAP IDLE until the server reports
+    /// a change inside this folder or timeout elapsed
+    pub fn idle(&mut self, folder: &str, timeout: Duration) -> Result<()> {
+        self.session.select(folder)?;
+        debug!("Waiting for changes in {}", folder);
+        match self.session.idle()?.wait_with_timeout(timeout) {
+            Ok(_) => Ok(()),
+            Err(imap::error::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(()),
+            Err(e) => Err(e.into())
+        }
+    }
+}
+
+impl <'a, S: Read + Write>MailServiceImpl<'a, S> {
+    /// Logs in over an already established connection, e.g. a unix socket, an ssh tunnel
+    /// or an in-memory pipe. The stream has to be at the point where the server sends its greeting
+    pub async fn new_with_stream(stream: S, profile: &'a Profile) -> Result<MailServiceImpl<'a, S>> {
+        let (stream, recorder) = RecordingStream::new(stream);
+        let mut client = Client::new(stream);
+        client.read_greeting()?;
+        Ok(MailServiceImpl::new(login(client, profile).await?, recorder, profile))
+    }
+
+    fn new(session: Session<RecordingStream<S>>, recorder: Recorder, profile: &'a Profile) -> MailServiceImpl<'a, S> {
+        MailServiceImpl {
+            session,
+            recorder,
+            profile,
+            condstore: None,
+            uidplus: None
+        }
     }
 
     pub fn fetch_headers_in_folder(&mut self, folder_name: String) -> Vec<RemoteNoteMetaData> {
-        if let Some(result) = self.session.session.select(&folder_name).err() {
+        if let Some(result) = self.session.select(&folder_name).err() {
             warn!("Could not select folder {} [{}]", &folder_name, result)
         }
-        let messages_result = self.session.session.fetch("1:*", "(RFC822.HEADER UID)");
+        let messages_result = self.session.fetch("1:*", "(RFC822.HEADER UID)");
         match messages_result {
             Ok(messages) => {
                 debug!("Message Loading for {} successful", &folder_name.to_string());
@@ -150,60 +267,165 @@ impl <'a>MailServiceImpl<'a> {
         }
     }
 
-    fn get_body(&mut self,fetch: &Fetch) -> Option<String> {
-        match mailparse::parse_mail(fetch.body()?) {
-            Ok(body) => body.get_body().ok(),
-            _ => None
+    fn get_content(&mut self,fetch: &Fetch) -> Option<NoteContent> {
+        attachment::parse_message(fetch.body()?)
+    }
+
+    pub fn supports_idle(&mut self) -> Result<bool> {
+        Ok(self.session.capabilities()?.has_str("IDLE"))
+    }
+
+    fn supports_condstore(&mut self) -> Result<bool> {
+        if let Some(condstore) = self.condstore {
+            return Ok(condstore);
         }
+        let condstore = self.session.capabilities()?.has_str("CONDSTORE");
+        self.condstore = Some(condstore);
+        Ok(condstore)
     }
 
-    pub fn list_note_folders(&mut self) -> Result<Vec<String>> {
-        let folders_result = self.session.session.list(None, Some("Notes*"));
-        match folders_result {
-            Ok(result) => {
-                let names: Vec<String> = result.iter().map(|name| name.name().to_string()).collect();
-                Ok(names)
-            }
-            Err(e) => Err(e.into())
+    fn supports_uidplus(&mut self) -> Result<bool> {
+        if let Some(uidplus) = self.uidplus {
+            return Ok(uidplus);
+        }
+        let uidplus = self.session.capabilities()?.has_str("UIDPLUS");
+        self.uidplus = Some(uidplus);
+        Ok(uidplus)
+    }
+
+    /// Appends message to folder, returns the uid of the new message
+    /// if the server supports UIDPLUS
+    fn append(&mut self, folder: &str, message: &[u8]) -> Result<Option<u32>> {
+        if self.supports_uidplus()? == false {
+            self.session.append(folder, message)?;
+            return Ok(None);
         }
+
+        self.recorder.start();
+        let result = self.session.append(folder, message);
+        let response = self.recorder.stop();
+        result?;
+
+        let uid = parse_appenduid_response(&String::from_utf8_lossy(&response));
+        if uid.is_none() {
+            warn!("Server supports UIDPLUS, but did not send APPENDUID");
+        }
+        Ok(uid)
+    }
+
+    /// Returns the highest uid of the messages with the passed Message-ID inside the selected folder
+    fn search_message_id(&mut self, message_id: &str) -> Result<Option<u32>> {
+        Ok(self.session.uid_search(format!("HEADER Message-ID {}", message_id))?
+            .into_iter()
+            .max())
     }
 
-    /// Deletes all notes remotely that have the uuid provided by local_note, expect
-    /// the note with uid_to_keep
+    /// Deletes all notes remotely that have the passed uuid, expect the note with
+    /// uid_to_keep inside target_folder. All note folders get searched, because
+    /// duplicates of a note may reside in different folders
     fn delete_old_mergeable_notes(&mut self,
-                                  local_note: &LocalNote,
+                                  uuid: &str,
+                                  target_folder: &str,
                                   uid_to_keep: u32) -> Result<()>
     {
-        self.session.session.
-            select(&local_note.metadata.folder()).map_err(|e| e.into())
-            .and_then(|_| self.session.session.uid_search(
-                format!("HEADER X-Universally-Unique-Identifier {}", local_note.metadata.uuid)))
-            .map_err(|e| e.into())
-            .and_then(|uids| {
-                let uids: Vec<String> = uids.into_iter()
-                    .filter(|uid| uid != &uid_to_keep )
-                    .map(|x| (x.to_string())).collect();
-                for uid in uids {
-                    info!("Will delete remote note with uid: {}", uid);
-                    self.flag_as_deleted(uid)?;
-                }
-                self.delete_flagged()?;
-                Ok(())
-            })
+        for folder in self.list_note_folders()? {
+            self.session.select(&folder)?;
+            let uids: Vec<String> = self.session.uid_search(
+                format!("HEADER X-Universally-Unique-Identifier {}", uuid))?
+                .into_iter()
+                .filter(|uid| folder != target_folder || uid != &uid_to_keep)
+                .map(|x| (x.to_string())).collect();
+
+            if uids.is_empty() {
+                continue;
+            }
+
+            for uid in uids {
+                info!("Will delete remote note with uid: {} in folder {}", uid, folder);
+                self.flag_as_deleted(uid)?;
+            }
+            self.delete_flagged()?;
+        }
+        Ok(())
     }
 
     fn delete_flagged(&mut self) -> Result<Vec<u32>> {
-        self.session.session.expunge().map_err(|e| e.into())
+        self.session.expunge().map_err(|e| e.into())
     }
 
     fn flag_as_deleted(&mut self, uid: String) -> Result<()> {
         // If note was new everything is ready
-        self.session.session.uid_store(uid, "+FLAGS.SILENT (\\Seen \\Deleted)".to_string())?;
+        self.session.uid_store(uid, "+FLAGS.SILENT (\\Seen \\Deleted)".to_string())?;
         Ok(())
     }
 }
 
-impl <'a>MailService<Session<TlsStream<TcpStream>>> for MailServiceImpl<'a> {
+impl <'a, S: Read + Write>MailService<S> for MailServiceImpl<'a, S> {
+
+    fn list_note_folders(&mut self) -> Result<Vec<String>> {
+        let folders_result = self.session.list(None, Some("Notes*"));
+        match folders_result {
+            Ok(result) => {
+                let names: Vec<String> = result.iter().map(|name| name.name().to_string()).collect();
+                Ok(names)
+            }
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn folder_status(&mut self, folder: &str) -> Result<FolderStatus> {
+        let items = if self.supports_condstore()? {
+            "(MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)"
+        } else {
+            "(MESSAGES UIDNEXT UIDVALIDITY)"
+        };
+
+        let quoted_folder = format!("\"{}\"", folder.replace('\\', "\\\\").replace('"', "\\\""));
+        let response = self.session
+            .run_command_and_read_response(&format!("STATUS {} {}", quoted_folder, items))?;
+
+        parse_status_response(&String::from_utf8_lossy(&response))
+            .ok_or(imap::error::Error::Bad(format!("Invalid status response for {}", folder)).into())
+    }
+
+    fn fetch_headers_since(&mut self, folder: &str, min_uid: u32) -> Result<Vec<RemoteNoteMetaData>> {
+        let min_uid = min_uid.max(1);
+        self.session.select(folder)?;
+        let messages = self.session.uid_fetch(format!("{}:*", min_uid), "(RFC822.HEADER UID)")?;
+        debug!("Fetched {} headers in {} starting with uid {}", messages.len(), folder, min_uid);
+        // n:* always returns the message with the highest uid, even if it is lower than n
+        Ok(messages.iter()
+            .filter(|fetch| fetch.uid.map(|uid| uid >= min_uid).unwrap_or(false))
+            .map(|fetch| self.get_headers(fetch, folder))
+            .collect())
+    }
+
+    fn fetch_uids(&mut self, folder: &str) -> Result<HashSet<u32>> {
+        self.session.select(folder)?;
+        self.session.uid_search("ALL").map_err(|e| e.into())
+    }
+
+    fn fetch_changed_uids(&mut self, folder: &str, modseq: u64) -> Result<HashSet<u32>> {
+        self.session.select(folder)?;
+        // The MODSEQ items of the response are read by hand, like the STATUS response
+        let response = self.session
+            .run_command_and_read_response(&format!("UID FETCH 1:* (UID) (CHANGEDSINCE {})", modseq))?;
+        let uids = parse_fetched_uids(&String::from_utf8_lossy(&response));
+        debug!("{} messages in {} changed since modseq {}", uids.len(), folder, modseq);
+        Ok(uids)
+    }
+
+    fn fetch_headers_by_uid(&mut self, folder: &str, uids: &HashSet<u32>) -> Result<Vec<RemoteNoteMetaData>> {
+        if uids.is_empty() {
+            return Ok(vec![]);
+        }
+        self.session.select(folder)?;
+        let uid_set = uids.iter().map(|uid| uid.to_string()).collect::<Vec<String>>().join(",");
+        let messages = self.session.uid_fetch(uid_set, "(RFC822.HEADER UID)")?;
+        Ok(messages.iter()
+            .map(|fetch| self.get_headers(fetch, folder))
+            .collect())
+    }
 
     fn fetch_headers(&mut self) -> Result<Vec<RemoteNoteMetaData>> {
         info!("Fetching Headers of Remote Notes...");
@@ -217,16 +439,46 @@ impl <'a>MailService<Session<TlsStream<TcpStream>>> for MailServiceImpl<'a> {
     }
 
     fn create_mailbox(&mut self, note: &NotesMetadata) -> Result<()> {
-        self.session.session.create(&note.folder()).or(Ok(()))
+        self.session.create(&note.folder()).or(Ok(()))
+    }
+
+    fn create_folder(&mut self, folder: &str) -> Result<()> {
+        info!("Creating folder {}", folder);
+        self.session.create(folder).map_err(|e| e.into())
+    }
+
+    fn rename_folder(&mut self, old: &str, new: &str) -> Result<()> {
+        info!("Renaming folder {} to {}", old, new);
+        self.session.rename(old, new).map_err(|e| e.into())
+    }
+
+    fn delete_folder(&mut self, folder: &str) -> Result<()> {
+        info!("Deleting folder {}", folder);
+        self.session.delete(folder).map_err(|e| e.into())
+    }
+
+    fn list_backup_folders(&mut self) -> Result<Vec<String>> {
+        let folders = self.session.list(None, Some("Backup_Notes*"))?;
+        Ok(folders.iter().map(|name| name.name().to_string()).collect())
+    }
+
+    fn copy_folder(&mut self, folder: &str, target: &str) -> Result<u32> {
+        let mailbox = self.session.select(folder)?;
+        // COPY with an empty sequence set is rejected by most servers
+        if mailbox.exists > 0 {
+            debug!("Copying {} messages from {} to {}", mailbox.exists, folder, target);
+            self.session.uid_copy("1:*", target)?;
+        }
+        Ok(mailbox.exists)
     }
 
     fn fetch_header(&mut self, subfolder: &str, uid: i64) -> Result<RemoteNoteMetaData> {
         info!("Fetching single header of not with UID {}", uid);
-        if let Some(result) = self.session.session.select(&subfolder).err() {
+        if let Some(result) = self.session.select(&subfolder).err() {
             warn!("Could not select folder {} [{}]", &subfolder, result)
         }
 
-        let messages_result = self.session.session.uid_fetch(uid.to_string(), "(RFC822 UID)");
+        let messages_result = self.session.uid_fetch(uid.to_string(), "(RFC822 UID)");
         match messages_result {
             Ok(message) => {
                 debug!("Message Loading for message with UID {} successful", uid);
@@ -240,17 +492,17 @@ impl <'a>MailService<Session<TlsStream<TcpStream>>> for MailServiceImpl<'a> {
         }
     }
 
-    fn fetch_note_content(&mut self, subfolder: &str, uid: i64) -> Result<String> {
-        if let Some(result) = self.session.session.select(&subfolder).err() {
+    fn fetch_note_content(&mut self, subfolder: &str, uid: i64) -> Result<NoteContent> {
+        if let Some(result) = self.session.select(&subfolder).err() {
             warn!("Could not select folder {} [{}]", &subfolder, result)
         }
 
-        let messages_result = self.session.session.uid_fetch(uid.to_string(), "(RFC822 UID)");
+        let messages_result = self.session.uid_fetch(uid.to_string(), "(RFC822 UID)");
         match messages_result {
             Ok(message) => {
                 debug!("Message Loading for message with UID {} successful", uid);
                 let first_message = message.first().expect("Expected message");
-                Ok(self.get_body(first_message).expect("Expected note body, found none"))
+                Ok(self.get_content(first_message).expect("Expected note body, found none"))
             },
             Err(error) => {
                 warn!("Could not load notes from {}! {}", &subfolder, error);
@@ -259,64 +511,195 @@ impl <'a>MailService<Session<TlsStream<TcpStream>>> for MailServiceImpl<'a> {
         }
     }
 
-    fn get_session(&self) -> Session<TlsStream<TcpStream>> {
-        unimplemented!()
-    }
-
-    fn update_message(&mut self, localnote: &LocalNote) -> Result<u32> {
+    fn update_message(&mut self, localnote: &LocalNote, attachments: &Vec<AttachmentData>) -> Result<u32> {
         //Todo check >1
 
-        let headers = localnote.to_header_vector(self.profile).iter().map( |(k,v)| {
-            format!("{}: {}",k,v)
-        })
-            .collect::<Vec<String>>()
-            .join("\n");
+        let headers = localnote.to_header_vector(self.profile);
 
         // Updated message must be merged
         //let _content = converter::convert_to_html(&localnote.body.first().unwrap());
 
         let body = localnote.body.first().unwrap();
-        let message = format!("{}\n\n{}",headers, convert_to_html(body));
+        let message = attachment::build_message(headers, &convert_to_html(body), attachments);
+        let folder = localnote.metadata.folder();
 
-        self.session.session
+        self
             // Write new message into the mailbox
-            .append(&localnote.metadata.folder(), message.as_bytes()).map_err(|e| e.into())
+            .append(&folder, message.as_bytes())
             // Select the appropriate mailbox, in which the updated message was saved
-            .and_then(|_| self.session.session.select(&localnote.metadata.folder()).map_err(|e| e.into()))
+            .and_then(|appended_uid| self.session.select(&folder).map(|_| appended_uid).map_err(|e| e.into()))
             // Set the old (overridden) message to "deleted", so that it can be expunged
-            .and_then(|_| {
-                if localnote.metadata.new == false && localnote.body[0].uid.is_some() {
-                    self.flag_as_deleted(localnote.body[0].uid.unwrap().to_string()).map_err(|e| e.into())
+            .and_then(|appended_uid| {
+                // The uid of a moved note belongs to another folder, the old message gets removed by delete_old_mergeable_notes
+                if localnote.metadata.new == false && localnote.body[0].uid.is_some() && localnote.metadata.moved_from.is_none() {
+                    self.flag_as_deleted(localnote.body[0].uid.unwrap().to_string()).map(|_| appended_uid)
                 } else {
-                    Ok(())
+                    Ok(appended_uid)
                 }
             })
             // Expunge them //TODO might need check if note is new, skip if note is new
-            .and_then(|_| self.delete_flagged().map_err(|e| e.into()))
-            // Search for the new message, to get the new UID of the updated message
-            .and_then(|_| self.session.session.uid_search(format!("HEADER Message-ID {}", localnote.body[0].message_id)).map_err(|e| e.into()))
-            // Get the first UID
-            .and_then(|id| id.into_iter().collect::<Vec<u32>>().first().cloned().ok_or(imap::error::Error::Bad("no uid found".to_string()).into()))
+            .and_then(|appended_uid| self.delete_flagged().map(|_| appended_uid))
+            // Without UIDPLUS the new message has to be searched, to get the new UID of the updated message
+            .and_then(|appended_uid| match appended_uid {
+                Some(uid) => Ok(Some(uid)),
+                None => self.search_message_id(&body.message_id)
+            })
+            .and_then(|uid| uid.ok_or(imap::error::Error::Bad("no uid found".to_string()).into()))
             // Save the new UID to the metadata file, also set seen flag so that mail clients dont get notified on updated message
-            .and_then(|new_uid| self.session.session.uid_store(format!("{}", &new_uid), "+FLAGS.SILENT (\\Seen)".to_string()).map(|_| new_uid).map_err(|e| e.into()))
+            .and_then(|new_uid| self.session.uid_store(format!("{}", &new_uid), "+FLAGS.SILENT (\\Seen)".to_string()).map(|_| new_uid).map_err(|e| e.into()))
             // Delete dangling remote non merged notes
-            .and_then(|new_uid| self.delete_old_mergeable_notes(&localnote, new_uid).map(|_| new_uid))
+            .and_then(|new_uid| self.delete_old_mergeable_notes(&localnote.metadata.uuid, &folder, new_uid).map(|_| new_uid))
     }
 
     fn delete_message(&mut self, localnote: &LocalNote) -> Result<()> {
-        self.session.session
-            .select(&localnote.metadata.folder()).map_err(|e| e.into())
+        self.session
+            .select(&localnote.remote_folder()).map_err(|e| e.into())
             .and_then(|_| self.flag_as_deleted(localnote.body[0].uid.expect("expected uid").to_string()).map_err(|e| e.into()))
             .and_then(|_| self.delete_flagged().map(|_| ()).map_err(|e| e.into()))
     }
 
+    fn move_message(&mut self, localnote: &LocalNote) -> Result<u32> {
+        let uid = localnote.body[0].uid.expect("expected uid").to_string();
+        let target_folder = localnote.folder();
+
+        self.session.select(&localnote.remote_folder())?;
+
+        if self.session.capabilities()?.has_str("MOVE") {
+            self.session.uid_mv(&uid, &target_folder)?;
+        } else {
+            debug!("Server does not support MOVE, falling back to COPY and EXPUNGE");
+            self.session.uid_copy(&uid, &target_folder)?;
+            self.flag_as_deleted(uid)?;
+            self.delete_flagged()?;
+        }
+
+        // The uuid stays the same, the message gets a new uid inside the target folder
+        self.session.select(&target_folder)?;
+        self.search_message_id(&localnote.body[0].message_id)?
+            .ok_or(imap::error::Error::Bad("no uid found".to_string()).into())
+    }
+
+    fn find_message(&mut self, folder: &str, message_id: &str) -> Result<Option<u32>> {
+        if self.list_note_folders()?.iter().any(|name| name == folder) == false {
+            return Ok(None);
+        }
+        self.session.select(folder)?;
+        self.search_message_id(message_id)
+    }
+
+    fn delete_duplicates(&mut self, uuid: &str, folder: &str, uid_to_keep: u32) -> Result<()> {
+        self.delete_old_mergeable_notes(uuid, folder, uid_to_keep)
+    }
+
     fn select(&mut self, folder: &str) -> Result<Mailbox> {
         //todo wrap mailbox type?
-        self.session.session.select(folder).map_err(|e| e.into())
+        self.session.select(folder).map_err(|e| e.into())
     }
 
     fn logout(&mut self) -> Result<()> {
         info!("Imap logout");
-        self.session.session.logout().map_err(|e| e.into())
+        self.session.logout().map_err(|e| e.into())
+    }
+}
+
+#[cfg(test)]
+mod apple_imap_tests {
+    use crate::apple_imap::{parse_status_response, parse_appenduid_response, parse_fetched_uids, FolderStatus, MailService, MailServiceImpl};
+    use crate::attachment;
+    use crate::builder::{NotesMetadataBuilder, BodyMetadataBuilder};
+    use crate::fake_imap::FakeImapServer;
+
+    #[test]
+    fn parse_status() {
+        let response = "* STATUS \"Notes\" (MESSAGES 12 UIDNEXT 140 UIDVALIDITY 1610000000 HIGHESTMODSEQ 9001)\r\n";
+
+        assert_eq!(parse_status_response(response), Some(FolderStatus {
+            uid_validity: 1610000000,
+            uid_next: 140,
+            messages: 12,
+            highest_modseq: Some(9001)
+        }));
+
+        let response = "* STATUS Notes (MESSAGES 0 UIDNEXT 1 UIDVALIDITY 3)\r\n";
+        assert_eq!(parse_status_response(response).unwrap().highest_modseq, None);
+
+        assert_eq!(parse_status_response("* STATUS Notes (MESSAGES 0)"), None);
+    }
+
+    #[test]
+    fn parse_changed_uids() {
+        let response = "* 1 FETCH (UID 4 MODSEQ (12))\r\n* 3 FETCH (MODSEQ (15) UID 9)\r\n";
+        assert_eq!(parse_fetched_uids(response), vec![4, 9].into_iter().collect());
+        assert!(parse_fetched_uids("").is_empty());
+    }
+
+    #[test]
+    fn parse_appenduid() {
+        assert_eq!(parse_appenduid_response("+ Ready for literal data\r\na5 OK [APPENDUID 38505 3955] APPEND completed\r\n"), Some(3955));
+        assert_eq!(parse_appenduid_response("a5 OK APPEND completed\r\n"), None);
+    }
+
+    /// Without UIDPLUS the uid of the uploaded message gets searched
+    #[tokio::test]
+    async fn update_message_without_uidplus() {
+        let server = FakeImapServer::start();
+        server.disable_uidplus();
+        let profile = server.profile("imap_without_uidplus");
+        server.append("Notes", "Subject: Other message\r\n\r\nBody");
+
+        let note = note![
+            NotesMetadataBuilder::new().with_folder("".to_string()).is_new(true).build(),
+            BodyMetadataBuilder::new().with_text("Note\n\nUploaded").build()
+        ];
+
+        let mut imap_service = MailServiceImpl::new_with_login(&profile).await.unwrap();
+        let uid = imap_service.update_message(&note, &vec![]).unwrap();
+        imap_service.logout().unwrap();
+
+        let messages = server.messages("Notes");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].0, uid);
+        assert!(messages[1].1.contains(&note.body[0].message_id));
+    }
+
+    /// Copies of a note in other folders are removed when the note gets updated
+    #[tokio::test]
+    async fn update_message_removes_duplicates() {
+        let server = FakeImapServer::start();
+        server.create_folder("Notes.Work");
+        let profile = server.profile("imap_duplicates");
+
+        let note = note![
+            NotesMetadataBuilder::new().with_folder("Work".to_string()).build(),
+            BodyMetadataBuilder::new().with_text("Note\n\nWith a stale copy").build()
+        ];
+        let stale = attachment::build_message(note.to_header_vector(&profile), "<div>stale</div>", &vec![]);
+        server.append("Notes", &stale);
+        let uid = server.append("Notes.Work", &stale);
+
+        // Locally edited version of the note inside Notes.Work
+        let note = note![
+            note.metadata.clone(),
+            BodyMetadataBuilder::new().with_uid(Some(uid as i64)).with_text("Note\n\nEdited").build()
+        ];
+
+        let mut imap_service = MailServiceImpl::new_with_login(&profile).await.unwrap();
+        let new_uid = imap_service.update_message(&note, &vec![]).unwrap();
+        imap_service.logout().unwrap();
+
+        assert!(server.messages("Notes").is_empty());
+        let messages = server.messages("Notes.Work");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, new_uid);
+        assert!(messages[0].1.contains(&note.body[0].message_id));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn login_with_wrong_password() {
+        let server = FakeImapServer::start();
+        let mut profile = server.profile("imap_wrong_password");
+        profile.password = Some("wrong".to_string());
+
+        assert!(MailServiceImpl::new_with_login(&profile).await.is_err());
+    }
+}
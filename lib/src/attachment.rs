@@ -0,0 +1,334 @@
+use std::path::PathBuf;
+use regex::Regex;
+use uuid::Uuid;
+use mailparse::ParsedMail;
+use crate::db::DatabaseService;
+use crate::error::Result;
+use crate::model::Attachment;
+use crate::notes::localnote::LocalNote;
+use crate::notes::note_content::{NoteContent, RemoteAttachment};
+use crate::notes::note_headers::NoteHeaders;
+use crate::util::generate_uuid;
+
+/// Attachment metadata together with its content
+pub type AttachmentData = (Attachment, Vec<u8>);
+
+/// Placeholders that surround the hex encoded content id of an embedded attachment before
+/// the html gets converted to markdown, so that html2runes leaves them untouched
+const ATTACHMENT_PLACEHOLDER: &str = "APNOTESATTACHMENT";
+const END_PLACEHOLDER: &str = "APNOTESEND";
+
+/// Replaces every image or object that references an attachment ("cid:" url) with a placeholder
+pub fn attachments_to_placeholders(html: &str) -> String {
+    let embed_regex = Regex::new(r#"(?is)<img[^>]*\ssrc\s*=\s*["']cid:([^"']+)["'][^>]*>|<object[^>]*\sdata\s*=\s*["']cid:([^"']+)["'][^>]*>.*?</object>"#).unwrap();
+
+    embed_regex.replace_all(html, |embed: &regex::Captures| {
+        let content_id = embed.get(1).or(embed.get(2)).unwrap().as_str();
+        let hex: String = content_id.bytes().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}{}{}", ATTACHMENT_PLACEHOLDER, hex, END_PLACEHOLDER)
+    }).into_owned()
+}
+
+/// Turns the placeholders of attachments_to_placeholders into markdown images
+pub fn placeholders_to_markdown(markdown: &str) -> String {
+    let placeholder_regex = Regex::new(&format!("{}([0-9a-f]*){}", ATTACHMENT_PLACEHOLDER, END_PLACEHOLDER)).unwrap();
+
+    placeholder_regex.replace_all(markdown, |placeholder: &regex::Captures| {
+        let bytes: Vec<u8> = (0..placeholder[1].len()).step_by(2)
+            .filter_map(|i| u8::from_str_radix(&placeholder[1][i..i + 2], 16).ok())
+            .collect();
+        format!("![attachment](cid:{})", String::from_utf8_lossy(&bytes))
+    }).into_owned()
+}
+
+/// Parses a note message, multipart messages are searched for the html part,
+/// every other part that is not text becomes an attachment
+///
+/// Attachments that are not referenced by the html get a reference appended,
+/// otherwise they would not be part of the markdown and got lost with the next upload
+pub fn parse_message(raw: &[u8]) -> Option<NoteContent> {
+    let mail = mailparse::parse_mail(raw).ok()?;
+
+    if mail.subparts.is_empty() {
+        return mail.get_body().ok().map(|html| NoteContent { html, attachments: vec![] });
+    }
+
+    let uuid = header_value(&mail, "X-Universally-Unique-Identifier").unwrap_or_default();
+
+    let mut html: Option<String> = None;
+    let mut attachments: Vec<RemoteAttachment> = vec![];
+    collect_parts(&mail, &uuid, &mut html, &mut attachments);
+
+    html.map(|html| {
+        let referenced = referenced_content_ids(&html);
+        let missing: Vec<&RemoteAttachment> = attachments.iter()
+            .filter(|attachment| referenced.contains(&attachment.content_id) == false)
+            .collect();
+        NoteContent { html: append_references(&html, &missing), attachments: attachments.clone() }
+    })
+}
+
+/// Parts without Content-ID get an id derived from the note and their content,
+/// so that the same part gets the same id with every fetch
+fn stable_content_id(uuid: &str, data: &[u8]) -> String {
+    let name = [uuid.as_bytes(), data].concat();
+    format!("{}@apnotes", Uuid::new_v5(&Uuid::NAMESPACE_OID, &name))
+}
+
+/// Adds an attachment block for every passed attachment at the end of the html body
+fn append_references(html: &str, attachments: &Vec<&RemoteAttachment>) -> String {
+    if attachments.is_empty() {
+        return html.to_string();
+    }
+
+    let references: String = attachments.iter()
+        .map(|attachment| format!("<div><object type=\"application/x-apple-msg-attachment\" data=\"cid:{}\"></object></div>", attachment.content_id))
+        .collect();
+
+    let body_end = Regex::new("(?i)</body>").unwrap();
+    match body_end.find_iter(html).last().map(|end| end.start()) {
+        Some(index) => format!("{}{}{}", &html[..index], references, &html[index..]),
+        None => format!("{}{}", html, references)
+    }
+}
+
+fn collect_parts(part: &ParsedMail, uuid: &str, html: &mut Option<String>, attachments: &mut Vec<RemoteAttachment>) {
+    if part.subparts.is_empty() == false {
+        part.subparts.iter().for_each(|subpart| collect_parts(subpart, uuid, html, attachments));
+        return;
+    }
+
+    let mime_type = part.ctype.mimetype.to_lowercase();
+    let content_id = header_value(part, "Content-ID")
+        .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string());
+
+    if mime_type == "text/html" && html.is_none() && content_id.is_none() {
+        *html = part.get_body().ok();
+        return;
+    }
+
+    // Plain text alternatives are not needed, the html part contains the same text
+    if mime_type.starts_with("text/") && content_id.is_none() {
+        return;
+    }
+
+    let file_name_regex = Regex::new(r#"(?i)(?:file)?name\s*=\s*"?([^";]+)"?"#).unwrap();
+    let file_name = header_value(part, "Content-Disposition")
+        .and_then(|disposition| file_name_regex.captures(&disposition).map(|c| c[1].to_string()))
+        .or(part.ctype.params.get("name").cloned())
+        .unwrap_or("attachment".to_string());
+
+    match part.get_body_raw() {
+        Ok(data) => attachments.push(RemoteAttachment {
+            content_id: content_id.unwrap_or_else(|| stable_content_id(uuid, &data)),
+            file_name,
+            mime_type,
+            data
+        }),
+        Err(e) => warn!("Could not decode attachment {}: {}", file_name, e)
+    }
+}
+
+fn header_value(part: &ParsedMail, key: &str) -> Option<String> {
+    part.headers.iter()
+        .find(|header| header.get_key().map(|k| k.eq_ignore_ascii_case(key)).unwrap_or(false))
+        .and_then(|header| header.get_value().ok())
+}
+
+/// Writes the content of all attachments into the blob store and saves their metadata
+pub fn store_attachments(db_connection: &Box<dyn DatabaseService + Send>, uuid: &str, attachments: &Vec<RemoteAttachment>) -> Result<()> {
+    for attachment in attachments {
+        let blob = store_blob(db_connection.profile_name(), &attachment.data)?;
+        db_connection.insert_attachment(&Attachment {
+            content_id: attachment.content_id.clone(),
+            metadata_uuid: uuid.to_string(),
+            file_name: attachment.file_name.clone(),
+            mime_type: attachment.mime_type.clone(),
+            blob
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns all attachments of the note that are still referenced by its text
+pub fn load_referenced_attachments(db_connection: &Box<dyn DatabaseService + Send>, localnote: &LocalNote) -> Result<Vec<AttachmentData>> {
+    let text = localnote.body.first().and_then(|body| body.text.clone()).unwrap_or_default();
+    let referenced = referenced_content_ids(&text);
+
+    db_connection.fetch_attachments(&localnote.metadata.uuid)?
+        .into_iter()
+        .filter(|attachment| referenced.contains(&attachment.content_id))
+        .map(|attachment| read_blob(db_connection.profile_name(), &attachment.blob).map(|data| (attachment, data)))
+        .collect()
+}
+
+/// Returns the content ids of all "cid:" links inside the passed text
+pub fn referenced_content_ids(text: &str) -> Vec<String> {
+    let cid_regex = Regex::new(r#"cid:([^)\s"'>]+)"#).unwrap();
+    cid_regex.captures_iter(text).map(|c| c[1].to_string()).collect()
+}
+
+/// Blobs are named after their content, so that every file is only stored once per profile
+fn store_blob(profile_name: &str, data: &[u8]) -> Result<String> {
+    let name = Uuid::new_v5(&Uuid::NAMESPACE_OID, data).to_string();
+    let path = blob_path(profile_name, &name)?;
+    if path.exists() == false {
+        std::fs::write(&path, data)?;
+    }
+    Ok(name)
+}
+
+/// Removes a blob from the blob store of the profile, blobs are shared between the
+/// notes of a profile so this must only be called once no attachment references it anymore
+pub fn remove_blob(profile_name: &str, name: &str) -> Result<()> {
+    let path = blob_path(profile_name, name)?;
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+fn read_blob(profile_name: &str, name: &str) -> Result<Vec<u8>> {
+    std::fs::read(blob_path(profile_name, name)?).map_err(|e| e.into())
+}
+
+fn blob_path(profile_name: &str, name: &str) -> Result<PathBuf> {
+    Ok(crate::profile::get_blob_dir(profile_name)?.join(name))
+}
+
+/// Builds the message that gets uploaded. Without attachments it only consists of the
+/// html, otherwise a multipart/related message gets created that contains the html
+/// and every attachment as separate part
+pub fn build_message(headers: NoteHeaders, html: &str, attachments: &Vec<AttachmentData>) -> String {
+    let format_headers = |headers: &NoteHeaders| headers.iter()
+        .map(|(k, v)| format!("{}: {}", k, v))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    if attachments.is_empty() {
+        return format!("{}\n\n{}", format_headers(&headers), html);
+    }
+
+    let boundary = format!("Apple-Mail={}", generate_uuid());
+
+    let headers: NoteHeaders = headers.into_iter()
+        .filter(|(k, _)| k.eq_ignore_ascii_case("Content-Transfer-Encoding") == false)
+        .map(|(k, v)| {
+            if k.eq_ignore_ascii_case("Content-Type") {
+                (k, format!("multipart/related; type=\"text/html\"; boundary=\"{}\"", boundary))
+            } else {
+                (k, v)
+            }
+        })
+        .collect();
+
+    let mut message = format!("{}\n\n--{}\nContent-Type: text/html; charset=utf-8\nContent-Transfer-Encoding: quoted-printable\n\n{}\n",
+                              format_headers(&headers), boundary, html);
+
+    for (attachment, data) in attachments {
+        let encoded = base64::encode(data);
+        let lines = encoded.as_bytes()
+            .chunks(76)
+            .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        message.push_str(&format!("--{}\nContent-Type: {}; name=\"{}\"\nContent-Transfer-Encoding: base64\n\
+                                   Content-Disposition: inline; filename=\"{}\"\nContent-ID: <{}>\n\n{}\n",
+                                  boundary, attachment.mime_type, attachment.file_name,
+                                  attachment.file_name, attachment.content_id, lines));
+    }
+
+    message.push_str(&format!("--{}--\n", boundary));
+    message
+}
+
+#[cfg(test)]
+mod attachment_tests {
+    use crate::attachment::*;
+
+    fn attachment() -> AttachmentData {
+        (Attachment {
+            content_id: "1234@apnotes".to_string(),
+            metadata_uuid: "1".to_string(),
+            file_name: "photo.png".to_string(),
+            mime_type: "image/png".to_string(),
+            blob: "blob".to_string()
+        }, vec![0, 1, 2, 3, 255])
+    }
+
+    #[test]
+    fn round_trip() {
+        let headers: NoteHeaders = vec![
+            ("Subject".to_string(), "Photo".to_string()),
+            ("Content-Type".to_string(), "text/html; charset=utf-8".to_string()),
+            ("Content-Transfer-Encoding".to_string(), "quoted-printable".to_string())
+        ];
+        let html = "<html><body><div>Photo</div><img src=3D\"cid:1234@apnotes\"></body></html>";
+
+        let message = build_message(headers, html, &vec![attachment()]);
+        assert!(message.contains("Content-Type: multipart/related"));
+
+        let content = parse_message(message.replace('\n', "\r\n").as_bytes()).unwrap();
+        assert_eq!(content.html.trim_end(), "<html><body><div>Photo</div><img src=\"cid:1234@apnotes\"></body></html>");
+        assert_eq!(content.attachments, vec![RemoteAttachment {
+            content_id: "1234@apnotes".to_string(),
+            file_name: "photo.png".to_string(),
+            mime_type: "image/png".to_string(),
+            data: vec![0, 1, 2, 3, 255]
+        }]);
+    }
+
+    #[test]
+    fn attachment_placeholders() {
+        let html = "<div>Photo<img style=\"max-width: 100%\" src=\"cid:1234@apnotes\"></div>\
+            <object type=\"application/x-apple-msg-attachment\" data=\"cid:5\"></object>";
+
+        let placeholders = attachments_to_placeholders(html);
+        assert_eq!(placeholders, format!("<div>Photo{0}313233344061706e6f746573{1}</div>{0}35{1}",
+                                         ATTACHMENT_PLACEHOLDER, END_PLACEHOLDER));
+        assert_eq!(placeholders_to_markdown(&placeholders),
+                   "<div>Photo![attachment](cid:1234@apnotes)</div>![attachment](cid:5)");
+    }
+
+    fn message_without_content_id(uuid: &str) -> String {
+        format!("X-Universally-Unique-Identifier: {}\r\n\
+                 Content-Type: multipart/mixed; boundary=\"part\"\r\n\r\n\
+                 --part\r\nContent-Type: text/html; charset=utf-8\r\n\r\n\
+                 <html><body><div>Scan</div></body></html>\r\n\
+                 --part\r\nContent-Type: application/pdf; name=\"scan.pdf\"\r\n\
+                 Content-Transfer-Encoding: base64\r\n\
+                 Content-Disposition: attachment; filename=\"scan.pdf\"\r\n\r\n\
+                 AAECAw==\r\n--part--\r\n", uuid)
+    }
+
+    /// Parts without Content-ID get the same id with every fetch and a reference inside the html
+    #[test]
+    fn part_without_content_id() {
+        let content = parse_message(message_without_content_id("note-1").as_bytes()).unwrap();
+        assert_eq!(content.attachments.len(), 1);
+        let content_id = content.attachments[0].content_id.clone();
+        assert!(content_id.ends_with("@apnotes"));
+        assert_eq!(content.attachments[0].data, vec![0, 1, 2, 3]);
+
+        let refetched = parse_message(message_without_content_id("note-1").as_bytes()).unwrap();
+        assert_eq!(refetched.attachments[0].content_id, content_id);
+
+        // The same file inside another note must not share the attachment row
+        let other_note = parse_message(message_without_content_id("note-2").as_bytes()).unwrap();
+        assert_ne!(other_note.attachments[0].content_id, content_id);
+
+        assert!(content.html.ends_with(&format!("data=\"cid:{}\"></object></div></body></html>", content_id)));
+        assert_eq!(referenced_content_ids(&crate::converter::convert2md(&content.html)), vec![content_id]);
+    }
+
+    #[test]
+    fn plain_message() {
+        let content = parse_message(b"Subject: Note\r\nContent-Type: text/html\r\n\r\n<div>Note</div>").unwrap();
+        assert_eq!(content.html, "<div>Note</div>");
+        assert!(content.attachments.is_empty());
+
+        assert_eq!(referenced_content_ids("Text\n![photo](cid:1234@apnotes) and ![x](cid:5)"),
+                   vec!["1234@apnotes".to_string(), "5".to_string()]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::apple_imap::MailService;
+use crate::error::Result;
+
+/// Root folder of all backups, every backup is stored in a timestamped subfolder of it
+const BACKUP_ROOT: &str = "Backup_Notes";
+
+/// Maps a note folder to its counterpart inside the backup, e.g.
+/// "Notes.todo" -> "Backup_Notes.2022-11-17_10-00-00.todo"
+///
+/// Folders that only share the prefix, like "NotesX", keep their whole name
+fn backup_folder_name(note_folder: &str, timestamp: &str) -> String {
+    if note_folder == "Notes" {
+        return format!("{}.{}", BACKUP_ROOT, timestamp);
+    }
+    match note_folder.strip_prefix("Notes.") {
+        Some(subfolder) => format!("{}.{}.{}", BACKUP_ROOT, timestamp, subfolder),
+        None => format!("{}.{}.{}", BACKUP_ROOT, timestamp, note_folder)
+    }
+}
+
+/// Copies every note folder into a new backup folder named after timestamp
+///
+/// on_progress gets called after every copied folder with the folder name, the count of
+/// copied messages, the count of already processed folders and the total folder count.
+///
+/// Returns the name of the new backup folder
+pub fn backup<T, F>(imap_service: &mut dyn MailService<T>, timestamp: &str, mut on_progress: F) -> Result<String>
+    where F: FnMut(&str, u32, usize, usize)
+{
+    let mut folders = imap_service.list_note_folders()?;
+    // Parent folders have to be created before their subfolders
+    folders.sort();
+
+    for (index, folder) in folders.iter().enumerate() {
+        let target = backup_folder_name(folder, timestamp);
+        imap_service.create_folder(&target)?;
+        let copied = imap_service.copy_folder(folder, &target)?;
+        on_progress(folder, copied, index + 1, folders.len());
+    }
+
+    Ok(format!("{}.{}", BACKUP_ROOT, timestamp))
+}
+
+/// Returns the names of all existing backups, oldest first
+pub fn list_backups<T>(imap_service: &mut dyn MailService<T>) -> Result<Vec<String>> {
+    let mut backups: Vec<String> = imap_service.list_backup_folders()?
+        .into_iter()
+        .filter(|folder| {
+            folder.strip_prefix(BACKUP_ROOT)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .map(|timestamp| timestamp.is_empty() == false && timestamp.contains('.') == false)
+                .unwrap_or(false)
+        })
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod backup_tests {
+    use super::*;
+    use crate::apple_imap::MockMailService;
+
+    #[test]
+    fn backup_nested_folders() {
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders()
+            .returning(|| Ok(vec!["Notes.todo".to_string(), "Notes".to_string(), "Notes.todo.work".to_string()]));
+
+        let mut created = vec![];
+        imap_service.expect_create_folder()
+            .times(3)
+            .returning(|_| Ok(()));
+        imap_service.expect_copy_folder()
+            .times(3)
+            .returning(|folder, target| {
+                assert_eq!(backup_folder_name(folder, "2022-11-17_10-00-00"), target);
+                Ok(2)
+            });
+
+        let name = backup(&mut imap_service, "2022-11-17_10-00-00", |folder, copied, done, total| {
+            assert_eq!(copied, 2);
+            assert_eq!(total, 3);
+            created.push((folder.to_string(), done));
+        }).unwrap();
+
+        assert_eq!(name, "Backup_Notes.2022-11-17_10-00-00");
+        assert_eq!(created, vec![
+            ("Notes".to_string(), 1),
+            ("Notes.todo".to_string(), 2),
+            ("Notes.todo.work".to_string(), 3)
+        ]);
+        assert_eq!(backup_folder_name("Notes.todo.work", "ts"), "Backup_Notes.ts.todo.work");
+        assert_eq!(backup_folder_name("Notes", "ts"), "Backup_Notes.ts");
+        assert_eq!(backup_folder_name("NotesX", "ts"), "Backup_Notes.ts.NotesX");
+    }
+
+    #[test]
+    fn list_backup_roots() {
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_backup_folders()
+            .returning(|| Ok(vec![
+                "Backup_Notes".to_string(),
+                "Backup_Notes.2022-11-17_10-00-00.todo".to_string(),
+                "Backup_Notes.2022-11-17_10-00-00".to_string(),
+                "Backup_Notes.2022-10-01_08-30-00".to_string(),
+            ]));
+
+        assert_eq!(list_backups(&mut imap_service).unwrap(), vec![
+            "Backup_Notes.2022-10-01_08-30-00".to_string(),
+            "Backup_Notes.2022-11-17_10-00-00".to_string()
+        ]);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::model::{Body, NotesMetadata};
-use crate::util::generate_uuid;
+use crate::util::{generate_uuid, imap_folder_name};
 use chrono::Utc;
 use crate::notes::note_headers::NoteHeaders;
 use crate::notes::traits::header_parser::HeaderParser;
@@ -24,7 +24,9 @@ impl BodyMetadataBuilder {
                 message_id: format!("<{}@{}>", generate_uuid(), &profile.domain),
                 text: None,
                 uid: None,
-                metadata_uuid: "".to_string()
+                metadata_uuid: "".to_string(),
+                base_text: None,
+                html: None
             }
         }
     }
@@ -37,7 +39,9 @@ impl BodyMetadataBuilder {
                 message_id: format!("<{}@{}>", generate_uuid(), "test@test.de".clone()),
                 text: None,
                 uid: None,
-                metadata_uuid: "".to_string()
+                metadata_uuid: "".to_string(),
+                base_text: None,
+                html: None
             }
         }
     }
@@ -68,6 +72,16 @@ impl BodyMetadataBuilder {
         self
     }
 
+    pub fn with_base_text(mut self, base_text: Option<String>) -> Self {
+        self.body.base_text = base_text;
+        self
+    }
+
+    pub fn with_html(mut self, html: Option<String>) -> Self {
+        self.body.html = html;
+        self
+    }
+
     pub fn build(self) -> Body {
         self.body
     }
@@ -93,7 +107,8 @@ impl NotesMetadataBuilder {
                 edited: false,
                 date,
                 uuid: generate_uuid(),
-                mime_version: "1.0 (Mac OS X Notes 4.6 \\(879.10\\))".to_string()
+                mime_version: "1.0 (Mac OS X Notes 4.6 \\(879.10\\))".to_string(),
+                moved_from: None
             }
         }
 
@@ -123,11 +138,13 @@ impl NotesMetadataBuilder {
     }
 
     pub fn with_folder(mut self, folder: String) -> Self {
-        if folder.len() > 0 {
-            self.notes_metadata.subfolder = format!("Notes.{}",folder.clone());
-        } else {
-            self.notes_metadata.subfolder = format!("Notes{}",folder.clone());
-        }
+        self.notes_metadata.subfolder = imap_folder_name(&folder);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn moved_from(mut self, folder: &str) -> Self {
+        self.notes_metadata.moved_from = Some(folder.to_string());
         self
     }
 
@@ -0,0 +1,187 @@
+use regex::Regex;
+
+/// Placeholders that replace the checkboxes of checklist items before the html gets converted
+/// to markdown. They only consist of letters, so that html2runes leaves them untouched
+const CHECKED_PLACEHOLDER: &str = "APNOTESCHECKLISTDONE";
+const UNCHECKED_PLACEHOLDER: &str = "APNOTESCHECKLISTOPEN";
+
+pub const CHECKED_MARKER: &str = "- [x] ";
+pub const UNCHECKED_MARKER: &str = "- [ ] ";
+
+lazy_static! {
+    static ref CHECKLIST_REGEX: Regex = Regex::new(r#"(?i)<ul[^>]*class\s*=\s*["'][^"']*checklist[^"']*["'][^>]*>"#).unwrap();
+    static ref LIST_TAG_REGEX: Regex = Regex::new(r#"(?i)<(/?)ul\b[^>]*>"#).unwrap();
+    static ref ITEM_TAG_REGEX: Regex = Regex::new(r#"(?i)<(/?)li\b([^>]*)>"#).unwrap();
+    static ref CHECKBOX_REGEX: Regex = Regex::new(r#"(?i)<input([^>]*)>"#).unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"([^\s"'=<>/]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#).unwrap();
+}
+
+/// Replaces every checklist (a list with a "checklist" class, e.g. "Apple-checklist") with
+/// one line per item, each starting with a placeholder that tells if the item is checked.
+/// Checklists nested inside an item follow on the lines after it.
+///
+/// Items are checked if they have a "checked" class or contain a checked checkbox
+pub fn checklists_to_placeholders(html: &str) -> String {
+    let mut result = String::new();
+    let mut rest = html;
+
+    while let Some(list) = CHECKLIST_REGEX.find(rest) {
+        let content = &rest[list.end()..];
+        let (content_end, list_end) = match closing_tag(&LIST_TAG_REGEX, content) {
+            Some(closing) => closing,
+            None => break
+        };
+
+        result.push_str(&rest[..list.start()]);
+        result.push_str(&items_to_placeholders(&content[..content_end]));
+        rest = &content[list_end..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Converts the items of a checklist, html is the content between its ul tags
+fn items_to_placeholders(html: &str) -> String {
+    let mut result = String::new();
+    let mut rest = html;
+
+    while let Some(item) = ITEM_TAG_REGEX.captures(rest) {
+        let tag = item.get(0).unwrap();
+        let content = &rest[tag.end()..];
+        if item[1].is_empty() == false {
+            rest = content;
+            continue;
+        }
+
+        // The closing tag is optional, the item then ends with the list
+        let (content_end, item_end) = closing_tag(&ITEM_TAG_REGEX, content)
+            .unwrap_or((content.len(), content.len()));
+        let content = &content[..content_end];
+
+        // Only the checkbox in front of a nested list belongs to the item
+        let own_end = LIST_TAG_REGEX.find(content).map(|list| list.start()).unwrap_or(content.len());
+        let checked = is_checked(&item[2]) || CHECKBOX_REGEX.captures(&content[..own_end])
+            .map(|checkbox| is_checked(&checkbox[1]))
+            .unwrap_or(false);
+
+        let placeholder = if checked { CHECKED_PLACEHOLDER } else { UNCHECKED_PLACEHOLDER };
+        result.push_str(&format!("<div>{}{}</div>{}",
+                                 placeholder,
+                                 CHECKBOX_REGEX.replace_all(&content[..own_end], "").trim(),
+                                 checklists_to_placeholders(&content[own_end..])));
+
+        rest = &rest[tag.end() + item_end..];
+    }
+
+    result
+}
+
+/// Returns the start and the end of the tag that closes the element, whose opening
+/// tag ends right in front of html. Nested elements of the same type are skipped
+fn closing_tag(tag_regex: &Regex, html: &str) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    for tag in tag_regex.captures_iter(html) {
+        if tag[1].is_empty() {
+            depth += 1;
+        } else if depth == 0 {
+            let tag = tag.get(0).unwrap();
+            return Some((tag.start(), tag.end()));
+        } else {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Checks if the attributes of a tag contain a "checked" attribute or a "checked" class,
+/// attributes like "data-checked" or classes like "unchecked" do not count
+fn is_checked(attributes: &str) -> bool {
+    ATTRIBUTE_REGEX.captures_iter(attributes).any(|attribute| {
+        let name = &attribute[1];
+        let value = attribute.get(2).or(attribute.get(3)).or(attribute.get(4))
+            .map(|value| value.as_str())
+            .unwrap_or("");
+
+        name.eq_ignore_ascii_case("checked") || (name.eq_ignore_ascii_case("class") &&
+            value.split_whitespace().any(|class| class.eq_ignore_ascii_case("checked")))
+    })
+}
+
+/// Turns the placeholders of checklists_to_placeholders into markdown task list items
+pub fn placeholders_to_markdown(markdown: &str) -> String {
+    markdown.split('\n').map(|line| {
+        let trimmed = line.trim_start();
+        if let Some(item) = trimmed.strip_prefix(CHECKED_PLACEHOLDER) {
+            format!("{}{}", CHECKED_MARKER, item.trim_start())
+        } else if let Some(item) = trimmed.strip_prefix(UNCHECKED_PLACEHOLDER) {
+            format!("{}{}", UNCHECKED_MARKER, item.trim_start())
+        } else {
+            line.to_string()
+        }
+    }).collect::<Vec<String>>().join("\n")
+}
+
+/// Returns the text of all unchecked task list items inside markdown
+pub fn open_items(markdown: &str) -> Vec<String> {
+    markdown.lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            line.strip_prefix(UNCHECKED_MARKER)
+                .or(line.strip_prefix("* [ ] "))
+                .or(line.strip_prefix("+ [ ] "))
+        })
+        .map(|item| item.trim().to_string())
+        .filter(|item| item.is_empty() == false)
+        .collect()
+}
+
+#[cfg(test)]
+mod checklist_tests {
+    use crate::checklist::*;
+
+    #[test]
+    fn checklist_placeholders() {
+        let html = "<div>Shopping</div><ul class=\"Apple-checklist\"><li class=\"checked\">Milk</li>\
+            <li><input type=\"checkbox\">Bread</li><li><input type=\"checkbox\" checked>Eggs</li></ul>";
+
+        assert_eq!(checklists_to_placeholders(html), format!(
+            "<div>Shopping</div><div>{0}Milk</div><div>{1}Bread</div><div>{0}Eggs</div>",
+            CHECKED_PLACEHOLDER, UNCHECKED_PLACEHOLDER));
+
+        let markdown = format!("Shopping\n{}Milk\n  {}Bread", CHECKED_PLACEHOLDER, UNCHECKED_PLACEHOLDER);
+        assert_eq!(placeholders_to_markdown(&markdown), "Shopping\n- [x] Milk\n- [ ] Bread");
+    }
+
+    /// Attributes and classes that only contain "checked" leave the item unchecked
+    #[test]
+    fn checked_attribute_and_class() {
+        let html = "<ul class=\"Apple-checklist\"><li data-checked=\"false\">Milk</li>\
+            <li class=\"unchecked-item\">Bread</li><li class=\"item checked\">Eggs</li>\
+            <li><input type=\"checkbox\" data-checked=\"false\">Butter</li>\
+            <li><input checked=\"checked\" type=\"checkbox\">Cheese</li></ul>";
+
+        assert_eq!(checklists_to_placeholders(html), format!(
+            "<div>{1}Milk</div><div>{1}Bread</div><div>{0}Eggs</div><div>{1}Butter</div><div>{0}Cheese</div>",
+            CHECKED_PLACEHOLDER, UNCHECKED_PLACEHOLDER));
+    }
+
+    /// Nested lists do not end the checklist early
+    #[test]
+    fn nested_checklists() {
+        let html = "<ul class=\"Apple-checklist\"><li>Shopping<ul class=\"Apple-checklist\">\
+            <li class=\"checked\">Milk</li><li>Bread</li></ul></li>\
+            <li>Notes<ul><li>plain</li></ul></li><li class=\"checked\">Cleaning</li></ul><div>End</div>";
+
+        assert_eq!(checklists_to_placeholders(html), format!(
+            "<div>{1}Shopping</div><div>{0}Milk</div><div>{1}Bread</div>\
+            <div>{1}Notes</div><ul><li>plain</li></ul><div>{0}Cleaning</div><div>End</div>",
+            CHECKED_PLACEHOLDER, UNCHECKED_PLACEHOLDER));
+    }
+
+    #[test]
+    fn open_checklist_items() {
+        assert_eq!(open_items("Shopping\n- [x] Milk\n- [ ] Bread\n* [ ] Eggs\n- [ ]\n- Butter"),
+                   vec!["Bread".to_string(), "Eggs".to_string()]);
+    }
+}
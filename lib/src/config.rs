@@ -0,0 +1,438 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use serde_derive::{Deserialize, Serialize};
+use crate::error::ProfileError::InvalidConfig;
+use crate::error::Result;
+
+/// Name of the profile that gets used if no profile is passed
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Content of the config file
+///
+/// ```toml
+/// default_profile = "personal"
+///
+/// [profiles.personal]
+/// username = "me"
+/// ...
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile that gets used without --profile, if not set the profile
+    /// named "default" gets used, or the only profile of the file
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub username: String,
+    pub imap_server: String,
+    /// Defaults to 993 for TLS and 143 otherwise
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    /// Pem file of a certificate that gets trusted additionally, e.g. a self-signed certificate
+    pub ca_file: Option<PathBuf>,
+    pub email: String,
+    #[serde(default)]
+    pub password_type: PasswordType,
+    pub password: Option<String>,
+    pub secret_service_attribute: Option<String>,
+    pub secret_service_value: Option<String>,
+    /// Shell command that prints the password, needed for password_type COMMAND
+    pub password_command: Option<String>,
+    /// Gpg encrypted file with the password, needed for password_type GPG_FILE
+    pub password_file: Option<PathBuf>,
+    /// Token endpoint of the provider, needed for password_type OAUTH2
+    pub oauth2_token_url: Option<String>,
+    pub oauth2_client_id: Option<String>,
+    /// Not every provider issues secrets to installed applications
+    pub oauth2_client_secret: Option<String>,
+    pub editor: String,
+    #[serde(default)]
+    pub editor_arguments: Vec<String>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PasswordType {
+    Plain,
+    SecretService,
+    /// XOAUTH2 login, the refresh token is stored in the secret service
+    #[serde(rename = "OAUTH2")]
+    OAuth2,
+    /// First line of the output of password_command
+    Command,
+    /// First line of password_file, decrypted with gpg
+    GpgFile
+}
+
+impl Default for PasswordType {
+    fn default() -> Self {
+        PasswordType::Plain
+    }
+}
+
+/// How the connection to the imap server gets encrypted
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Security {
+    /// Implicit tls
+    #[serde(rename = "TLS")]
+    Tls,
+    #[serde(rename = "STARTTLS")]
+    StartTls,
+    /// Unencrypted, only allowed for servers on the loopback interface
+    #[serde(rename = "NONE")]
+    Plaintext
+}
+
+impl Security {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Security::Tls => 993,
+            Security::StartTls | Security::Plaintext => 143
+        }
+    }
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Security::Tls
+    }
+}
+
+/// Config together with the file it got read from
+#[derive(Debug)]
+pub struct LoadedConfig {
+    pub path: PathBuf,
+    /// True if the config still uses the old key=value format
+    pub legacy: bool,
+    pub profiles: Vec<String>,
+    /// Profile that gets used without --profile
+    pub default_profile: Option<String>,
+    pub config: Config
+}
+
+impl Config {
+
+    /// Returns the name and config of the passed profile, or of the default profile
+    pub fn profile(&self, name: Option<&str>) -> Option<(&String, &ProfileConfig)> {
+        let name = name
+            .or(self.default_profile.as_deref())
+            .or(self.profiles.get_key_value(DEFAULT_PROFILE).map(|(name, _)| name.as_str()))
+            .or(if self.profiles.len() == 1 { self.profiles.keys().next().map(|name| name.as_str()) } else { None })?;
+        self.profiles.get_key_value(name)
+    }
+}
+
+/// Parses a toml config file, errors contain the line of the invalid entry
+pub fn parse_toml(content: &str) -> Result<Config> {
+    let config: Config = toml::from_str(content)
+        .map_err(|e| InvalidConfig(e.to_string()))?;
+
+    for (name, profile) in &config.profiles {
+        let header = format!("[profiles.{}]", name);
+        validate_profile(name, profile)
+            .map_err(|e| with_line(section_line(content, &header), e))?;
+    }
+    validate_default_profile(&config)?;
+
+    Ok(config)
+}
+
+/// Parses the key=value config file that was used before the toml config existed.
+///
+/// Every profile starts with a "[name]" line, keys before the first section belong
+/// to the default profile. Lines starting with "#" are comments.
+pub fn parse_legacy(content: &str) -> Result<Config> {
+    // Profile name, line of the section and all of its keys with their line
+    let mut sections: Vec<(String, usize, HashMap<String, (usize, String)>)> =
+        vec![(DEFAULT_PROFILE.to_string(), 1, HashMap::new())];
+    let mut current = 0;
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let name = name.trim().to_string();
+            current = match sections.iter().position(|(section, _, _)| section == &name) {
+                Some(index) => index,
+                None => {
+                    sections.push((name, line_number, HashMap::new()));
+                    sections.len() - 1
+                }
+            };
+            continue;
+        }
+
+        let (key, value) = line.split_once('=')
+            .ok_or(with_line(Some(line_number), format!("expected key=value, found \"{}\"", line)))?;
+        let key = key.trim();
+
+        if LEGACY_KEYS.contains(&key) == false {
+            return Err(with_line(Some(line_number), format!("unknown key \"{}\"", key)).into());
+        }
+
+        let previous = sections[current].2.insert(key.to_string(), (line_number, value.trim().to_string()));
+        if previous.is_some() {
+            return Err(with_line(Some(line_number), format!("duplicate key \"{}\"", key)).into());
+        }
+    }
+
+    // The default profile only exists if there are keys before the first section
+    let has_default_keys = sections[0].2.is_empty() == false;
+    let first_profile = sections.get(1).map(|(name, _, _)| name.clone());
+
+    let mut profiles = BTreeMap::new();
+    for (name, line, keys) in sections.into_iter().filter(|(_, _, keys)| keys.is_empty() == false) {
+        let profile = legacy_profile(line, &keys)?;
+        validate_profile(&name, &profile).map_err(|e| with_line(Some(line), e))?;
+        profiles.insert(name, profile);
+    }
+
+    let config = Config {
+        default_profile: if has_default_keys { None } else { first_profile },
+        profiles
+    };
+    validate_default_profile(&config)?;
+
+    Ok(config)
+}
+
+const LEGACY_KEYS: [&str; 14] = ["username", "password", "password_type", "imap_server", "port", "security",
+    "ca_file", "email", "editor", "editor_arguments", "secret_service_attribute", "secret_service_value",
+    "password_command", "password_file"];
+
+fn legacy_profile(section_line: usize, keys: &HashMap<String, (usize, String)>) -> Result<ProfileConfig> {
+    let optional = |key: &str| keys.get(key).map(|(_, value)| value.clone());
+    let required = |key: &str| optional(key)
+        .ok_or(with_line(Some(section_line), format!("missing key \"{}\"", key)));
+
+    let password_type = match keys.get("password_type") {
+        None => PasswordType::Plain,
+        Some((_, value)) if value == "PLAIN" => PasswordType::Plain,
+        Some((_, value)) if value == "SECRET_SERVICE" => PasswordType::SecretService,
+        Some((_, value)) if value == "COMMAND" => PasswordType::Command,
+        Some((_, value)) if value == "GPG_FILE" => PasswordType::GpgFile,
+        Some((line, value)) if value == "OAUTH2" => {
+            return Err(with_line(Some(*line), "password_type OAUTH2 is only supported by config.toml".to_string()).into())
+        },
+        Some((line, value)) => return Err(with_line(Some(*line), format!("unknown password_type \"{}\"", value)).into())
+    };
+
+    let security = match keys.get("security") {
+        None => Security::Tls,
+        Some((_, value)) if value == "TLS" => Security::Tls,
+        Some((_, value)) if value == "STARTTLS" => Security::StartTls,
+        Some((_, value)) if value == "NONE" => Security::Plaintext,
+        Some((line, value)) => return Err(with_line(Some(*line), format!("unknown security \"{}\"", value)).into())
+    };
+
+    let port = match keys.get("port") {
+        None => None,
+        Some((line, value)) => Some(value.parse::<u16>()
+            .map_err(|_| with_line(Some(*line), format!("invalid port \"{}\"", value)))?)
+    };
+
+    Ok(ProfileConfig {
+        username: required("username")?,
+        imap_server: required("imap_server")?,
+        port,
+        security,
+        ca_file: optional("ca_file").map(PathBuf::from),
+        email: required("email")?,
+        password_type,
+        password: optional("password"),
+        secret_service_attribute: optional("secret_service_attribute"),
+        secret_service_value: optional("secret_service_value"),
+        password_command: optional("password_command"),
+        password_file: optional("password_file").map(PathBuf::from),
+        oauth2_token_url: None,
+        oauth2_client_id: None,
+        oauth2_client_secret: None,
+        editor: required("editor")?,
+        editor_arguments: optional("editor_arguments")
+            .map(|args| args.split(' ').map(|s| s.to_string()).filter(|s| s.len() > 0).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Checks the values of a profile that can not be checked while parsing
+fn validate_profile(name: &str, profile: &ProfileConfig) -> std::result::Result<(), String> {
+    // The name becomes part of the database file name
+    if name.is_empty() || name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') == false {
+        return Err(format!("invalid profile name \"{}\", only alphanumeric characters, \"-\" and \"_\" are allowed", name));
+    }
+
+    if profile.email.rsplit_once('@').map(|(_, domain)| domain.is_empty()).unwrap_or(true) {
+        return Err(format!("profile \"{}\": email \"{}\" has no domain", name, profile.email));
+    }
+
+    if profile.security == Security::Plaintext && is_loopback(&profile.imap_server) == false {
+        return Err(format!("profile \"{}\": security NONE is only allowed for servers on the loopback interface", name));
+    }
+
+    match profile.password_type {
+        PasswordType::Plain if profile.password.is_none() => {
+            Err(format!("profile \"{}\": password_type PLAIN needs a password", name))
+        },
+        PasswordType::SecretService if profile.secret_service_attribute.is_none() || profile.secret_service_value.is_none() => {
+            Err(format!("profile \"{}\": password_type SECRET_SERVICE needs secret_service_attribute and secret_service_value", name))
+        },
+        PasswordType::OAuth2 if profile.secret_service_attribute.is_none() || profile.secret_service_value.is_none() => {
+            Err(format!("profile \"{}\": password_type OAUTH2 needs secret_service_attribute and secret_service_value of the refresh token", name))
+        },
+        PasswordType::Command if profile.password_command.is_none() => {
+            Err(format!("profile \"{}\": password_type COMMAND needs a password_command", name))
+        },
+        PasswordType::GpgFile if profile.password_file.is_none() => {
+            Err(format!("profile \"{}\": password_type GPG_FILE needs a password_file", name))
+        },
+        PasswordType::OAuth2 if profile.oauth2_token_url.is_none() || profile.oauth2_client_id.is_none() => {
+            Err(format!("profile \"{}\": password_type OAUTH2 needs oauth2_token_url and oauth2_client_id", name))
+        },
+        _ => Ok(())
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "localhost" || host.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+fn validate_default_profile(config: &Config) -> Result<()> {
+    match &config.default_profile {
+        Some(name) if config.profiles.contains_key(name) == false => {
+            Err(InvalidConfig(format!("default_profile \"{}\" does not exist", name)).into())
+        },
+        _ => Ok(())
+    }
+}
+
+/// Returns the line number of the passed section header
+fn section_line(content: &str, header: &str) -> Option<usize> {
+    content.lines()
+        .position(|line| line.trim().replace(' ', "") == header)
+        .map(|index| index + 1)
+}
+
+fn with_line(line: Option<usize>, message: String) -> crate::error::ProfileError {
+    match line {
+        Some(line) => InvalidConfig(format!("line {}: {}", line, message)),
+        None => InvalidConfig(message)
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use crate::config::*;
+
+    #[test]
+    fn toml_config() {
+        let config = parse_toml("
+            default_profile = \"work\"
+
+            # Private account
+            [profiles.personal]
+            username = \"me\"
+            imap_server = \"imap.test.de\"
+            email = \"me@test.de\"
+            password = \"f\"
+            editor = \"vim\"
+
+            [profiles.work]
+            username = \"me@work.de\"
+            imap_server = \"imap.work.de\"
+            email = \"me@work.de\"
+            password_type = \"SECRET_SERVICE\"
+            secret_service_attribute = \"mail\"
+            secret_service_value = \"work\"
+            editor = \"nvim\"
+            editor_arguments = [\"-R\"]
+        ").unwrap();
+
+        let (name, profile) = config.profile(None).unwrap();
+        assert_eq!(name, "work");
+        assert_eq!(profile.password_type, PasswordType::SecretService);
+        assert_eq!(profile.editor_arguments, vec!["-R".to_string()]);
+        assert_eq!(config.profile(Some("personal")).unwrap().1.password_type, PasswordType::Plain);
+        assert!(config.profile(Some("private")).is_none());
+
+        // Toml can be written and read again, that's how old configs get migrated
+        assert_eq!(parse_toml(&toml::to_string(&config).unwrap()).unwrap(), config);
+    }
+
+    #[test]
+    fn invalid_toml_config() {
+        let unknown_key = parse_toml("[profiles.work]\nusername = \"me\"\nsecret_service_username = \"me\"\n");
+        let message = unknown_key.err().unwrap().to_string();
+        assert!(message.contains("secret_service_username"));
+        assert!(message.contains("line "));
+
+        let missing_password = parse_toml("\n[profiles.work]\nusername = \"me\"\nimap_server = \"imap\"\nemail = \"me@work.de\"\neditor = \"vim\"\n");
+        assert!(missing_password.err().unwrap().to_string().contains("line 2: profile \\\"work\\\": password_type PLAIN needs a password"));
+    }
+
+    #[test]
+    fn legacy_config() {
+        let config = parse_legacy("
+            username=me
+            # Comment
+            imap_server=imap.test.de
+            email=me@test.de
+            password=f
+            editor=vim
+            editor_arguments=-R --clean
+
+            [work]
+            secret_service_username=me
+        ");
+        assert_eq!(config.err().unwrap().to_string(), "InvalidConfig(\"line 11: unknown key \\\"secret_service_username\\\"\")");
+
+        let config = parse_legacy("
+            [personal]
+            username=me
+            imap_server=imap.test.de
+            email=me@test.de
+            password=f
+            editor=vim
+            editor_arguments=
+        ").unwrap();
+        let (name, profile) = config.profile(None).unwrap();
+        assert_eq!(name, "personal");
+        assert_eq!(profile.username, "me");
+        assert!(profile.editor_arguments.is_empty());
+
+        assert!(parse_legacy("[work]\nusername=me").err().unwrap().to_string().contains("line 1: missing key"));
+    }
+
+    #[test]
+    fn connection_security() {
+        let profile = |server: &str, security: &str| format!(
+            "[profiles.test]\nusername = \"me\"\nimap_server = \"{}\"\nemail = \"me@test.de\"\npassword = \"f\"\neditor = \"vim\"\n{}\n",
+            server, security);
+
+        let config = parse_toml(&profile("127.0.0.1", "security = \"NONE\"\nport = 1143")).unwrap();
+        let (_, test) = config.profile(None).unwrap();
+        assert_eq!(test.security, Security::Plaintext);
+        assert_eq!(test.port, Some(1143));
+
+        let config = parse_toml(&profile("imap.test.de", "security = \"STARTTLS\"\nca_file = \"/tmp/ca.pem\"")).unwrap();
+        let (_, test) = config.profile(None).unwrap();
+        assert_eq!(test.security.default_port(), 143);
+        assert_eq!(test.ca_file, Some(PathBuf::from("/tmp/ca.pem")));
+
+        assert_eq!(parse_toml(&profile("imap.test.de", "")).unwrap().profile(None).unwrap().1.security, Security::Tls);
+        assert!(parse_toml(&profile("imap.test.de", "security = \"NONE\"")).err().unwrap().to_string().contains("loopback"));
+        assert!(parse_legacy("port=imap").err().unwrap().to_string().contains("line 1: invalid port"));
+    }
+}
@@ -1,23 +1,419 @@
 extern crate pulldown_cmark;
 
 use crate::model::Body;
-use self::pulldown_cmark::{Parser, html};
+use crate::checklist;
+use crate::table;
+use crate::attachment;
+use self::pulldown_cmark::{Parser, Options, Event, Tag, html};
 
+const HTML_HEAD: &str = "<html><head></head><body style=\"word-wrap: break-word; -webkit-nbsp-mode: space; line-break: after-white-space;\">";
+const HTML_TAIL: &str = "</body></html>";
+
+/// Elements that never have a closing tag
+const VOID_ELEMENTS: [&str; 8] = ["br", "hr", "img", "input", "meta", "link", "col", "wbr"];
+
+/// Converts the html of a note into markdown, checklists become task list items ("- [ ] ")
+/// tables become pipe tables and embedded attachments become images with a "cid:" url
 pub fn convert2md(input: &String) -> String {
-    html2runes::markdown::convert_string(input.as_str())
+    let html = attachment::attachments_to_placeholders(input);
+    let html = table::tables_to_placeholders(&checklist::checklists_to_placeholders(&html));
+    let markdown = html2runes::markdown::convert_string(&html);
+    let markdown = checklist::placeholders_to_markdown(&table::placeholders_to_markdown(&markdown));
+    attachment::placeholders_to_markdown(&markdown)
 }
 
+/// Returns the html that gets uploaded for the passed body, quoted-printable encoded
 pub fn convert_to_html(input: &Body) -> String {
+    quoted_printable::encode_to_str(render_html(input)).replace("=0A", "")
+}
+
+/// Returns the html of the passed body
+///
+/// If the html of the remote message is known, only the text blocks that changed
+/// get converted from markdown, all other blocks are kept as they are. Otherwise
+/// the whole text gets converted.
+pub fn render_html(input: &Body) -> String {
     let content = input.text.as_ref().expect("Expected body with message");
-    let content = htmlescape::encode_minimal(&content);
-    let parser = Parser::new(&content);
+    match &input.html {
+        Some(original) => apply_changes(original, content),
+        None => format!("{}{}{}", HTML_HEAD, markdown_to_html(content), HTML_TAIL)
+    }
+}
+
+fn markdown_to_html(text: &str) -> String {
+    let content = htmlescape::encode_minimal(text);
+    let events: Vec<Event> = Parser::new_ext(&content, Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES).collect();
+
+    // Returns the checked state if the item starting at index is a task list item
+    let task_state = |index: usize| -> Option<bool> {
+        events[index + 1..].iter()
+            .take(2)
+            .find_map(|event| match event {
+                Event::TaskListMarker(checked) => Some(*checked),
+                _ => None
+            })
+    };
+
+    let mut checklists: Vec<bool> = vec![];
+    let mut output: Vec<Event> = vec![];
+    for (index, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(Tag::List(None)) => {
+                let is_checklist = events[index + 1..].first()
+                    .map(|item| matches!(item, Event::Start(Tag::Item)) && task_state(index + 1).is_some())
+                    .unwrap_or(false);
+                checklists.push(is_checklist);
+                let class = if is_checklist { "checklist" } else { "Apple-dash-list" };
+                output.push(Event::Html(format!("<ul class=\"{}\">\n", class).into()));
+            }
+            Event::Start(Tag::List(Some(_))) => {
+                checklists.push(false);
+                output.push(event.clone());
+            }
+            Event::End(Tag::List(_)) => {
+                checklists.pop();
+                output.push(event.clone());
+            }
+            Event::Start(Tag::Item) if checklists.last() == Some(&true) => {
+                let item = if task_state(index) == Some(true) { "<li class=\"checked\">" } else { "<li>" };
+                output.push(Event::Html(item.into()));
+            }
+            // The state of the item is part of the li element
+            Event::TaskListMarker(_) => {}
+            // Tables are written the way the notes app renders them, without header
+            Event::Start(Tag::Table(_)) => output.push(Event::Html(table::TABLE_START.into())),
+            Event::End(Tag::Table(_)) => output.push(Event::Html(format!("{}\n", table::TABLE_END).into())),
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => output.push(Event::Html("<tr>".into())),
+            Event::End(Tag::TableHead) | Event::End(Tag::TableRow) => output.push(Event::Html("</tr>".into())),
+            Event::Start(Tag::TableCell) => output.push(Event::Html(table::CELL_START.into())),
+            Event::End(Tag::TableCell) => output.push(Event::Html(table::CELL_END.into())),
+            _ => output.push(event.clone())
+        }
+    }
+
     let mut html_output: String = String::new();
-    html::push_html(&mut html_output, parser);
-    let output = format!("{}{}{}",
-            "<html><head></head><body style=\"word-wrap: break-word; -webkit-nbsp-mode: space; line-break: after-white-space;\">",
-            html_output,
-            "</body></html>")
-        .replace("<ul>", "<ul class=\"Apple-dash-list\">");
-
-    quoted_printable::encode_to_str(output).replace("=0A", "")
+    html::push_html(&mut html_output, output.into_iter());
+    html_output
+}
+
+/// Part of the new html, either an untouched block of the original html
+/// or lines of the edited text that replace or get inserted between blocks
+enum Segment {
+    Original(usize),
+    Changed(Vec<usize>)
+}
+
+/// Diffs the text of every top level block of original against text and only replaces
+/// the blocks whose text changed. Blank lines are ignored while diffing, because
+/// html2runes does not convert whitespace between blocks consistently
+fn apply_changes(original: &str, text: &str) -> String {
+    let (head, content, tail) = split_document(original);
+    let blocks = split_blocks(content);
+
+    // Text lines of all blocks, together with the block they belong to
+    let mut base_lines: Vec<String> = vec![];
+    let mut owner: Vec<usize> = vec![];
+    for (index, block) in blocks.iter().enumerate() {
+        for line in convert2md(&block.to_string()).lines().filter(|line| line.trim().is_empty() == false) {
+            base_lines.push(line.trim().to_string());
+            owner.push(index);
+        }
+    }
+
+    let text_lines: Vec<&str> = text.lines().collect();
+    let new_index: Vec<usize> = (0..text_lines.len())
+        .filter(|index| text_lines[*index].trim().is_empty() == false)
+        .collect();
+    let new_lines: Vec<String> = new_index.iter().map(|index| text_lines[*index].trim().to_string()).collect();
+
+    let mut segments: Vec<Segment> = vec![];
+    let mut block_lines: Vec<Vec<usize>> = vec![vec![]; blocks.len()];
+    let mut changed = vec![false; blocks.len()];
+    let mut started = vec![false; blocks.len()];
+    let mut next_block = 0;
+    let mut pending: Vec<usize> = vec![];
+    let (mut left, mut right) = (0, 0);
+
+    for result in diff::slice(&base_lines, &new_lines) {
+        if let diff::Result::Right(_) = result {
+            pending.push(new_index[right]);
+            right += 1;
+            continue;
+        }
+
+        let block = owner[left];
+        if started[block] == false {
+            // Blocks without text are kept in front of the inserted lines
+            while next_block < block {
+                segments.push(Segment::Original(next_block));
+                next_block += 1;
+            }
+            if pending.is_empty() == false {
+                segments.push(Segment::Changed(pending.drain(..).collect()));
+            }
+            segments.push(Segment::Original(block));
+            next_block = block + 1;
+            started[block] = true;
+        } else if pending.is_empty() == false {
+            // Lines got inserted inside of a block
+            block_lines[block].extend(pending.drain(..));
+            changed[block] = true;
+        }
+
+        match result {
+            diff::Result::Both(_, _) => {
+                block_lines[block].push(new_index[right]);
+                right += 1;
+            }
+            _ => changed[block] = true
+        }
+        left += 1;
+    }
+
+    while next_block < blocks.len() {
+        segments.push(Segment::Original(next_block));
+        next_block += 1;
+    }
+    if pending.is_empty() == false {
+        segments.push(Segment::Changed(pending));
+    }
+
+    let render_lines = |indexes: &Vec<usize>| -> String {
+        match (indexes.first(), indexes.last()) {
+            // Includes the blank lines in between, so that paragraphs stay intact
+            (Some(first), Some(last)) => markdown_to_html(&text_lines[*first..=*last].join("\n")),
+            _ => "".to_string()
+        }
+    };
+
+    let body: String = segments.iter().map(|segment| match segment {
+        Segment::Original(block) if changed[*block] == false => blocks[*block].to_string(),
+        Segment::Original(block) => render_lines(&block_lines[*block]),
+        Segment::Changed(indexes) => render_lines(indexes)
+    }).collect();
+
+    format!("{}{}{}", head, body, tail)
+}
+
+/// Splits a html document into everything up to the opening body tag,
+/// the content of the body and everything after the closing body tag
+fn split_document(html: &str) -> (&str, &str, &str) {
+    let lowercase = html.to_lowercase();
+    let content_start = lowercase.find("<body")
+        .and_then(|start| find_tag_end(html, start));
+    let content_end = lowercase.rfind("</body");
+
+    match (content_start, content_end) {
+        (Some(start), Some(end)) if start <= end => (&html[..start], &html[start..end], &html[end..]),
+        _ => ("", html, "")
+    }
+}
+
+/// Returns the index after the end of the tag that starts at start,
+/// or None if the tag is not terminated
+fn find_tag_end(html: &str, start: usize) -> Option<usize> {
+    if html[start..].starts_with("<!--") {
+        return html[start..].find("-->").map(|end| start + end + 3);
+    }
+
+    let mut quote: Option<char> = None;
+    for (offset, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(start + offset + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits html into its top level elements, text between elements forms its own block
+/// and whitespace gets attached to the previous block. Joining all blocks results in
+/// the passed html again
+fn split_blocks(html: &str) -> Vec<&str> {
+    let mut blocks: Vec<&str> = vec![];
+    let mut depth = 0usize;
+    let mut block_start = 0;
+    let mut index = 0;
+
+    while let Some(offset) = html[index..].find('<') {
+        let tag_start = index + offset;
+        let tag_end = match find_tag_end(html, tag_start) {
+            Some(end) => end,
+            None => break
+        };
+
+        if depth == 0 && tag_start > block_start {
+            blocks.push(&html[block_start..tag_start]);
+            block_start = tag_start;
+        }
+
+        let tag = &html[tag_start..tag_end];
+        let name = tag.trim_start_matches(|c| c == '<' || c == '/')
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        if tag.starts_with("</") {
+            depth = depth.saturating_sub(1);
+        } else if tag.starts_with("<!") == false && tag.ends_with("/>") == false && VOID_ELEMENTS.contains(&name.as_str()) == false {
+            depth += 1;
+        }
+
+        if depth == 0 {
+            blocks.push(&html[block_start..tag_end]);
+            block_start = tag_end;
+        }
+        index = tag_end;
+    }
+
+    if block_start < html.len() {
+        blocks.push(&html[block_start..]);
+    }
+
+    // Whitespace between blocks has no text, so it stays with the block in front of it
+    let mut merged: Vec<&str> = vec![];
+    let mut start = 0;
+    for block in blocks {
+        let end = start + block.len();
+        match merged.last_mut() {
+            Some(last) if block.trim().is_empty() => {
+                let last_start = end - block.len() - last.len();
+                *last = &html[last_start..end];
+            }
+            _ => merged.push(&html[start..end])
+        }
+        start = end;
+    }
+    merged
+}
+
+#[cfg(test)]
+mod converter_tests {
+    use crate::converter::*;
+    use crate::builder::BodyMetadataBuilder;
+
+    const NOTE: &str = "<html><head></head><body style=\"word-wrap: break-word;\">\
+        <div><font face=\"Helvetica\">Title</font></div>\n\
+        <div class=\"Apple-checklist\"><b>Buy</b> milk</div>\n\
+        <div>Last line</div>\
+        </body></html>";
+
+    #[test]
+    fn split_into_blocks() {
+        let html = "<div>a<br>b</div>\n<ul><li title=\"<li>\">c</li></ul><!-- x -->text<img src=\"a.png\"/>";
+        assert_eq!(split_blocks(html), vec![
+            "<div>a<br>b</div>\n",
+            "<ul><li title=\"<li>\">c</li></ul>",
+            "<!-- x -->",
+            "text",
+            "<img src=\"a.png\"/>"
+        ]);
+        assert_eq!(split_blocks(html).concat(), html);
+
+        let (head, content, tail) = split_document(NOTE);
+        assert_eq!(head, "<html><head></head><body style=\"word-wrap: break-word;\">");
+        assert!(content.starts_with("<div><font"));
+        assert_eq!(tail, "</body></html>");
+    }
+
+    /// object has a closing tag, so it must not end the surrounding block
+    #[test]
+    fn object_block_stays_together() {
+        let html = "<div><object type=\"application/x-apple-msg-attachment\" data=\"cid:1@apnotes\"></object></div>\n<div>Text</div>";
+        assert_eq!(split_blocks(html), vec![
+            "<div><object type=\"application/x-apple-msg-attachment\" data=\"cid:1@apnotes\"></object></div>\n",
+            "<div>Text</div>"
+        ]);
+    }
+
+    #[test]
+    fn unchanged_note_keeps_html() {
+        let body = BodyMetadataBuilder::new()
+            .with_text(&convert2md(&NOTE.to_string()))
+            .with_html(Some(NOTE.to_string()))
+            .build();
+
+        assert_eq!(render_html(&body), NOTE);
+    }
+
+    #[test]
+    fn only_changed_block_gets_replaced() {
+        let text = convert2md(&NOTE.to_string()).replace("Last line", "Changed line");
+        let body = BodyMetadataBuilder::new()
+            .with_text(&text)
+            .with_html(Some(NOTE.to_string()))
+            .build();
+
+        let html = render_html(&body);
+        assert!(html.starts_with("<html><head></head><body style=\"word-wrap: break-word;\"><div><font face=\"Helvetica\">Title</font></div>"));
+        assert!(html.contains("<div class=\"Apple-checklist\"><b>Buy</b> milk</div>"));
+        assert!(html.contains("Changed line"));
+        assert!(html.contains("Last line") == false);
+        assert!(html.ends_with("</body></html>"));
+    }
+
+    #[test]
+    fn checklist_round_trip() {
+        let html = format!("{}<div>Shopping</div><ul class=\"checklist\"><li class=\"checked\">Milk</li><li>Bread</li></ul>{}", HTML_HEAD, HTML_TAIL);
+        let markdown = convert2md(&html);
+        assert!(markdown.contains("- [x] Milk"));
+        assert!(markdown.contains("- [ ] Bread"));
+
+        let body = BodyMetadataBuilder::new().with_text(&markdown).build();
+        let uploaded = render_html(&body);
+        assert!(uploaded.contains("<ul class=\"checklist\">"));
+        assert!(uploaded.contains("<li class=\"checked\">Milk</li>"));
+        assert!(uploaded.contains("<li>Bread</li>"));
+
+        let markdown = convert2md(&uploaded);
+        assert!(markdown.contains("- [x] Milk"));
+        assert!(markdown.contains("- [ ] Bread"));
+    }
+
+    #[test]
+    fn table_round_trip() {
+        let html = format!("{}<div>Prices</div>{}<tr>{}Item{}{}Price{}</tr><tr>{}Milk{}{}1,20{}</tr>{}{}",
+                           HTML_HEAD,
+                           table::TABLE_START,
+                           table::CELL_START, table::CELL_END, table::CELL_START, table::CELL_END,
+                           table::CELL_START, table::CELL_END, table::CELL_START, table::CELL_END,
+                           table::TABLE_END,
+                           HTML_TAIL);
+        let markdown = convert2md(&html);
+        assert!(markdown.contains("| Item | Price |\n| --- | --- |\n| Milk | 1,20 |"));
+
+        // Unchanged tables stay as they are
+        let body = BodyMetadataBuilder::new().with_text(&markdown).with_html(Some(html.clone())).build();
+        assert_eq!(render_html(&body), html);
+
+        let body = BodyMetadataBuilder::new().with_text(&markdown.replace("1,20", "1,30")).with_html(Some(html.clone())).build();
+        let uploaded = render_html(&body);
+        assert!(uploaded.contains(&format!("{}<tr>{}Item{}{}Price{}</tr><tr>{}Milk{}{}1,30{}</tr>{}",
+                                          table::TABLE_START,
+                                          table::CELL_START, table::CELL_END, table::CELL_START, table::CELL_END,
+                                          table::CELL_START, table::CELL_END, table::CELL_START, table::CELL_END,
+                                          table::TABLE_END)));
+        assert!(uploaded.contains("<div>Prices</div>"));
+    }
+
+    #[test]
+    fn attachment_round_trip() {
+        let html = format!("{}<div>Photo</div><div><img src=\"cid:1234@apnotes\"><br></div>{}", HTML_HEAD, HTML_TAIL);
+        let markdown = convert2md(&html);
+        assert!(markdown.contains("![attachment](cid:1234@apnotes)"));
+
+        let body = BodyMetadataBuilder::new().with_text(&markdown).build();
+        let uploaded = render_html(&body);
+        assert!(uploaded.contains("src=\"cid:1234@apnotes\""));
+        assert!(convert2md(&uploaded).contains("![attachment](cid:1234@apnotes)"));
+    }
+
+    #[test]
+    fn new_note_gets_converted() {
+        let body = BodyMetadataBuilder::new().with_text("Title\n\n- item").build();
+        assert_eq!(render_html(&body), format!("{}<p>Title</p>\n<ul class=\"Apple-dash-list\">\n<li>item</li>\n</ul>\n{}", HTML_HEAD, HTML_TAIL));
+    }
 }
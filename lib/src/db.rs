@@ -10,9 +10,11 @@ Set: SQLITE3_LIB_DIR
 use crate::{schema};
 
 use diesel::{SqliteConnection, Connection};
+use diesel::connection::TransactionManager;
 use diesel::*;
 use diesel::result::Error;
-use crate::model::{NotesMetadata, Body};
+use crate::model::{NotesMetadata, Body, FolderSyncState, CachedHeader, Attachment, JournalEntry, HistoryEntry, NewHistoryEntry};
+use crate::notes::remote_note_metadata::RemoteNoteMetaData;
 use crate::schema::metadata::dsl::metadata;
 use crate::schema::body::dsl::body;
 use self::log::*;
@@ -21,6 +23,12 @@ use std::collections::HashSet;
 use std::collections::hash_map::RandomState;
 use crate::schema::metadata::columns::subfolder;
 use crate::notes::localnote::LocalNote;
+use crate::notes::search_result::SearchResult;
+use crate::util::is_inside_folder;
+use diesel::sql_types::Text;
+use crate::history;
+use chrono::Utc;
+use std::cell::RefCell;
 
 embed_migrations!("../migrations/");
 
@@ -40,7 +48,7 @@ pub trait DatabaseService {
     /// In case of a successful merge this method replaces all unmerged notes with a single
     /// merged note
     fn update_merged_note(&self, note_body: &Body) -> Result<(), Error>;
-    /// Deletes the passed local_note with all note_bodies
+    /// Deletes the passed local_note with all note_bodies, attachments and its history
     fn delete(&self, local_note: &LocalNote) -> Result<(), Error>;
     /// Deletes a single note_body
     fn delete_note_body(&self, note_body: &Body) -> Result<(), Error>;
@@ -48,10 +56,14 @@ pub trait DatabaseService {
     fn delete_note_bodies<'a>(&self, note_bodies: &Vec<&'a Body>) -> Result<(), Error>;
     /// Updates the passed local_note with the new content
     ///
-    /// Overrides everything
+    /// Overrides everything, replaced texts are recorded as local edit
     fn update(&self, local_note: &LocalNote) -> Result<(), Error>;
+    /// Same as update, replaced texts are recorded with the passed history source
+    fn update_with_source(&self, local_note: &LocalNote, source: &str) -> Result<(), Error>;
     /// Inserts the passed local_note
     fn insert_into_db(&self,note: &LocalNote) -> Result<(), Error>;
+    /// Inserts all passed notes, if one of them fails none of them gets inserted
+    fn insert_notes(&self, notes: &Vec<LocalNote>) -> Result<(), Error>;
     /// Returns all local_notes that are currently inside the database, including
     /// the note_bodies
     fn fetch_all_notes(&self) -> Result<HashSet<LocalNote>,Error>;
@@ -63,9 +75,60 @@ pub trait DatabaseService {
     /// Checks if the Note-Metadata Entry with the specified id
     /// does have note-bodies.
     fn is_widow(&self, metadata_unique_id: &str) -> Result<bool, Error>;
-    /// Deletes a single metadata object, needed to delete widow_metadata_entries
+    /// Deletes a single metadata object and its history, needed to delete widow_metadata_entries
     fn delete_metadata(&self, uuid: &str) -> Result<(), Error>;
-    fn replace_notes(&self, notes: &Vec<Body>, uuid: String) -> Result<(), Error>;
+    /// Replaces all bodies of the note with the passed uuid, replaced texts
+    /// are recorded with the passed history source
+    fn replace_notes(&self, notes: &Vec<Body>, uuid: String, source: &str) -> Result<(), Error>;
+    /// Searches the text of all note bodies, returns the matching notes
+    /// ordered by relevance, every note is only returned once
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, Error>;
+    /// Returns the state of the imap folder at the last synchronization
+    fn fetch_folder_sync_state(&self, folder: &str) -> Result<Option<FolderSyncState>, Error>;
+    /// Returns the cached headers of all messages inside the specified imap folder
+    fn fetch_cached_headers(&self, folder: &str) -> Result<Vec<RemoteNoteMetaData>, Error>;
+    /// Replaces the sync state and all cached headers of an imap folder
+    fn update_folder_sync_state(&self, state: &FolderSyncState, headers: &Vec<RemoteNoteMetaData>) -> Result<(), Error>;
+    /// Deletes the sync state and cached headers of an imap folder and all of its subfolders
+    fn delete_folder_sync_state(&self, folder: &str) -> Result<(), Error>;
+    /// Moves all notes inside the folder old and its subfolders into the folder new
+    fn rename_folder(&self, old: &str, new: &str) -> Result<(), Error>;
+    /// Name of the profile the database belongs to, every profile has its own blob store
+    fn profile_name(&self) -> &str;
+    /// Inserts or replaces the metadata of an attachment, attachments are
+    /// identified by the uuid of their note and their content id
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), Error>;
+    /// Returns the metadata of all attachments of the note with the passed uuid
+    fn fetch_attachments(&self, uuid: &str) -> Result<Vec<Attachment>, Error>;
+    /// Inserts or replaces the journal entry of a planned sync action
+    fn insert_journal_entry(&self, entry: &JournalEntry) -> Result<(), Error>;
+    /// Sets the phase and the uid of the written message of a journal entry
+    fn update_journal_phase(&self, uuid: &str, phase: &str, uid: Option<i64>) -> Result<(), Error>;
+    /// Deletes the journal entry of a finished sync action
+    fn delete_journal_entry(&self, uuid: &str) -> Result<(), Error>;
+    /// Returns the journal entries of all unfinished sync actions
+    fn fetch_journal(&self) -> Result<Vec<JournalEntry>, Error>;
+    /// Returns all previous texts of the note with the passed uuid, oldest first
+    fn fetch_history(&self, uuid: &str) -> Result<Vec<HistoryEntry>, Error>;
+}
+
+#[derive(QueryableByName)]
+struct SearchRow {
+    #[sql_type = "Text"]
+    note_uuid: String,
+    #[sql_type = "Text"]
+    snippet: String
+}
+
+/// Converts user input into a fts5 query, every word is treated
+/// as prefix of a term, so that the search works while typing
+fn fts_query(query: &str) -> String {
+    query.split_whitespace()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| word.is_empty() == false)
+        .map(|word| format!("\"{}\"*", word))
+        .collect::<Vec<String>>()
+        .join(" ")
 }
 
 struct SqLiteConnector {
@@ -73,8 +136,8 @@ struct SqLiteConnector {
 }
 
 impl SqLiteConnector {
-    fn connect() -> SqliteConnection {
-        let database_url = crate::profile::get_db_path().into_os_string().to_string_lossy().to_string();
+    fn connect(profile_name: &str) -> SqliteConnection {
+        let database_url = crate::profile::get_db_path(profile_name).into_os_string().to_string_lossy().to_string();
 
         #[cfg(debug)]
         info!("Database Path: {}", database_url);
@@ -90,19 +153,143 @@ impl SqLiteConnector {
 }
 
 pub struct SqliteDBConnection {
-    connection: ::diesel::sqlite::SqliteConnection
+    connection: ::diesel::sqlite::SqliteConnection,
+    profile_name: String,
+    /// Blobs of deleted attachments, see remove_unused_blobs
+    unused_blobs: RefCell<Vec<String>>
 }
 
 impl SqliteDBConnection {
+    /// Connects to the database of the default profile
     pub fn new() -> SqliteDBConnection {
+        SqliteDBConnection::for_profile(crate::config::DEFAULT_PROFILE)
+    }
+
+    /// Connects to the database of the passed profile, every profile has its own database
+    pub fn for_profile(profile_name: &str) -> SqliteDBConnection {
         SqliteDBConnection {
-            connection: SqLiteConnector::connect()
+            connection: SqLiteConnector::connect(profile_name),
+            profile_name: profile_name.to_string(),
+            unused_blobs: RefCell::new(vec![])
         }
     }
 
     pub fn connection(&self) -> &::diesel::sqlite::SqliteConnection {
         &self.connection
     }
+
+    /// Rebuilds the full-text index entries of all bodies of the specified note
+    fn reindex(&self, uuid: &str) -> Result<(), Error> {
+        sql_query("DELETE FROM body_fts WHERE metadata_uuid = ?")
+            .bind::<Text, _>(uuid)
+            .execute(&self.connection)?;
+
+        sql_query("INSERT INTO body_fts(message_id, metadata_uuid, text) \
+                   SELECT message_id, metadata_uuid, text FROM body WHERE metadata_uuid = ?")
+            .bind::<Text, _>(uuid)
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+
+    /// Deletes the metadata and all bodies of the passed note
+    fn delete_note_rows(&self, local_note: &LocalNote) -> Result<(), Error> {
+        diesel::delete(schema::body::dsl::body)
+            .filter(schema::body::dsl::metadata_uuid.eq(&local_note.metadata.uuid))
+            .execute(&self.connection)?;
+
+        diesel::delete(schema::metadata::dsl::metadata)
+            .filter(schema::metadata::dsl::uuid.eq(&local_note.metadata.uuid))
+            .execute(&self.connection)?;
+
+        self.reindex(&local_note.metadata.uuid)
+    }
+
+    /// Deletes the attachments of the specified note, their blobs are
+    /// removed with the next call of remove_unused_blobs
+    fn delete_attachments(&self, uuid: &str) -> Result<(), Error> {
+        let attachments = self.fetch_attachments(uuid)?;
+
+        diesel::delete(schema::attachment::dsl::attachment)
+            .filter(schema::attachment::dsl::metadata_uuid.eq(uuid))
+            .execute(&self.connection)?;
+
+        self.unused_blobs.borrow_mut()
+            .extend(attachments.into_iter().map(|attachment| attachment.blob));
+
+        Ok(())
+    }
+
+    /// Removes the blobs of deleted attachments from the blob store of the profile, once
+    /// the outermost transaction is finished. Blobs that are still used by an attachment,
+    /// e.g. because the deletion got rolled back, are kept
+    fn remove_unused_blobs(&self) {
+        if self.connection.transaction_manager().get_transaction_depth() > 0 {
+            return;
+        }
+
+        let unused_blobs: Vec<String> = self.unused_blobs.borrow_mut().drain(..).collect();
+        for blob in unused_blobs {
+            let users: std::result::Result<i64, Error> = schema::attachment::dsl::attachment
+                .filter(schema::attachment::dsl::blob.eq(&blob))
+                .count()
+                .get_result(&self.connection);
+
+            match users {
+                Ok(0) => {
+                    debug!("Removing blob {}", blob);
+                    if let Err(e) = crate::attachment::remove_blob(&self.profile_name, &blob) {
+                        warn!("Could not remove blob {}: {}", blob, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Could not check if blob {} is still used: {}", blob, e)
+            }
+        }
+    }
+
+    /// Deletes the recorded texts of the specified note
+    fn delete_history(&self, uuid: &str) -> Result<(), Error> {
+        diesel::delete(schema::body_history::dsl::body_history)
+            .filter(schema::body_history::dsl::metadata_uuid.eq(uuid))
+            .execute(&self.connection)?;
+        Ok(())
+    }
+
+    /// Records the texts of all bodies of the specified note that are not
+    /// part of the new bodies anymore
+    fn record_history(&self, uuid: &str, new_bodies: &Vec<Body>, source: &str) -> Result<(), Error> {
+        let old_bodies: Vec<Body> = body
+            .filter(metadata_uuid.eq(uuid))
+            .load::<Body>(&self.connection)?;
+
+        let date = Utc::now().to_rfc2822();
+
+        for old_body in &old_bodies {
+            let old_text = match &old_body.text {
+                Some(old_text) => old_text,
+                None => continue
+            };
+
+            if new_bodies.iter().any(|new_body| new_body.text.as_ref() == Some(old_text)) {
+                continue;
+            }
+
+            debug!("Recording previous text of {} ({})", old_body.message_id, source);
+
+            diesel::insert_into(schema::body_history::table)
+                .values(&NewHistoryEntry {
+                    metadata_uuid: uuid,
+                    message_id: &old_body.message_id,
+                    text: old_text,
+                    date: date.clone(),
+                    source
+                })
+                .execute(&self.connection)?;
+        }
+
+        Ok(())
+    }
 }
 
 
@@ -116,6 +303,23 @@ impl DatabaseService for SqliteDBConnection {
             diesel::delete(schema::metadata::dsl::metadata)
                 .execute(&self.connection)?;
 
+            sql_query("DELETE FROM body_fts")
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::remote_header::dsl::remote_header)
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::folder_sync_state::dsl::folder_sync_state)
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::attachment::dsl::attachment)
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::sync_journal::dsl::sync_journal)
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::body_history::dsl::body_history)
+                .execute(&self.connection)?;
 
             Ok(())
         })
@@ -127,12 +331,15 @@ impl DatabaseService for SqliteDBConnection {
                 .values(model)
                 .execute(&self.connection)?;
 
+            self.reindex(&model.metadata_uuid)?;
+
             Ok(())
         })
     }
 
     fn update_merged_note(&self, note_body: &Body) -> Result<(), Error> {
         self.connection.transaction::<_,Error,_>(|| {
+            self.record_history(&note_body.metadata_uuid, &vec![note_body.clone()], history::MERGE)?;
             diesel::delete(schema::body::dsl::body
                 .filter(metadata_uuid.eq(note_body.metadata_uuid.clone()))
             )
@@ -143,22 +350,18 @@ impl DatabaseService for SqliteDBConnection {
     }
 
     fn delete(&self, local_note: &LocalNote) -> Result<(), Error> {
-        self.connection.transaction::<_, Error, _>(|| {
-
-            diesel::delete(schema::body::dsl::body)
-                .filter(schema::body::dsl::metadata_uuid.eq(&local_note.metadata.uuid))
-                .execute(&self.connection)?;
-
-            diesel::delete(schema::metadata::dsl::metadata)
-                .filter(schema::metadata::dsl::uuid.eq(&local_note.metadata.uuid))
-                .execute(&self.connection)?;
-
+        let result = self.connection.transaction::<_, Error, _>(|| {
+            self.delete_note_rows(local_note)?;
+            self.delete_attachments(&local_note.metadata.uuid)?;
+            self.delete_history(&local_note.metadata.uuid)?;
             Ok(())
-        })
+        });
+        self.remove_unused_blobs();
+        result
     }
 
     fn delete_note_body(&self, note_body: &Body) -> Result<(), Error> {
-        self.connection.transaction::<_, Error, _>(|| {
+        let result = self.connection.transaction::<_, Error, _>(|| {
 
             diesel::delete(schema::body::dsl::body)
                 .filter(schema::body::dsl::message_id.eq(&note_body.message_id))
@@ -166,30 +369,43 @@ impl DatabaseService for SqliteDBConnection {
 
             let uuid = note_body.metadata_uuid.clone();
 
+            self.reindex(&uuid)?;
+
             // if parent localnote object has no childs any more delete it
             if self.is_widow(&uuid)? {
                 self.delete_metadata(&uuid)?;
+                self.delete_attachments(&uuid)?;
             }
 
             Ok(())
-        })
+        });
+        self.remove_unused_blobs();
+        result
     }
 
     fn delete_note_bodies<'a>(&self, note_bodies: &Vec<&'a Body>) -> Result<(), Error> {
-        self.connection.transaction::<_, Error, _>(|| {
+        let result = self.connection.transaction::<_, Error, _>(|| {
 
             for b in note_bodies {
                 self.delete_note_body(b)?;
             }
 
             Ok(())
-        })
+        });
+        self.remove_unused_blobs();
+        result
     }
 
     fn update(&self, local_note: &LocalNote) -> Result<(), Error> {
+        self.update_with_source(local_note, history::LOCAL_EDIT)
+    }
+
+    fn update_with_source(&self, local_note: &LocalNote, source: &str) -> Result<(), Error> {
         self.connection.transaction::<_, Error, _>(|| {
+            self.record_history(&local_note.metadata.uuid, &local_note.body, source)?;
             //TODO replace with upsert with diesel 2.0
-            self.delete( local_note)?;
+            // Attachments are kept, the updated note still references them
+            self.delete_note_rows(local_note)?;
             self.insert_into_db(local_note)?;
             Ok(())
         })
@@ -207,6 +423,17 @@ impl DatabaseService for SqliteDBConnection {
                     .execute(&self.connection)?;
             }
 
+            self.reindex(&note.metadata.uuid)?;
+
+            Ok(())
+        })
+    }
+
+    fn insert_notes(&self, notes: &Vec<LocalNote>) -> Result<(), Error> {
+        self.connection.transaction::<_, Error, _>(|| {
+            for note in notes {
+                self.insert_into_db(note)?;
+            }
             Ok(())
         })
     }
@@ -311,21 +538,222 @@ impl DatabaseService for SqliteDBConnection {
                 .filter(schema::metadata::dsl::uuid.eq(uuid))
                 .execute(&self.connection)?;
 
+            self.delete_history(uuid)?;
+
             Ok(())
         })
     }
 
-    fn replace_notes(&self, notes: &Vec<Body>, uuid: String) -> Result<(), Error> {
+    fn replace_notes(&self, notes: &Vec<Body>, uuid: String, source: &str) -> Result<(), Error> {
         self.connection.transaction::<_, Error, _>(|| {
+            self.record_history(&uuid, notes, source)?;
 
             diesel::delete(schema::body::dsl::body)
-                .filter(schema::body::dsl::metadata_uuid.eq(uuid))
+                .filter(schema::body::dsl::metadata_uuid.eq(&uuid))
                 .execute(&self.connection)?;
 
             for note in notes {
                 self.append_note(note)?;
             }
 
+            self.reindex(&uuid)?;
+
+            Ok(())
+        })
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, Error> {
+        let match_query = fts_query(query);
+
+        if match_query.is_empty() {
+            return Ok(vec![])
+        }
+
+        let rows: Vec<SearchRow> = sql_query(
+            "SELECT metadata_uuid AS note_uuid, snippet(body_fts, 2, '[', ']', '...', 8) AS snippet \
+             FROM body_fts WHERE body_fts MATCH ? ORDER BY bm25(body_fts)")
+            .bind::<Text, _>(&match_query)
+            .load(&self.connection)?;
+
+        let mut seen = HashSet::new();
+        let mut results = vec![];
+
+        for row in rows {
+            if seen.insert(row.note_uuid.clone()) == false {
+                continue;
+            }
+
+            if let Some(note) = self.fetch_single_note(&row.note_uuid)? {
+                results.push(SearchResult {
+                    note,
+                    snippet: row.snippet.replace('\n', " ")
+                });
+            }
+        }
+
+        debug!("Search for {} returned {} notes", match_query, results.len());
+
+        Ok(results)
+    }
+
+    fn fetch_folder_sync_state(&self, folder: &str) -> Result<Option<FolderSyncState>, Error> {
+        schema::folder_sync_state::dsl::folder_sync_state
+            .filter(schema::folder_sync_state::dsl::folder.eq(folder))
+            .first::<FolderSyncState>(&self.connection)
+            .optional()
+    }
+
+    fn fetch_cached_headers(&self, folder: &str) -> Result<Vec<RemoteNoteMetaData>, Error> {
+        let cached: Vec<CachedHeader> = schema::remote_header::dsl::remote_header
+            .filter(schema::remote_header::dsl::folder.eq(folder))
+            .order(schema::remote_header::dsl::uid.asc())
+            .load::<CachedHeader>(&self.connection)?;
+
+        cached.into_iter().map(|header| {
+            serde_json::from_str(&header.headers)
+                .map(|headers| RemoteNoteMetaData {
+                    headers,
+                    folder: header.folder,
+                    uid: header.uid
+                })
+                .map_err(|e| Error::DeserializationError(Box::new(e)))
+        }).collect()
+    }
+
+    fn delete_folder_sync_state(&self, folder: &str) -> Result<(), Error> {
+        self.connection.transaction::<_, Error, _>(|| {
+            let subfolders = format!("{}.%", folder);
+
+            diesel::delete(schema::remote_header::dsl::remote_header)
+                .filter(schema::remote_header::dsl::folder.eq(folder)
+                    .or(schema::remote_header::dsl::folder.like(&subfolders)))
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::folder_sync_state::dsl::folder_sync_state)
+                .filter(schema::folder_sync_state::dsl::folder.eq(folder)
+                    .or(schema::folder_sync_state::dsl::folder.like(&subfolders)))
+                .execute(&self.connection)?;
+
+            Ok(())
+        })
+    }
+
+    fn rename_folder(&self, old: &str, new: &str) -> Result<(), Error> {
+        let rename = |folder: &str| format!("{}{}", new, &folder[old.len()..]);
+
+        self.connection.transaction::<_, Error, _>(|| {
+            let notes: Vec<NotesMetadata> = metadata.load::<NotesMetadata>(&self.connection)?;
+
+            for note in notes {
+                let subfolder_name = if is_inside_folder(&note.subfolder, old) {
+                    rename(&note.subfolder)
+                } else {
+                    note.subfolder.clone()
+                };
+
+                let moved_from = match note.moved_from.as_ref() {
+                    Some(folder) if is_inside_folder(folder, old) => Some(rename(folder)),
+                    _ => note.moved_from.clone()
+                };
+
+                if subfolder_name != note.subfolder || moved_from != note.moved_from {
+                    debug!("Moving {} from {} to {}", note.uuid, note.subfolder, subfolder_name);
+                    diesel::update(metadata.filter(schema::metadata::dsl::uuid.eq(&note.uuid)))
+                        .set((
+                            schema::metadata::dsl::subfolder.eq(subfolder_name),
+                            schema::metadata::dsl::moved_from.eq(moved_from)
+                        ))
+                        .execute(&self.connection)?;
+                }
+            }
+
+            self.delete_folder_sync_state(old)?;
+
+            Ok(())
+        })
+    }
+
+    fn profile_name(&self) -> &str {
+        &self.profile_name
+    }
+
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), Error> {
+        diesel::replace_into(schema::attachment::table)
+            .values(attachment)
+            .execute(&self.connection)
+            .map(|_| ())
+    }
+
+    fn fetch_attachments(&self, uuid: &str) -> Result<Vec<Attachment>, Error> {
+        schema::attachment::dsl::attachment
+            .filter(schema::attachment::dsl::metadata_uuid.eq(uuid))
+            .load::<Attachment>(&self.connection)
+    }
+
+    fn insert_journal_entry(&self, entry: &JournalEntry) -> Result<(), Error> {
+        diesel::replace_into(schema::sync_journal::table)
+            .values(entry)
+            .execute(&self.connection)
+            .map(|_| ())
+    }
+
+    fn update_journal_phase(&self, uuid: &str, phase: &str, uid: Option<i64>) -> Result<(), Error> {
+        diesel::update(schema::sync_journal::dsl::sync_journal.filter(schema::sync_journal::dsl::uuid.eq(uuid)))
+            .set((
+                schema::sync_journal::dsl::phase.eq(phase),
+                schema::sync_journal::dsl::uid.eq(uid)
+            ))
+            .execute(&self.connection)
+            .map(|_| ())
+    }
+
+    fn delete_journal_entry(&self, uuid: &str) -> Result<(), Error> {
+        diesel::delete(schema::sync_journal::dsl::sync_journal)
+            .filter(schema::sync_journal::dsl::uuid.eq(uuid))
+            .execute(&self.connection)
+            .map(|_| ())
+    }
+
+    fn fetch_journal(&self) -> Result<Vec<JournalEntry>, Error> {
+        schema::sync_journal::dsl::sync_journal
+            .load::<JournalEntry>(&self.connection)
+    }
+
+    fn fetch_history(&self, uuid: &str) -> Result<Vec<HistoryEntry>, Error> {
+        schema::body_history::dsl::body_history
+            .filter(schema::body_history::dsl::metadata_uuid.eq(uuid))
+            .order(schema::body_history::dsl::id.asc())
+            .load::<HistoryEntry>(&self.connection)
+    }
+
+    fn update_folder_sync_state(&self, state: &FolderSyncState, headers: &Vec<RemoteNoteMetaData>) -> Result<(), Error> {
+        self.connection.transaction::<_, Error, _>(|| {
+
+            diesel::delete(schema::remote_header::dsl::remote_header)
+                .filter(schema::remote_header::dsl::folder.eq(&state.folder))
+                .execute(&self.connection)?;
+
+            diesel::delete(schema::folder_sync_state::dsl::folder_sync_state)
+                .filter(schema::folder_sync_state::dsl::folder.eq(&state.folder))
+                .execute(&self.connection)?;
+
+            diesel::insert_into(schema::folder_sync_state::table)
+                .values(state)
+                .execute(&self.connection)?;
+
+            for header in headers {
+                let cached = CachedHeader {
+                    folder: header.folder.clone(),
+                    uid: header.uid,
+                    headers: serde_json::to_string(&header.headers)
+                        .map_err(|e| Error::SerializationError(Box::new(e)))?
+                };
+
+                diesel::insert_into(schema::remote_header::table)
+                    .values(&cached)
+                    .execute(&self.connection)?;
+            }
+
             Ok(())
         })
     }
@@ -492,6 +920,25 @@ mod db_tests {
         assert_eq!(a.len(),0);
     }
 
+    /// Checks that no note gets inserted if one of them fails
+    #[test]
+    pub fn insert_notes_reverts_all() {
+        let first = note![
+            NotesMetadataBuilder::new().build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+        let second = note![
+            NotesMetadataBuilder::new().build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().unwrap();
+
+        assert_eq!(con.insert_notes(&vec![first, second]).is_err(), true);
+        assert_eq!(con.fetch_all_notes().unwrap().len(), 0);
+    }
+
 
     /// Checks if all notes are getting fetched properly
     #[test]
@@ -880,4 +1327,170 @@ mod db_tests {
         assert_eq!(notes.iter().next().unwrap().metadata.uuid,"1".to_string());
 
     }
-}
\ No newline at end of file
+
+    /// Searches should return ranked notes and follow updates and deletions
+    #[test]
+    fn search_notes() {
+
+        dotenv::dotenv().ok();
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().expect("Should delete everything");
+
+        let groceries = note![
+                NotesMetadataBuilder::new().with_uuid("1").build(),
+                BodyMetadataBuilder::new().with_message_id("1").with_text("Groceries\napples\napples and pears").build()
+        ];
+
+        let recipes = note![
+                NotesMetadataBuilder::new().with_uuid("2").build(),
+                BodyMetadataBuilder::new().with_message_id("2").with_text("Recipes\napple pie with cinnamon").build()
+        ];
+
+        con.insert_into_db(&groceries).unwrap();
+        con.insert_into_db(&recipes).unwrap();
+
+        let results = con.search("appl").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].note.metadata.uuid, "1".to_string());
+        assert!(results[0].snippet.contains("[apples]"));
+
+        let results = con.search("cinnamon \"pie").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].note.metadata.uuid, "2".to_string());
+
+        let mut edited = recipes.clone();
+        edited.body[0].text = Some("Recipes\nbanana bread".to_string());
+        con.update(&edited).unwrap();
+
+        assert_eq!(con.search("cinnamon").unwrap().len(), 0);
+        assert_eq!(con.search("banana").unwrap().len(), 1);
+
+        con.delete(&groceries).unwrap();
+        assert_eq!(con.search("pears").unwrap().len(), 0);
+        assert_eq!(con.search("   ").unwrap().len(), 0);
+    }
+
+    /// Replaced texts are recorded with their source, unchanged texts are not
+    #[test]
+    fn record_history() {
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().expect("Should delete everything");
+
+        let note = note![
+                NotesMetadataBuilder::new().with_uuid("history").build(),
+                BodyMetadataBuilder::new().with_message_id("history-1").with_text("Note\nfirst version").build()
+        ];
+        con.insert_into_db(&note).unwrap();
+
+        // Only the metadata changed
+        let mut moved = note.clone();
+        moved.metadata.subfolder = "Notes.Archive".to_string();
+        con.update(&moved).unwrap();
+        assert!(con.fetch_history("history").unwrap().is_empty());
+
+        let mut edited = moved.clone();
+        edited.body[0].text = Some("Note\nsecond version".to_string());
+        con.update(&edited).unwrap();
+
+        let remote = BodyMetadataBuilder::new()
+            .with_message_id("history-2")
+            .with_metadata_uuid("history")
+            .with_text("Note\nthird version")
+            .build();
+        con.replace_notes(&vec![remote], "history".to_string(), crate::history::REMOTE_UPDATE).unwrap();
+
+        let history = con.fetch_history("history").unwrap();
+        assert_eq!(history.iter().map(|entry| entry.text.as_str()).collect::<Vec<&str>>(),
+                   vec!["Note\nfirst version", "Note\nsecond version"]);
+        assert_eq!(history.iter().map(|entry| entry.source.as_str()).collect::<Vec<&str>>(),
+                   vec![crate::history::LOCAL_EDIT, crate::history::REMOTE_UPDATE]);
+        assert_eq!(history[0].message_id, "history-1");
+    }
+
+    /// Content ids are only unique within a note, two notes can use the same one
+    #[test]
+    fn same_content_id_in_two_notes() {
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().expect("Should delete everything");
+
+        for (uuid, blob) in vec![("content-id-1", "blob-1"), ("content-id-2", "blob-2"), ("content-id-1", "blob-3")] {
+            con.insert_attachment(&Attachment {
+                content_id: "image@apnotes".to_string(),
+                metadata_uuid: uuid.to_string(),
+                file_name: "image.png".to_string(),
+                mime_type: "image/png".to_string(),
+                blob: blob.to_string()
+            }).unwrap();
+        }
+
+        // The second insert for the first note replaces its attachment
+        assert_eq!(con.fetch_attachments("content-id-1").unwrap()[0].blob, "blob-3");
+        assert_eq!(con.fetch_attachments("content-id-1").unwrap().len(), 1);
+        assert_eq!(con.fetch_attachments("content-id-2").unwrap()[0].blob, "blob-2");
+    }
+
+    /// The history of a note is deleted together with the note
+    #[test]
+    fn delete_history_with_note() {
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().expect("Should delete everything");
+
+        for uuid in vec!["history-deleted", "history-widow"] {
+            let note = note![
+                NotesMetadataBuilder::new().with_uuid(uuid).build(),
+                BodyMetadataBuilder::new().with_message_id(&format!("{}-1", uuid)).with_text("Note\nfirst version").build()
+            ];
+            con.insert_into_db(&note).unwrap();
+
+            let mut edited = note.clone();
+            edited.body[0].text = Some("Note\nsecond version".to_string());
+            con.update(&edited).unwrap();
+            assert_eq!(con.fetch_history(uuid).unwrap().len(), 1);
+        }
+
+        let deleted = con.fetch_single_note("history-deleted").unwrap().unwrap();
+        con.delete(&deleted).unwrap();
+        assert!(con.fetch_history("history-deleted").unwrap().is_empty());
+
+        // Deleting the last body removes the note as well
+        let widow = con.fetch_single_note("history-widow").unwrap().unwrap();
+        con.delete_note_body(&widow.body[0]).unwrap();
+        assert!(con.fetch_history("history-widow").unwrap().is_empty());
+    }
+
+    /// Attachments of deleted notes get removed, shared blobs stay until the last user is gone
+    #[test]
+    fn delete_attachments_with_note() {
+        let con = crate::db::SqliteDBConnection::new();
+        con.delete_everything().expect("Should delete everything");
+
+        let blob_path = crate::profile::get_blob_dir(con.profile_name()).unwrap().join("delete-attachments-blob");
+        std::fs::write(&blob_path, [1, 2, 3]).unwrap();
+
+        let first = note![NotesMetadataBuilder::new().with_uuid("attachments-1").build(), BodyMetadataBuilder::new().build()];
+        let second = note![NotesMetadataBuilder::new().with_uuid("attachments-2").build(), BodyMetadataBuilder::new().build()];
+
+        for (note, content_id) in vec![(&first, "first@apnotes"), (&second, "second@apnotes")] {
+            con.insert_into_db(note).unwrap();
+            con.insert_attachment(&Attachment {
+                content_id: content_id.to_string(),
+                metadata_uuid: note.metadata.uuid.clone(),
+                file_name: "scan.pdf".to_string(),
+                mime_type: "application/pdf".to_string(),
+                blob: "delete-attachments-blob".to_string()
+            }).unwrap();
+        }
+
+        // Updating keeps the attachments
+        con.update(&first).unwrap();
+        assert_eq!(con.fetch_attachments("attachments-1").unwrap().len(), 1);
+
+        con.delete(&first).unwrap();
+        assert!(con.fetch_attachments("attachments-1").unwrap().is_empty());
+        assert!(blob_path.exists());
+
+        con.delete(&second).unwrap();
+        assert!(con.fetch_attachments("attachments-2").unwrap().is_empty());
+        assert!(blob_path.exists() == false);
+    }
+}
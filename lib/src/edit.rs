@@ -73,7 +73,7 @@ fn remove_temp_file(file_path: &String) -> Result<(), NoteError> {
         .map_err(|e| NoteError::EditError(e.to_string()))
 }
 
-fn read_edited_text(local_note: &LocalNote, note: &Body, file_path: &str, _profile: &Profile) -> Result<LocalNote, NoteError> {
+fn read_edited_text(local_note: &LocalNote, note: &Body, file_path: &str, profile: &Profile) -> Result<LocalNote, NoteError> {
     //Read content and save to body.text
     let file_content = std::fs::read_to_string(&file_path)
         .map_err(|e| NoteError::EditError(e.to_string()))?;
@@ -81,41 +81,56 @@ fn read_edited_text(local_note: &LocalNote, note: &Body, file_path: &str, _profi
     if &file_content == note.text.as_ref().unwrap_or(&"".to_string())
         && local_note.metadata.new == false {
         return Err(ContentNotChanged);
-    } else {
-        // Create new edited date that matches current date
-        let local_note_metadata = NotesMetadata {
-            subfolder: local_note.metadata.subfolder.clone(),
-            locally_deleted: local_note.metadata.locally_deleted,
-            new: local_note.metadata.new,
-            edited: true,
-            date: Utc::now().to_rfc2822(),
-            uuid: local_note.metadata.uuid.clone(),
-            mime_version: local_note.metadata.mime_version.clone()
-        };
+    }
 
-        #[cfg(not(test))]
-        let mut body = BodyMetadataBuilder::new(_profile)
-            .with_uid(note.uid.clone())
-            .with_text(&file_content);
+    Ok(edited_note(local_note, note, &file_content, profile))
+}
 
-        #[cfg(test)]
-            let mut body = BodyMetadataBuilder::new()
-            .with_uid(note.uid.clone())
-            .with_text(&file_content);
+/// Replaces the text of the passed body and flags the note as edited, so that
+/// it gets uploaded with the next sync
+pub(crate) fn edited_note(local_note: &LocalNote, note: &Body, text: &str, _profile: &Profile) -> LocalNote {
+    // Create new edited date that matches current date
+    let local_note_metadata = NotesMetadata {
+        subfolder: local_note.metadata.subfolder.clone(),
+        locally_deleted: local_note.metadata.locally_deleted,
+        new: local_note.metadata.new,
+        edited: true,
+        date: Utc::now().to_rfc2822(),
+        uuid: local_note.metadata.uuid.clone(),
+        mime_version: local_note.metadata.mime_version.clone(),
+        moved_from: local_note.metadata.moved_from.clone()
+    };
 
-        if local_note.metadata.new == false {
-            body = body.with_old_remote_message_id(&note.message_id);
-        }
+    // Remember the last synchronized text, so that it can be used as
+    // common ancestor if the note also changes remotely
+    let base_text = note.base_text.clone().or_else(|| {
+        if local_note.metadata.new { None } else { note.text.clone() }
+    });
+
+    #[cfg(not(test))]
+    let mut body = BodyMetadataBuilder::new(_profile)
+        .with_uid(note.uid.clone())
+        .with_text(text)
+        .with_base_text(base_text)
+        .with_html(note.html.clone());
+
+    #[cfg(test)]
+        let mut body = BodyMetadataBuilder::new()
+        .with_uid(note.uid.clone())
+        .with_text(text)
+        .with_base_text(base_text)
+        .with_html(note.html.clone());
+
+    if local_note.metadata.new == false {
+        body = body.with_old_remote_message_id(&note.message_id);
+    }
 
-        let body = body.build();
+    let body = body.build();
 
-        Ok(
-            note!(
-                  local_note_metadata,
-                  body
-            )
-        )
-    }
+    note!(
+          local_note_metadata,
+          body
+    )
 }
 
 #[cfg(test)]
@@ -149,16 +164,25 @@ mod edit_tests {
     );
 
         let profile = Profile {
+            name: "default".to_string(),
             username: "".to_string(),
             password: Option::from("".to_string()),
             imap_server: "".to_string(),
+            port: 993,
+            security: crate::config::Security::Tls,
+            ca_file: None,
             email: "".to_string(),
             editor: "".to_string(),
             editor_arguments: vec![],
             secret_service_attribute: None,
             secret_service_value: None,
+            password_command: None,
+            password_file: None,
+            oauth2_token_url: None,
+            oauth2_client_id: None,
+            oauth2_client_secret: None,
             domain: "".to_string(),
-            password_type: "".to_string()
+            password_type: crate::config::PasswordType::Plain
         };
 
         match edit_note(&note, false, &profile) {
@@ -16,7 +16,18 @@ pub enum ProfileError {
     AgentLocked(),
     NoAttributeProvided(),
     NoValueProvided(),
-    NoEntryFound()
+    NoEntryFound(),
+    ProfileNotFound(String),
+    InvalidConfig(String),
+    /// The token endpoint refused to issue an access token
+    OAuth2Error(String),
+    /// The password command could not be started or exited with an error
+    PasswordCommandFailed(String),
+    /// The password command or password file did not contain a password
+    EmptyPassword(),
+    PasswordFileNotFound(String),
+    /// gpg could not be started or could not decrypt the password file
+    DecryptionFailed(String)
 }
 
 #[derive(Debug,PartialEq)]
@@ -31,7 +42,10 @@ pub enum NoteError {
     EditError(String),
     NeedsMerge,
     ContentNotChanged,
-    NoteNotFound
+    NoteNotFound,
+    FolderNotEmpty(String),
+    InvalidFolderName(String),
+    RevisionNotFound(usize)
 }
 
 
@@ -54,6 +68,13 @@ impl ErrorCode for ProfileError {
             ProfileError::NoAttributeProvided() => { 4 }
             ProfileError::NoValueProvided() => { 5 }
             ProfileError::NoEntryFound() => { 6 }
+            ProfileError::ProfileNotFound(_) => { 7 }
+            ProfileError::InvalidConfig(_) => { 8 }
+            ProfileError::OAuth2Error(_) => { 9 }
+            ProfileError::PasswordCommandFailed(_) => { 10 }
+            ProfileError::EmptyPassword() => { 11 }
+            ProfileError::PasswordFileNotFound(_) => { 12 }
+            ProfileError::DecryptionFailed(_) => { 13 }
         }
     }
 
@@ -83,6 +104,9 @@ impl ErrorCode for NoteError {
             NoteError::NeedsMerge => { 32 }
             NoteError::ContentNotChanged => { 33 }
             NoteError::NoteNotFound => { 34 }
+            NoteError::FolderNotEmpty(_) => { 35 }
+            NoteError::InvalidFolderName(_) => { 36 }
+            NoteError::RevisionNotFound(_) => { 37 }
         }
     }
 
@@ -152,6 +176,16 @@ impl ErrorCode for imap::Error{
     }
 }
 
+impl ErrorCode for curl::Error {
+    fn error_code(&self) -> i32 {
+        return 255;
+    }
+
+    fn human_readable_error_message(&self) -> String {
+        "An error occurred while requesting an access token:".to_string()
+    }
+}
+
 #[cfg(target_family = "unix")]
 impl ErrorCode for secret_service::Error {
     fn error_code(&self) -> i32 {
@@ -225,6 +259,12 @@ impl std::convert::From<imap::Error> for  std::boxed::Box<dyn ErrorCode> {
     }
 }
 
+impl std::convert::From<curl::Error> for  std::boxed::Box<dyn ErrorCode> {
+    fn from(e: curl::Error) -> Self {
+        Box::new(e)
+    }
+}
+
 impl std::error::Error for ProfileError {}
 impl std::error::Error for NoteError {}
 impl std::error::Error for UpdateError {}
@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use itertools::Itertools;
+use crate::error::Result;
+use crate::front_matter::FrontMatter;
+use crate::merge;
+use crate::model::Body;
+use crate::notes::localnote::LocalNote;
+use crate::notes::traits::identifyable_note::IdentifiableNote;
+use crate::util::display_folder_name;
+
+/// Writes every note as markdown file into dir, the folder structure
+/// mirrors the imap folders, e.g. "Notes.todo.work" becomes dir/Notes/todo/work.
+///
+/// Files are named after the escaped subject of the note, if multiple notes
+/// share the same subject the uuid gets appended. Notes that need to be merged
+/// are exported as a single conflict document, like merge would create it, so
+/// that every uuid belongs to exactly one file and import can read it again.
+///
+/// Returns the count of written files
+pub fn export_notes(notes: &HashSet<LocalNote>, dir: &Path) -> Result<usize> {
+    let mut written: HashSet<PathBuf> = HashSet::new();
+
+    // Sorted, so that the same note keeps its file name between exports
+    for note in notes.iter().sorted_by_key(|note| (note.folder(), note.metadata.uuid.clone())) {
+        let folder = display_folder_name(&note.folder());
+        let folder_path = folder.split('/')
+            .filter(|part| part.is_empty() == false)
+            .fold(dir.join("Notes"), |path, part| path.join(part));
+
+        std::fs::create_dir_all(&folder_path)?;
+
+        let (text, message_id) = if note.needs_merge() {
+            let message_ids = note.body.iter().map(|body| body.message_id.clone()).collect::<Vec<String>>();
+            (merge::merge_note(note), message_ids.join(","))
+        } else {
+            (note.body[0].text.clone().unwrap_or_default(), note.body[0].message_id.clone())
+        };
+
+        let file_path = file_name(&folder_path, &note.body[0], &note.metadata.uuid, &written);
+
+        let front_matter = FrontMatter {
+            uuid: note.metadata.uuid.clone(),
+            date: note.metadata.date.clone(),
+            message_id,
+            folder: folder.clone()
+        };
+
+        std::fs::write(&file_path, format!("{}{}", front_matter.render(), text))?;
+        debug!("Exported {} to {}", note.metadata.uuid, file_path.display());
+        written.insert(file_path);
+    }
+
+    Ok(written.len())
+}
+
+fn file_name(folder_path: &Path, body: &Body, uuid: &str, written: &HashSet<PathBuf>) -> PathBuf {
+    let subject = body.subject_escaped();
+    let subject = if subject.is_empty() { uuid.to_string() } else { subject };
+
+    let path = folder_path.join(format!("{}.md", subject));
+    if written.contains(&path) {
+        folder_path.join(format!("{}_{}.md", subject, uuid))
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use crate::builder::{NotesMetadataBuilder, BodyMetadataBuilder};
+
+    #[test]
+    fn export_tree() {
+        let dir = std::env::temp_dir().join(format!("apnotes_export_{}", crate::util::generate_uuid()));
+
+        let notes: HashSet<LocalNote> = vec![
+            note![
+                NotesMetadataBuilder::new().with_uuid("1").build(),
+                BodyMetadataBuilder::new().with_message_id("a").with_text("Shopping\nMilk").build()
+            ],
+            note![
+                NotesMetadataBuilder::new().with_uuid("2").build(),
+                BodyMetadataBuilder::new().with_message_id("b").with_text("Shopping\nBread").build()
+            ],
+            note![
+                NotesMetadataBuilder::new().with_uuid("3").with_folder("todo/work".to_string()).build(),
+                BodyMetadataBuilder::new().with_message_id("c").with_text("Tasks\nFirst").build(),
+                BodyMetadataBuilder::new().with_message_id("d").with_text("Tasks\nSecond").build()
+            ]
+        ].into_iter().collect();
+
+        assert_eq!(export_notes(&notes, &dir).unwrap(), 3);
+
+        let shopping = std::fs::read_to_string(dir.join("Notes/Shopping.md")).unwrap();
+        assert!(shopping.starts_with("---\nuuid: \"1\"\n"));
+        assert!(shopping.ends_with("---\nShopping\nMilk"));
+        assert!(dir.join("Notes/Shopping_2.md").exists());
+
+        // Both bodies end up in one conflict document
+        let tasks = std::fs::read_to_string(dir.join("Notes/todo/work/Tasks.md")).unwrap();
+        assert!(tasks.contains("message_id: \"c,d\"\nfolder: \"todo/work\"\n"));
+        assert!(tasks.ends_with("---\nTasks\n<<<<<<< c\nFirst\n======= d\nSecond\n>>>>>>>\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
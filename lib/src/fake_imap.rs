@@ -0,0 +1,590 @@
+//! In-process imap server for tests.
+//!
+//! Supports the subset of imap that the notes synchronization uses: LOGIN, CAPABILITY,
+//! LIST, STATUS, SELECT, (UID) FETCH, APPEND, UID STORE, EXPUNGE, UID SEARCH, UID COPY,
+//! CREATE, RENAME, DELETE, IDLE and LOGOUT. Like UIDPLUS servers APPEND responds with the
+//! uid of the message. Clients that connected over tcp get new messages of the folder
+//! they are idling on reported with EXISTS. Connections are unencrypted, the server only
+//! listens on the loopback interface.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::config::{PasswordType, Security};
+use crate::profile::Profile;
+
+pub const USERNAME: &str = "test@test.de";
+pub const PASSWORD: &str = "test";
+
+struct Message {
+    uid: u32,
+    deleted: bool,
+    content: Vec<u8>
+}
+
+struct Folder {
+    uid_validity: u32,
+    uid_next: u32,
+    messages: Vec<Message>
+}
+
+impl Folder {
+    fn new(uid_validity: u32) -> Folder {
+        Folder {
+            uid_validity,
+            uid_next: 1,
+            messages: vec![]
+        }
+    }
+
+    fn append(&mut self, content: Vec<u8>) -> u32 {
+        let uid = self.uid_next;
+        self.uid_next += 1;
+        self.messages.push(Message { uid, deleted: false, content });
+        uid
+    }
+}
+
+type Folders = Arc<Mutex<BTreeMap<String, Folder>>>;
+/// Connections that are idling, with their id, the selected folder and a writer to the client
+type Idlers = Arc<Mutex<Vec<(usize, String, Box<dyn Write + Send>)>>>;
+
+/// Reports the new message count of folder to every client idling on it
+fn notify_idlers(idlers: &Idlers, folder: &str, messages: usize) {
+    for (_, idle_folder, writer) in idlers.lock().unwrap().iter_mut() {
+        if idle_folder == folder {
+            let _ = writer.write_all(format!("* {} EXISTS\r\n", messages).as_bytes());
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// Id of the next connection
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub struct FakeImapServer {
+    port: u16,
+    folders: Folders,
+    uidplus: Arc<AtomicBool>,
+    disconnect: Arc<AtomicBool>,
+    idlers: Idlers
+}
+
+impl FakeImapServer {
+
+    /// Starts the server on a random port, the server contains an empty "Notes" folder
+    pub fn start() -> FakeImapServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut folders = BTreeMap::new();
+        folders.insert("Notes".to_string(), Folder::new(1));
+        let folders = Arc::new(Mutex::new(folders));
+        let uidplus = Arc::new(AtomicBool::new(true));
+        let disconnect = Arc::new(AtomicBool::new(false));
+        let idlers: Idlers = Arc::new(Mutex::new(vec![]));
+
+        let server_folders = folders.clone();
+        let server_uidplus = uidplus.clone();
+        let server_disconnect = disconnect.clone();
+        let server_idlers = idlers.clone();
+        std::thread::spawn(move || {
+            // Every client gets its own thread, watch keeps an idling connection per note folder
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let reader = stream.try_clone().unwrap();
+                    let idle_writer: Box<dyn Write + Send> = Box::new(stream.try_clone().unwrap());
+                    let mut connection = Connection::new(reader, stream, server_folders.clone(), server_uidplus.clone(), server_disconnect.clone(), server_idlers.clone());
+                    connection.idle_writer = Some(idle_writer);
+                    std::thread::spawn(move || {
+                        let _ = connection.handle();
+                    });
+                }
+            }
+        });
+
+        FakeImapServer {
+            port,
+            folders,
+            uidplus,
+            disconnect,
+            idlers
+        }
+    }
+
+    /// Behave like a server without UIDPLUS, APPEND does not return the uid of the message anymore
+    pub fn disable_uidplus(&self) {
+        self.uidplus.store(false, Ordering::SeqCst);
+    }
+
+    /// Closes the connection of the current client after its next command, without responding to it
+    pub fn disconnect(&self) {
+        self.disconnect.store(true, Ordering::SeqCst);
+    }
+
+    /// Profile that connects to this server, every profile name gets its own database
+    pub fn profile(&self, name: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            username: USERNAME.to_string(),
+            password_type: PasswordType::Plain,
+            imap_server: "127.0.0.1".to_string(),
+            port: self.port,
+            security: Security::Plaintext,
+            ca_file: None,
+            email: USERNAME.to_string(),
+            editor: "vim".to_string(),
+            editor_arguments: vec![],
+            secret_service_attribute: None,
+            secret_service_value: None,
+            password_command: None,
+            password_file: None,
+            oauth2_token_url: None,
+            oauth2_client_id: None,
+            oauth2_client_secret: None,
+            domain: "test.de".to_string(),
+            password: Some(PASSWORD.to_string())
+        }
+    }
+
+    /// Serves a single client over an arbitrary connection, e.g. one end of a socket pair.
+    /// reader and writer are usually clones of the same stream. IDLE of such a client never
+    /// reports changes, it only waits for the client to end it
+    pub fn serve<S: Read + Write + Send + 'static>(&self, reader: S, writer: S) {
+        let folders = self.folders.clone();
+        let uidplus = self.uidplus.clone();
+        let disconnect = self.disconnect.clone();
+        let idlers = self.idlers.clone();
+        std::thread::spawn(move || {
+            let _ = Connection::new(reader, writer, folders, uidplus, disconnect, idlers).handle();
+        });
+    }
+
+    pub fn create_folder(&self, folder: &str) {
+        let mut folders = self.folders.lock().unwrap();
+        let uid_validity = folders.len() as u32 + 1;
+        folders.entry(folder.to_string()).or_insert(Folder::new(uid_validity));
+    }
+
+    /// Adds a message like another client would do, returns its uid
+    pub fn append(&self, folder: &str, message: &str) -> u32 {
+        let (uid, messages) = {
+            let mut folders = self.folders.lock().unwrap();
+            let folder = folders.get_mut(folder).expect("folder does not exist");
+            (folder.append(message.as_bytes().to_vec()), folder.messages.len())
+        };
+        notify_idlers(&self.idlers, folder, messages);
+        uid
+    }
+
+    /// Removes a message like another client would do
+    pub fn expunge(&self, folder: &str, uid: u32) {
+        self.folders.lock().unwrap()
+            .get_mut(folder)
+            .expect("folder does not exist")
+            .messages
+            .retain(|message| message.uid != uid);
+    }
+
+    /// Returns uid and content of all messages inside folder
+    pub fn messages(&self, folder: &str) -> Vec<(u32, String)> {
+        self.folders.lock().unwrap()
+            .get(folder)
+            .map(|folder| folder.messages.iter()
+                .map(|message| (message.uid, String::from_utf8_lossy(&message.content).to_string()))
+                .collect())
+            .unwrap_or_default()
+    }
+}
+
+struct Connection<S: Read + Write> {
+    reader: BufReader<S>,
+    stream: S,
+    folders: Folders,
+    uidplus: Arc<AtomicBool>,
+    disconnect: Arc<AtomicBool>,
+    idlers: Idlers,
+    id: usize,
+    /// Second writer to the client, so that other threads can report changes while it idles
+    idle_writer: Option<Box<dyn Write + Send>>,
+    selected: Option<String>,
+    /// Response code of the tagged response of the running command
+    response_code: Option<String>
+}
+
+impl<S: Read + Write> Connection<S> {
+
+    fn new(reader: S, stream: S, folders: Folders, uidplus: Arc<AtomicBool>, disconnect: Arc<AtomicBool>, idlers: Idlers) -> Connection<S> {
+        Connection {
+            reader: BufReader::new(reader),
+            stream,
+            folders,
+            uidplus,
+            disconnect,
+            idlers,
+            id: NEXT_CONNECTION.fetch_add(1, Ordering::SeqCst),
+            idle_writer: None,
+            selected: None,
+            response_code: None
+        }
+    }
+
+    fn handle(&mut self) -> std::io::Result<()> {
+        self.send("* OK [CAPABILITY IMAP4rev1 IDLE] Fake server ready")?;
+
+        while let Some((line, literal)) = self.read_command()? {
+            let (tag, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+            let (command, arguments) = rest.split_once(' ').unwrap_or((rest, ""));
+            let command = command.to_uppercase();
+
+            let (command, arguments) = if command == "UID" {
+                let (command, arguments) = arguments.split_once(' ').unwrap_or((arguments, ""));
+                (format!("UID {}", command.to_uppercase()), arguments)
+            } else {
+                (command, arguments)
+            };
+
+            let response = self.execute(&command, &tokenize(arguments), literal);
+
+            if self.disconnect.swap(false, Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            match response {
+                Ok(untagged) => {
+                    for line in untagged {
+                        self.stream.write_all(&line)?;
+                    }
+                    match self.response_code.take() {
+                        Some(code) => self.send(&format!("{} OK [{}] {} completed", tag, code, command))?,
+                        None => self.send(&format!("{} OK {} completed", tag, command))?
+                    }
+                },
+                Err(message) => self.send(&format!("{} NO {}", tag, message))?
+            }
+
+            if command == "LOGOUT" {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, line: &str) -> std::io::Result<()> {
+        self.stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Reads a command line, a literal at the end of the line gets read as well
+    fn read_command(&mut self) -> std::io::Result<Option<(String, Option<Vec<u8>>)>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end().to_string();
+
+        let literal_length = line.strip_suffix('}')
+            .and_then(|line| line.rsplit_once('{'))
+            .and_then(|(_, length)| length.parse::<usize>().ok());
+
+        match literal_length {
+            Some(length) => {
+                self.send("+ Ready for literal data")?;
+                let mut literal = vec![0; length];
+                self.reader.read_exact(&mut literal)?;
+                // Rest of the command line after the literal
+                let mut rest = String::new();
+                self.reader.read_line(&mut rest)?;
+                let line = line[..line.rfind('{').unwrap()].trim_end().to_string();
+                Ok(Some((line, Some(literal))))
+            },
+            None => Ok(Some((line, None)))
+        }
+    }
+
+    fn execute(&mut self, command: &str, arguments: &Vec<String>, literal: Option<Vec<u8>>) -> Result<Vec<Vec<u8>>, String> {
+        let argument = |index: usize| arguments.get(index).cloned().ok_or("missing argument".to_string());
+        let rest = arguments.get(1..).unwrap_or(&[]).join(" ");
+
+        match command {
+            "LOGIN" if arguments.get(0..2) == Some(&[USERNAME.to_string(), PASSWORD.to_string()]) => Ok(vec![]),
+            "LOGIN" => Err("[AUTHENTICATIONFAILED] invalid credentials".to_string()),
+            "CAPABILITY" if self.uidplus.load(Ordering::SeqCst) => Ok(vec![b"* CAPABILITY IMAP4rev1 IDLE UIDPLUS\r\n".to_vec()]),
+            "CAPABILITY" => Ok(vec![b"* CAPABILITY IMAP4rev1 IDLE\r\n".to_vec()]),
+            "NOOP" => Ok(vec![]),
+            "IDLE" => self.idle(),
+            "LOGOUT" => Ok(vec![b"* BYE Logging out\r\n".to_vec()]),
+            "LIST" => self.list(&argument(1)?),
+            "STATUS" => self.status(&argument(0)?),
+            "SELECT" | "EXAMINE" => self.select(&argument(0)?),
+            "CREATE" => self.create(&argument(0)?),
+            "DELETE" => self.delete(&argument(0)?),
+            "RENAME" => self.rename(&argument(0)?, &argument(1)?),
+            "APPEND" => self.append(&argument(0)?, literal.ok_or("missing message".to_string())?),
+            "FETCH" => self.fetch(&argument(0)?, &rest, false),
+            "UID FETCH" => self.fetch(&argument(0)?, &rest, true),
+            "UID STORE" => self.store(&argument(0)?, &rest),
+            "UID SEARCH" => self.search(&arguments),
+            "UID COPY" => self.copy(&argument(0)?, &argument(1)?),
+            "EXPUNGE" => self.expunge(),
+            _ => Err(format!("{} is not supported", command))
+        }
+    }
+
+    /// Waits until the client ends IDLE with DONE, meanwhile new messages
+    /// of the selected folder get reported, see notify_idlers
+    fn idle(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        self.send("+ idling").map_err(|e| e.to_string())?;
+
+        if let (Some(folder), Some(writer)) = (self.selected.clone(), self.idle_writer.take()) {
+            self.idlers.lock().unwrap().push((self.id, folder, writer));
+        }
+
+        let mut done = String::new();
+        let result = self.reader.read_line(&mut done);
+
+        let mut idlers = self.idlers.lock().unwrap();
+        if let Some(index) = idlers.iter().position(|(id, _, _)| *id == self.id) {
+            self.idle_writer = Some(idlers.remove(index).2);
+        }
+        drop(idlers);
+
+        result.map_err(|e| e.to_string())?;
+        Ok(vec![])
+    }
+
+    fn list(&mut self, pattern: &str) -> Result<Vec<Vec<u8>>, String> {
+        let prefix = pattern.trim_end_matches(|c| c == '*' || c == '%');
+        Ok(self.folders.lock().unwrap().keys()
+            .filter(|name| if prefix.len() < pattern.len() { name.starts_with(prefix) } else { name.as_str() == pattern })
+            .map(|name| format!("* LIST (\\HasNoChildren) \".\" \"{}\"\r\n", name).into_bytes())
+            .collect())
+    }
+
+    fn status(&mut self, name: &str) -> Result<Vec<Vec<u8>>, String> {
+        let folders = self.folders.lock().unwrap();
+        let folder = folders.get(name).ok_or(format!("{} does not exist", name))?;
+        Ok(vec![format!("* STATUS \"{}\" (MESSAGES {} UIDNEXT {} UIDVALIDITY {})\r\n",
+                        name, folder.messages.len(), folder.uid_next, folder.uid_validity).into_bytes()])
+    }
+
+    fn select(&mut self, name: &str) -> Result<Vec<Vec<u8>>, String> {
+        let folders = self.folders.lock().unwrap();
+        let folder = folders.get(name).ok_or(format!("{} does not exist", name))?;
+        self.selected = Some(name.to_string());
+        Ok(vec![
+            b"* FLAGS (\\Seen \\Deleted)\r\n".to_vec(),
+            format!("* {} EXISTS\r\n", folder.messages.len()).into_bytes(),
+            b"* 0 RECENT\r\n".to_vec(),
+            format!("* OK [UIDVALIDITY {}] UIDs valid\r\n", folder.uid_validity).into_bytes(),
+            format!("* OK [UIDNEXT {}] Predicted next UID\r\n", folder.uid_next).into_bytes()
+        ])
+    }
+
+    fn create(&mut self, name: &str) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        if folders.contains_key(name) {
+            return Err(format!("{} does already exist", name));
+        }
+        let uid_validity = folders.len() as u32 + 1;
+        folders.insert(name.to_string(), Folder::new(uid_validity));
+        Ok(vec![])
+    }
+
+    fn delete(&mut self, name: &str) -> Result<Vec<Vec<u8>>, String> {
+        self.folders.lock().unwrap().remove(name).ok_or(format!("{} does not exist", name))?;
+        Ok(vec![])
+    }
+
+    fn rename(&mut self, old: &str, new: &str) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        let renamed: Vec<String> = folders.keys()
+            .filter(|name| name.as_str() == old || name.starts_with(&format!("{}.", old)))
+            .cloned()
+            .collect();
+        if renamed.is_empty() {
+            return Err(format!("{} does not exist", old));
+        }
+        for name in renamed {
+            let folder = folders.remove(&name).unwrap();
+            folders.insert(format!("{}{}", new, &name[old.len()..]), folder);
+        }
+        Ok(vec![])
+    }
+
+    fn append(&mut self, name: &str, message: Vec<u8>) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        let folder = folders.get_mut(name)
+            .ok_or(format!("[TRYCREATE] {} does not exist", name))?;
+        let uid = folder.append(message);
+        if self.uidplus.load(Ordering::SeqCst) {
+            self.response_code = Some(format!("APPENDUID {} {}", folder.uid_validity, uid));
+        }
+        let messages = folder.messages.len();
+        drop(folders);
+        notify_idlers(&self.idlers, name, messages);
+        Ok(vec![])
+    }
+
+    /// Returns the sequence numbers of all messages inside the set
+    fn sequence_numbers(folder: &Folder, set: &str, uid: bool) -> Vec<usize> {
+        let id = |index: usize| if uid { folder.messages[index].uid } else { index as u32 + 1 };
+        let max = folder.messages.len().checked_sub(1).map(id).unwrap_or(0);
+        let value = |value: &str| if value == "*" { max } else { value.parse().unwrap_or(0) };
+
+        (0..folder.messages.len())
+            .filter(|index| set.split(',').any(|range| {
+                let (from, to) = range.split_once(':').unwrap_or((range, range));
+                // n:* contains the last message, even if n is greater
+                let (from, to) = (value(from).min(value(to)), value(from).max(value(to)));
+                (from..=to).contains(&id(*index))
+            }))
+            .collect()
+    }
+
+    fn fetch(&mut self, set: &str, items: &str, uid: bool) -> Result<Vec<Vec<u8>>, String> {
+        let folders = self.folders.lock().unwrap();
+        let folder = self.selected.as_ref().and_then(|name| folders.get(name)).ok_or("no folder selected".to_string())?;
+
+        Ok(Self::sequence_numbers(folder, set, uid).into_iter().map(|index| {
+            let message = &folder.messages[index];
+            let (item, content) = if items.contains("RFC822.HEADER") {
+                ("RFC822.HEADER", header(&message.content))
+            } else {
+                ("RFC822", &message.content[..])
+            };
+
+            let mut response = format!("* {} FETCH (UID {} {} {{{}}}\r\n", index + 1, message.uid, item, content.len()).into_bytes();
+            response.extend_from_slice(content);
+            response.extend_from_slice(b")\r\n");
+            response
+        }).collect())
+    }
+
+    fn store(&mut self, set: &str, flags: &str) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        let folder = self.selected.as_ref().and_then(|name| folders.get_mut(name)).ok_or("no folder selected".to_string())?;
+
+        if flags.contains("\\Deleted") {
+            for index in Self::sequence_numbers(folder, set, true) {
+                folder.messages[index].deleted = flags.starts_with('+');
+            }
+        }
+        Ok(vec![])
+    }
+
+    /// Supports ALL and HEADER <field> <value>
+    fn search(&mut self, criteria: &Vec<String>) -> Result<Vec<Vec<u8>>, String> {
+        let folders = self.folders.lock().unwrap();
+        let folder = self.selected.as_ref().and_then(|name| folders.get(name)).ok_or("no folder selected".to_string())?;
+
+        let uids: Vec<String> = folder.messages.iter()
+            .filter(|message| match criteria.get(0).map(|c| c.to_uppercase()).as_deref() {
+                Some("ALL") => true,
+                Some("HEADER") if criteria.len() == 3 => has_header(&message.content, &criteria[1], &criteria[2]),
+                _ => false
+            })
+            .map(|message| message.uid.to_string())
+            .collect();
+
+        let mut response = String::from("* SEARCH");
+        for uid in uids {
+            response.push_str(&format!(" {}", uid));
+        }
+        Ok(vec![format!("{}\r\n", response).into_bytes()])
+    }
+
+    fn copy(&mut self, set: &str, target: &str) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        let folder = self.selected.as_ref().and_then(|name| folders.get(name)).ok_or("no folder selected".to_string())?;
+
+        let contents: Vec<Vec<u8>> = Self::sequence_numbers(folder, set, true).into_iter()
+            .map(|index| folder.messages[index].content.clone())
+            .collect();
+
+        let target = folders.get_mut(target).ok_or(format!("[TRYCREATE] {} does not exist", target))?;
+        for content in contents {
+            target.append(content);
+        }
+        Ok(vec![])
+    }
+
+    fn expunge(&mut self) -> Result<Vec<Vec<u8>>, String> {
+        let mut folders = self.folders.lock().unwrap();
+        let folder = self.selected.as_ref().and_then(|name| folders.get_mut(name)).ok_or("no folder selected".to_string())?;
+
+        let mut responses = vec![];
+        // Sequence numbers shift after every expunged message
+        while let Some(index) = folder.messages.iter().position(|message| message.deleted) {
+            folder.messages.remove(index);
+            responses.push(format!("* {} EXPUNGE\r\n", index + 1).into_bytes());
+        }
+        Ok(responses)
+    }
+}
+
+/// Splits the arguments of a command, quoted strings and parenthesized lists are single arguments
+fn tokenize(arguments: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = arguments.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => continue,
+            '"' => {
+                let mut token = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => token.extend(chars.next()),
+                        '"' => break,
+                        c => token.push(c)
+                    }
+                }
+                tokens.push(token);
+            },
+            '(' => {
+                let mut token = String::from("(");
+                while let Some(c) = chars.next() {
+                    token.push(c);
+                    if c == ')' {
+                        break;
+                    }
+                }
+                tokens.push(token);
+            },
+            c => {
+                let mut token = String::from(c);
+                while let Some(c) = chars.peek() {
+                    if *c == ' ' {
+                        break;
+                    }
+                    token.push(chars.next().unwrap());
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    tokens
+}
+
+/// Header of the message, including the empty line that separates it from the body
+fn header(content: &[u8]) -> &[u8] {
+    for separator in [&b"\r\n\r\n"[..], &b"\n\n"[..]] {
+        if let Some(position) = content.windows(separator.len()).position(|window| window == separator) {
+            return &content[..position + separator.len()];
+        }
+    }
+    content
+}
+
+fn has_header(content: &[u8], field: &str, value: &str) -> bool {
+    mailparse::parse_headers(header(content))
+        .map(|(headers, _)| headers.iter().any(|header| {
+            header.get_key().map(|key| key.eq_ignore_ascii_case(field)).unwrap_or(false)
+                && header.get_value().map(|v| v.to_lowercase().contains(&value.to_lowercase())).unwrap_or(false)
+        }))
+        .unwrap_or(false)
+}
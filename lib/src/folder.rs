@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use crate::apple_imap::MailService;
+use crate::db::DatabaseService;
+use crate::error::Result;
+use crate::error::NoteError::{FolderNotEmpty, InvalidFolderName};
+use crate::notes::note_folder::NoteFolder;
+use crate::util::{imap_folder_name, display_folder_name, is_inside_folder};
+
+/// Checks if the passed folder name is usable as note folder
+///
+/// Nested folders are separated with "/", every part of the name may
+/// only contain alphanumeric characters, spaces, "-" and "_".
+/// An empty name refers to the default folder.
+pub fn validate_folder_name(folder: &str) -> Result<()> {
+    let valid = folder.split('/').all(|part| {
+        part.trim().is_empty() == false
+            && part.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+    });
+
+    if folder.is_empty() || valid {
+        Ok(())
+    } else {
+        Err(InvalidFolderName(folder.to_string()).into())
+    }
+}
+
+/// Same as validate_folder_name, but also rejects the default folder
+fn validate_subfolder_name(folder: &str) -> Result<()> {
+    if folder.is_empty() {
+        return Err(InvalidFolderName("The default folder can not be changed".to_string()).into())
+    }
+    validate_folder_name(folder)
+}
+
+/// Lists all local and remote note folders, sorted by name
+pub fn list_folders<T>(imap_service: &mut dyn MailService<T>, db_connection: &Box<dyn DatabaseService + Send>)
+                       -> Result<Vec<NoteFolder>>
+{
+    let mut folders: BTreeMap<String, NoteFolder> = BTreeMap::new();
+
+    let new_folder = |imap_name: &str| NoteFolder {
+        name: display_folder_name(imap_name),
+        imap_name: imap_name.to_string(),
+        note_count: 0,
+        remote: false
+    };
+
+    for imap_name in imap_service.list_note_folders()? {
+        folders.entry(imap_name.clone()).or_insert_with(|| new_folder(&imap_name)).remote = true;
+    }
+
+    for note in db_connection.fetch_all_notes()? {
+        let imap_name = note.metadata.subfolder;
+        folders.entry(imap_name.clone()).or_insert_with(|| new_folder(&imap_name)).note_count += 1;
+    }
+
+    Ok(folders.into_iter().map(|(_, folder)| folder).collect())
+}
+
+pub fn create_folder<T>(imap_service: &mut dyn MailService<T>, folder: &str) -> Result<()> {
+    validate_subfolder_name(folder)?;
+    imap_service.create_folder(&imap_folder_name(folder))
+}
+
+/// Renames a folder and all of its subfolders on both sides
+///
+/// Folders that only exist locally, because they only contain new notes, are renamed locally.
+/// If the folder itself does not exist remotely, but some of its subfolders do, the topmost
+/// remote subfolders get renamed one by one
+pub fn rename_folder<T>(imap_service: &mut dyn MailService<T>,
+                        db_connection: &Box<dyn DatabaseService + Send>,
+                        old: &str,
+                        new: &str) -> Result<()>
+{
+    validate_subfolder_name(old)?;
+    validate_subfolder_name(new)?;
+
+    let old_imap_name = imap_folder_name(old);
+    let new_imap_name = imap_folder_name(new);
+
+    let remote_folders: Vec<String> = imap_service.list_note_folders()?
+        .into_iter()
+        .filter(|folder| is_inside_folder(folder, &old_imap_name))
+        .collect();
+
+    // The server renames the subfolders of a renamed folder as well
+    let topmost_folders = remote_folders.iter().filter(|folder| {
+        remote_folders.iter().any(|other| other != *folder && is_inside_folder(folder, other)) == false
+    });
+
+    for folder in topmost_folders {
+        imap_service.rename_folder(folder, &format!("{}{}", new_imap_name, &folder[old_imap_name.len()..]))?;
+    }
+
+    db_connection.rename_folder(&old_imap_name, &new_imap_name).map_err(|e| e.into())
+}
+
+/// Deletes a folder, only possible if the folder does not contain any notes or subfolders
+pub fn delete_folder<T>(imap_service: &mut dyn MailService<T>,
+                        db_connection: &Box<dyn DatabaseService + Send>,
+                        folder: &str) -> Result<()>
+{
+    validate_subfolder_name(folder)?;
+
+    let imap_name = imap_folder_name(folder);
+
+    let has_local_notes = db_connection.fetch_all_notes()?.iter().any(|note| {
+        is_inside_folder(&note.metadata.subfolder, &imap_name)
+            || note.metadata.moved_from.as_ref().map(|f| is_inside_folder(f, &imap_name)).unwrap_or(false)
+    });
+
+    if has_local_notes {
+        return Err(FolderNotEmpty(folder.to_string()).into())
+    }
+
+    let remote_folders = imap_service.list_note_folders()?;
+
+    if remote_folders.iter().any(|f| f != &imap_name && is_inside_folder(f, &imap_name)) {
+        return Err(FolderNotEmpty(folder.to_string()).into())
+    }
+
+    if remote_folders.contains(&imap_name) {
+        if imap_service.folder_status(&imap_name)?.messages > 0 {
+            return Err(FolderNotEmpty(folder.to_string()).into())
+        }
+        imap_service.delete_folder(&imap_name)?;
+    }
+
+    db_connection.delete_folder_sync_state(&imap_name).map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod folder_tests {
+    use super::*;
+    use crate::apple_imap::{MockMailService, FolderStatus};
+    use crate::builder::{NotesMetadataBuilder, BodyMetadataBuilder};
+    use crate::error::NoteError;
+
+    fn folder_of(db_connection: &Box<dyn DatabaseService + Send>, uuid: &str) -> String {
+        db_connection.fetch_single_note(uuid).unwrap().unwrap().metadata.subfolder
+    }
+
+    #[test]
+    fn folder_names() {
+        assert!(validate_folder_name("").is_ok());
+        assert!(validate_folder_name("todo/vacation 2022").is_ok());
+        assert!(validate_folder_name("todo.vacation").is_err());
+        assert!(validate_folder_name("todo//vacation").is_err());
+        assert!(validate_folder_name("Notes*").is_err());
+
+        assert_eq!(imap_folder_name("todo/vacation"), "Notes.todo.vacation");
+        assert_eq!(display_folder_name("Notes.todo.vacation"), "todo/vacation");
+        assert_eq!(display_folder_name("Notes"), "");
+    }
+
+    /// Renaming should affect nested folders, but not folders that share the prefix
+    #[test]
+    fn rename_nested_folder() {
+        dotenv::dotenv().ok();
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        db_connection.insert_into_db(&note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("a".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ]).unwrap();
+        db_connection.insert_into_db(&note![
+            NotesMetadataBuilder::new().with_uuid("2").with_folder("a/b".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("2").build()
+        ]).unwrap();
+        db_connection.insert_into_db(&note![
+            NotesMetadataBuilder::new().with_uuid("3").with_folder("ab".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("3").build()
+        ]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders()
+            .returning(|| Ok(vec!["Notes".to_string(), "Notes.a".to_string(), "Notes.a.b".to_string(), "Notes.ab".to_string()]));
+        imap_service.expect_rename_folder()
+            .withf(|old, new| old.to_string() == "Notes.a" && new.to_string() == "Notes.c")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        rename_folder(&mut imap_service, &db_connection, "a", "c").unwrap();
+
+        assert_eq!(folder_of(&db_connection, "1"), "Notes.c");
+        assert_eq!(folder_of(&db_connection, "2"), "Notes.c.b");
+        assert_eq!(folder_of(&db_connection, "3"), "Notes.ab");
+    }
+
+    /// If only subfolders exist remotely they get renamed, otherwise server and database diverge
+    #[test]
+    fn rename_folder_with_remote_subfolders_only() {
+        dotenv::dotenv().ok();
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        db_connection.insert_into_db(&note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("a/b".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders()
+            .returning(|| Ok(vec!["Notes".to_string(), "Notes.a.b".to_string(), "Notes.a.b.c".to_string(), "Notes.a.d".to_string()]));
+        imap_service.expect_rename_folder()
+            .withf(|old, new| old.to_string() == "Notes.a.b" && new.to_string() == "Notes.e.b")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        imap_service.expect_rename_folder()
+            .withf(|old, new| old.to_string() == "Notes.a.d" && new.to_string() == "Notes.e.d")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        rename_folder(&mut imap_service, &db_connection, "a", "e").unwrap();
+
+        assert_eq!(folder_of(&db_connection, "1"), "Notes.e.b");
+    }
+
+    /// Folders that still contain notes should not get deleted
+    #[test]
+    fn delete_non_empty_folder() {
+        dotenv::dotenv().ok();
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        db_connection.insert_into_db(&note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("a/b".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders()
+            .returning(|| Ok(vec!["Notes".to_string(), "Notes.a".to_string(), "Notes.a.b".to_string(), "Notes.c".to_string()]));
+        imap_service.expect_folder_status().returning(|_| Ok(FolderStatus {
+            uid_validity: 1,
+            uid_next: 1,
+            messages: 0,
+            highest_modseq: None
+        }));
+        imap_service.expect_delete_folder()
+            .withf(|folder| folder.to_string() == "Notes.c")
+            .times(1)
+            .returning(|_| Ok(()));
+
+        match delete_folder(&mut imap_service, &db_connection, "a") {
+            Err(e) => assert_eq!(e.error_code(), NoteError::FolderNotEmpty("a".to_string()).error_code()),
+            Ok(_) => panic!("Folder with notes should not get deleted")
+        }
+
+        delete_folder(&mut imap_service, &db_connection, "c").unwrap();
+    }
+}
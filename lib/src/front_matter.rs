@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+/// Metadata that gets written as YAML front matter in front of exported notes
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrontMatter {
+    pub uuid: String,
+    pub date: String,
+    pub message_id: String,
+    /// Folder of the note, nested folders are separated with "/",
+    /// the default folder is empty
+    pub folder: String
+}
+
+impl FrontMatter {
+    /// Renders the front matter including the surrounding "---" lines,
+    /// all values are double quoted so that no YAML parser trips over them
+    pub fn render(&self) -> String {
+        format!("---\nuuid: {}\ndate: {}\nmessage_id: {}\nfolder: {}\n---\n",
+                quote(&self.uuid),
+                quote(&self.date),
+                quote(&self.message_id),
+                quote(&self.folder))
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut unescaped = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c)
+            }
+        }
+        unescaped
+    } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits a document into the values of its front matter and the remaining content
+///
+/// Only flat "key: value" pairs are supported, every other line inside the
+/// front matter gets ignored. Documents without front matter are returned as they are
+pub fn split_front_matter(content: &str) -> (HashMap<String, String>, &str) {
+    let mut values = HashMap::new();
+
+    let rest = match content.strip_prefix("---\n").or(content.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (values, content)
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == "---" {
+            return (values, &rest[offset..])
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            values.insert(key.trim().to_string(), unquote(value));
+        }
+    }
+
+    // Not terminated, so it was no front matter after all
+    (HashMap::new(), content)
+}
+
+#[cfg(test)]
+mod front_matter_tests {
+    use crate::front_matter::{FrontMatter, split_front_matter};
+
+    #[test]
+    fn render_front_matter() {
+        let front_matter = FrontMatter {
+            uuid: "3A6D5A4E-1B2C-4D3E-8F90-ABCDEF012345".to_string(),
+            date: "Thu, 17 Nov 2022 10:00:00 +0100".to_string(),
+            message_id: "<1@test.de>".to_string(),
+            folder: "todo/\"work\"".to_string()
+        };
+
+        assert_eq!(front_matter.render(), "---\n\
+            uuid: \"3A6D5A4E-1B2C-4D3E-8F90-ABCDEF012345\"\n\
+            date: \"Thu, 17 Nov 2022 10:00:00 +0100\"\n\
+            message_id: \"<1@test.de>\"\n\
+            folder: \"todo/\\\"work\\\"\"\n\
+            ---\n");
+    }
+
+    #[test]
+    fn parse_front_matter() {
+        let (values, content) = split_front_matter("---\ntitle: 'It''s done'\nuuid: \"1\\\"2\"\ntags:\n  - todo\n---\nFirst line\n");
+        assert_eq!(values.get("title").unwrap(), "It's done");
+        assert_eq!(values.get("uuid").unwrap(), "1\"2");
+        assert_eq!(content, "First line\n");
+
+        let (values, content) = split_front_matter("---\nNo front matter");
+        assert!(values.is_empty());
+        assert_eq!(content, "---\nNo front matter");
+    }
+}
@@ -0,0 +1,100 @@
+//! Previous texts of notes.
+//!
+//! Every time the text of a note body gets replaced, the database records the
+//! old text together with the source of the change. Old texts can be compared
+//! with the current text or restored, a restored text gets uploaded with the next sync.
+
+use crate::edit;
+use crate::error::NoteError;
+use crate::model::HistoryEntry;
+use crate::notes::localnote::LocalNote;
+use crate::notes::revision::Revision;
+use crate::profile::Profile;
+
+/// The text got replaced by editing or restoring the note locally
+pub const LOCAL_EDIT: &str = "local edit";
+/// The text got replaced by a newer version of the note on the server
+pub const REMOTE_UPDATE: &str = "remote update";
+/// The text got replaced by merging conflicting versions of the note
+pub const MERGE: &str = "merge";
+
+/// Numbers the history entries of a single note, oldest first
+pub fn revisions(entries: Vec<HistoryEntry>) -> Vec<Revision> {
+    entries.into_iter()
+        .enumerate()
+        .map(|(index, entry)| Revision {
+            number: index + 1,
+            date: entry.date,
+            source: entry.source,
+            text: entry.text
+        })
+        .collect()
+}
+
+pub fn find_revision(revisions: Vec<Revision>, number: usize) -> Result<Revision, NoteError> {
+    revisions.into_iter()
+        .find(|revision| revision.number == number)
+        .ok_or(NoteError::RevisionNotFound(number))
+}
+
+/// Line based diff from old to new, removed lines are prefixed
+/// with "-", added lines with "+"
+pub fn diff(old: &str, new: &str) -> String {
+    diff::lines(old, new).iter().map(|diff| {
+        match diff {
+            diff::Result::Left(l) => format!("-{}", l),
+            diff::Result::Both(l, _) => format!(" {}", l),
+            diff::Result::Right(r) => format!("+{}", r)
+        }
+    })
+        .map(|string| format!("{}\n", string))
+        .collect()
+}
+
+/// Replaces the text of the note with the text of the revision and flags
+/// the note as edited, the restored text gets uploaded with the next sync
+pub fn restore(note: &LocalNote, revision: &Revision, profile: &Profile) -> Result<LocalNote, NoteError> {
+    if note.needs_merge() {
+        return Err(NoteError::NeedsMerge);
+    }
+
+    let body = note.body.first()
+        .expect("Expected at least 1 note body");
+
+    if body.text.as_deref() == Some(revision.text.as_str()) {
+        return Err(NoteError::ContentNotChanged);
+    }
+
+    Ok(edit::edited_note(note, body, &revision.text, profile))
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+
+    fn entry(id: i32, text: &str, source: &str) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            metadata_uuid: "uuid".to_string(),
+            message_id: format!("<{}@test>", id),
+            text: text.to_string(),
+            date: "Tue, 13 Dec 2022 10:00:00 +0000".to_string(),
+            source: source.to_string()
+        }
+    }
+
+    #[test]
+    fn number_revisions_per_note() {
+        let revisions = revisions(vec![entry(7, "first", LOCAL_EDIT), entry(12, "second", REMOTE_UPDATE)]);
+
+        assert_eq!(revisions.iter().map(|r| r.number).collect::<Vec<usize>>(), vec![1, 2]);
+        assert_eq!(find_revision(revisions.clone(), 2).unwrap().text, "second");
+        assert_eq!(find_revision(revisions, 3).unwrap_err(), NoteError::RevisionNotFound(3));
+    }
+
+    #[test]
+    fn diff_lines() {
+        let diff = diff("Note\nold line\nsame", "Note\nnew line\nsame");
+        assert_eq!(diff, " Note\n-old line\n+new line\n same\n");
+    }
+}
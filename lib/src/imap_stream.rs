@@ -0,0 +1,176 @@
+extern crate native_tls;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use self::native_tls::{TlsConnector, TlsStream, Certificate};
+use imap::extensions::idle::SetReadTimeout;
+use crate::config::Security;
+use crate::error::Result;
+use crate::profile::Profile;
+
+/// Connection to the imap server, encrypted or, for test servers on the
+/// loopback interface, unencrypted
+#[derive(Debug)]
+pub enum ImapStream {
+    Tls(TlsStream<TcpStream>),
+    Plain(TcpStream)
+}
+
+impl ImapStream {
+
+    /// Connects to the imap server of the profile, the returned stream is ready for
+    /// the login. The greeting of the server has to be read before logging in, except
+    /// for STARTTLS, where it gets read before the connection gets upgraded
+    pub fn connect(profile: &Profile) -> Result<ImapStream> {
+        let domain = profile.imap_server.as_str();
+        info!("Connecting to {}:{} ({:?})", domain, profile.port, profile.security);
+
+        let tcp_stream = TcpStream::connect((domain, profile.port))?;
+
+        match profile.security {
+            Security::Tls => {
+                let tls = tls_connector(profile)?;
+                tls.connect(domain, tcp_stream)
+                    .map(ImapStream::Tls)
+                    .map_err(|e| imap::error::Error::TlsHandshake(e).into())
+            },
+            Security::StartTls => starttls(tcp_stream, domain, &tls_connector(profile)?).map(ImapStream::Tls),
+            Security::Plaintext => Ok(ImapStream::Plain(tcp_stream))
+        }
+    }
+}
+
+/// Default tls connector, that additionally trusts the certificate inside ca_file
+fn tls_connector(profile: &Profile) -> Result<TlsConnector> {
+    let mut builder = TlsConnector::builder();
+    if let Some(ca_file) = &profile.ca_file {
+        let certificate = Certificate::from_pem(&std::fs::read(ca_file)?)
+            .map_err(imap::error::Error::Tls)?;
+        builder.add_root_certificate(certificate);
+    }
+    builder.build().map_err(|e| imap::error::Error::Tls(e).into())
+}
+
+/// Reads the greeting, sends STARTTLS and upgrades the connection
+fn starttls(mut tcp_stream: TcpStream, domain: &str, tls: &TlsConnector) -> Result<TlsStream<TcpStream>> {
+    // The server does not send anything after the tagged response,
+    // so the reader can not buffer any bytes of the tls handshake
+    let mut reader = BufReader::new(tcp_stream.try_clone()?);
+    let mut line = String::new();
+
+    reader.read_line(&mut line)?;
+    if line.starts_with("* OK") == false {
+        return Err(imap::error::Error::Bad(format!("Unexpected greeting: {}", line.trim())).into());
+    }
+
+    tcp_stream.write_all(b"a0 STARTTLS\r\n")?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed during STARTTLS").into());
+        }
+        if line.starts_with("a0 ") {
+            break;
+        }
+    }
+
+    if line.starts_with("a0 OK") == false {
+        return Err(imap::error::Error::Bad(format!("STARTTLS failed: {}", line.trim())).into());
+    }
+
+    tls.connect(domain, tcp_stream).map_err(|e| imap::error::Error::TlsHandshake(e).into())
+}
+
+impl Read for ImapStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ImapStream::Tls(stream) => stream.read(buf),
+            ImapStream::Plain(stream) => stream.read(buf)
+        }
+    }
+}
+
+impl Write for ImapStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ImapStream::Tls(stream) => stream.write(buf),
+            ImapStream::Plain(stream) => stream.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ImapStream::Tls(stream) => stream.flush(),
+            ImapStream::Plain(stream) => stream.flush()
+        }
+    }
+}
+
+/// Needed for IMAP IDLE
+impl SetReadTimeout for ImapStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+        match self {
+            ImapStream::Tls(stream) => SetReadTimeout::set_read_timeout(stream, timeout),
+            ImapStream::Plain(stream) => SetReadTimeout::set_read_timeout(stream, timeout)
+        }
+    }
+}
+
+/// Stream of an imap session, that keeps a copy of the received bytes while recording.
+///
+/// The imap crate drops the response codes of tagged responses, like the
+/// APPENDUID of UIDPLUS servers, they are read from the copy instead
+pub struct RecordingStream<S> {
+    stream: S,
+    recording: Arc<Mutex<Option<Vec<u8>>>>
+}
+
+/// Starts and stops the recording of a RecordingStream, which is owned by the session
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<Option<Vec<u8>>>>);
+
+impl<S> RecordingStream<S> {
+    pub fn new(stream: S) -> (RecordingStream<S>, Recorder) {
+        let recording = Arc::new(Mutex::new(None));
+        (RecordingStream { stream, recording: recording.clone() }, Recorder(recording))
+    }
+}
+
+impl Recorder {
+    pub fn start(&self) {
+        *self.0.lock().unwrap() = Some(Vec::new());
+    }
+
+    /// Stops the recording, returns everything that got received since start
+    pub fn stop(&self) -> Vec<u8> {
+        self.0.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.stream.read(buf)?;
+        if let Some(recording) = self.recording.lock().unwrap().as_mut() {
+            recording.extend_from_slice(&buf[..size]);
+        }
+        Ok(size)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: SetReadTimeout> SetReadTimeout for RecordingStream<S> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
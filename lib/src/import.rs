@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use walkdir::WalkDir;
+use crate::error::NoteError::InsertionError;
+use crate::error::Result;
+use crate::front_matter::split_front_matter;
+use crate::merge;
+
+/// A markdown or text file that should become a new note
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedNote {
+    /// Uuid from the front matter, or derived from the relative file path, so
+    /// that importing the same directory again results in the same uuid
+    pub uuid: String,
+    /// Folder of the note, nested folders are separated with "/"
+    pub folder: String,
+    /// Text of the note, the first line is the subject
+    pub text: String,
+    pub path: PathBuf
+}
+
+/// Reads all .md and .txt files inside dir
+///
+/// The folder of a note is taken from its subdirectory, a leading "Notes" directory
+/// gets ignored, so that directories created by export can be imported again.
+/// The subject is taken from the "title" or "subject" front matter value, or the first
+/// line of the file, or the file name if the file is empty. Files that share the same
+/// uuid are combined into a single note, see combine_duplicates.
+pub fn read_notes(dir: &Path) -> Result<Vec<ImportedNote>> {
+    let mut notes = vec![];
+
+    for entry in WalkDir::new(dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry.map_err(|e| std::io::Error::from(e))?;
+        let path = entry.path();
+
+        let is_note = path.extension()
+            .map(|extension| extension == "md" || extension == "txt")
+            .unwrap_or(false);
+
+        if entry.file_type().is_file() == false || is_note == false {
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(dir).unwrap_or(path);
+        let mut folders: Vec<String> = relative_path.parent()
+            .map(|parent| parent.iter().map(|part| part.to_string_lossy().to_string()).collect())
+            .unwrap_or_default();
+        if folders.first().map(|first| first == "Notes").unwrap_or(false) {
+            folders.remove(0);
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let (front_matter, content) = split_front_matter(&content);
+
+        let subject = front_matter.get("title")
+            .or(front_matter.get("subject"))
+            .cloned()
+            .filter(|subject| subject.is_empty() == false);
+
+        let first_line = content.lines().next().unwrap_or("").trim();
+        let text = match subject {
+            Some(subject) if subject != first_line => format!("{}\n{}", subject, content),
+            None if first_line.is_empty() => {
+                let file_name = path.file_stem().unwrap_or_default().to_string_lossy();
+                format!("{}\n{}", file_name, content)
+            },
+            _ => content.to_string()
+        };
+
+        let uuid = match front_matter.get("uuid").filter(|uuid| uuid.is_empty() == false) {
+            Some(uuid) => match Uuid::parse_str(uuid) {
+                Ok(uuid) => uuid.to_string().to_uppercase(),
+                Err(_) => return Err(InsertionError(format!("{} has an invalid uuid: {}", path.display(), uuid)).into())
+            },
+            None => path_uuid(relative_path)
+        };
+
+        notes.push(ImportedNote {
+            uuid,
+            folder: folders.join("/"),
+            text,
+            path: path.to_path_buf()
+        });
+    }
+
+    Ok(combine_duplicates(notes))
+}
+
+/// Combines files with the same uuid, e.g. copies of an exported note, into one note.
+/// Their texts get merged like the bodies of a note, the file names are used as labels
+/// of the conflicting versions. The folder of the first file is kept
+fn combine_duplicates(notes: Vec<ImportedNote>) -> Vec<ImportedNote> {
+    let mut groups: Vec<Vec<ImportedNote>> = vec![];
+    for note in notes {
+        match groups.iter_mut().find(|group| group[0].uuid == note.uuid) {
+            Some(group) => group.push(note),
+            None => groups.push(vec![note])
+        }
+    }
+
+    groups.into_iter().map(|mut group| {
+        if group.len() == 1 {
+            return group.remove(0);
+        }
+
+        let labels: Vec<String> = group.iter()
+            .map(|note| note.path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect();
+        warn!("{} share the uuid {}, they get imported as one note", labels.join(", "), group[0].uuid);
+
+        let bodies: Vec<(&str, &str)> = labels.iter()
+            .zip(group.iter())
+            .map(|(label, note)| (label.as_str(), note.text.as_str()))
+            .collect();
+        let text = merge::merge_all(None, &bodies);
+
+        let mut note = group.remove(0);
+        note.text = text;
+        note
+    }).collect()
+}
+
+/// Name based uuid, stays the same as long as the file does not get moved
+fn path_uuid(relative_path: &Path) -> String {
+    let name = relative_path.iter()
+        .map(|part| part.to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/");
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, format!("apnotes-import:{}", name).as_bytes())
+        .to_string()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod import_tests {
+    use super::*;
+
+    #[test]
+    fn read_note_tree() {
+        let dir = std::env::temp_dir().join(format!("apnotes_import_{}", crate::util::generate_uuid()));
+        std::fs::create_dir_all(dir.join("Notes/todo/work")).unwrap();
+        std::fs::create_dir_all(dir.join("recipes")).unwrap();
+
+        std::fs::write(dir.join("Notes/Shopping.md"), "---\nuuid: \"3f2504e0-4f89-11d3-9a0c-0305e82c3301\"\nfolder: \"\"\n---\nShopping\nMilk").unwrap();
+        std::fs::write(dir.join("Notes/todo/work/tasks.txt"), "---\ntitle: Tasks\n---\nFirst").unwrap();
+        std::fs::write(dir.join("recipes/Pancakes.md"), "").unwrap();
+        std::fs::write(dir.join("recipes/image.png"), "").unwrap();
+
+        let notes = read_notes(&dir).unwrap();
+        assert_eq!(notes.len(), 3);
+
+        assert_eq!(notes[0].uuid, "3F2504E0-4F89-11D3-9A0C-0305E82C3301");
+        assert_eq!(notes[0].folder, "");
+        assert_eq!(notes[0].text, "Shopping\nMilk");
+
+        assert_eq!(notes[1].folder, "todo/work");
+        assert_eq!(notes[1].text, "Tasks\nFirst");
+
+        assert_eq!(notes[2].folder, "recipes");
+        assert_eq!(notes[2].text, "Pancakes\n");
+        assert!(crate::util::is_uuid(&notes[2].uuid));
+
+        // Same path, same uuid
+        assert_eq!(read_notes(&dir).unwrap()[2].uuid, notes[2].uuid);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_uuid() {
+        let dir = std::env::temp_dir().join(format!("apnotes_import_{}", crate::util::generate_uuid()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Shopping.md"), "---\nuuid: \"1\"\n---\nShopping\nMilk").unwrap();
+
+        assert!(read_notes(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Files with the same uuid become one note with conflict markers
+    #[test]
+    fn combine_files_with_same_uuid() {
+        let dir = std::env::temp_dir().join(format!("apnotes_import_{}", crate::util::generate_uuid()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let front_matter = "---\nuuid: \"3F2504E0-4F89-11D3-9A0C-0305E82C3301\"\n---\n";
+        std::fs::write(dir.join("Tasks_1.md"), format!("{}Tasks\nFirst", front_matter)).unwrap();
+        std::fs::write(dir.join("Tasks_2.md"), format!("{}Tasks\nSecond", front_matter)).unwrap();
+
+        let notes = read_notes(&dir).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].text, "Tasks\n<<<<<<< Tasks_1.md\nFirst\n======= Tasks_2.md\nSecond\n>>>>>>>\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Journal of the actions of a running synchronization.
+//!
+//! Every action gets recorded before it is processed and removed after the imap
+//! server and the database got changed. If the synchronization gets interrupted
+//! in between, the next synchronization rolls the left over actions forward or back
+//! before it plans new actions, so that no notes get duplicated on the server.
+
+use crate::apple_imap::MailService;
+use crate::db::DatabaseService;
+use crate::error::Result;
+use crate::model::JournalEntry;
+use crate::notes::traits::identifyable_note::IdentifiableNote;
+use crate::notes::traits::header_parser::HeaderParser;
+use crate::sync::{UpdateAction, synced_note};
+
+/// The action got planned, the imap server might already have been changed
+pub const PLANNED: &str = "planned";
+/// The imap server got changed, the database not yet
+pub const REMOTE_DONE: &str = "remote_done";
+
+/// Creates the journal entry of a planned action
+pub fn entry(action: &UpdateAction) -> JournalEntry {
+    let (uuid, folder, message_id) = match action {
+        UpdateAction::AddRemotely(note) | UpdateAction::UpdateRemotely(note) =>
+            (note.metadata.uuid.clone(), note.metadata.folder(), note.body[0].message_id.clone()),
+        UpdateAction::MoveRemotely(note) =>
+            (note.metadata.uuid.clone(), note.folder(), note.body[0].message_id.clone()),
+        UpdateAction::DeleteRemote(note) | UpdateAction::DeleteLocally(note) =>
+            (note.metadata.uuid.clone(), note.remote_folder(), note.body[0].message_id.clone()),
+        UpdateAction::UpdateLocally(headers) | UpdateAction::Merge(_, headers) | UpdateAction::AddLocally(headers) => {
+            let header = headers.last().expect("Expected at least 1 remote note");
+            (header.headers.uuid(), header.folder.clone(), header.headers.message_id())
+        }
+    };
+
+    JournalEntry {
+        uuid,
+        action: action.to_string(),
+        phase: PLANNED.to_string(),
+        folder,
+        message_id,
+        uid: None
+    }
+}
+
+/// Rolls all actions of an interrupted synchronization forward or back
+///
+/// Actions that only change the database are planned again by the next
+/// synchronization, for them the journal entry just gets removed
+pub fn recover<T>(imap_connection: &mut dyn MailService<T>,
+                  db_connection: &Box<dyn DatabaseService + Send>) -> Result<()> {
+    for entry in db_connection.fetch_journal()? {
+        info!("Recovering interrupted {} of {} ({})", entry.action, entry.uuid, entry.phase);
+
+        match entry.action.as_str() {
+            "AddRemotely" | "UpdateRemotely" => recover_upload(imap_connection, db_connection, &entry)?,
+            "MoveRemotely" => recover_move(imap_connection, db_connection, &entry)?,
+            "DeleteRemote" => recover_delete(imap_connection, db_connection, &entry)?,
+            _ => {}
+        }
+
+        db_connection.delete_journal_entry(&entry.uuid)?;
+    }
+    Ok(())
+}
+
+/// Returns the uid of the message the action wrote, None if it did not reach the server
+fn written_uid<T>(imap_connection: &mut dyn MailService<T>, entry: &JournalEntry) -> Result<Option<u32>> {
+    match entry.uid {
+        Some(uid) if entry.phase == REMOTE_DONE => Ok(Some(uid as u32)),
+        _ => imap_connection.find_message(&entry.folder, &entry.message_id)
+    }
+}
+
+fn recover_upload<T>(imap_connection: &mut dyn MailService<T>,
+                     db_connection: &Box<dyn DatabaseService + Send>,
+                     entry: &JournalEntry) -> Result<()> {
+    let uid = match written_uid(imap_connection, entry)? {
+        Some(uid) => uid,
+        None => {
+            // The local note is still flagged as edited, it gets uploaded again
+            info!("{} did not reach the server, rolling back", entry.message_id);
+            return Ok(());
+        }
+    };
+
+    info!("{} got uploaded with uid {}, rolling forward", entry.message_id, uid);
+    // The replaced message might not have been expunged yet
+    imap_connection.delete_duplicates(&entry.uuid, &entry.folder, uid)?;
+
+    let note = match db_connection.fetch_single_note(&entry.uuid)? {
+        Some(note) if note.body.len() == 1 => note,
+        _ => return Ok(())
+    };
+
+    if note.body[0].message_id == entry.message_id {
+        db_connection.update(&synced_note(&note, uid))?;
+    } else {
+        // The note got edited again, the uploaded message gets replaced with the next sync
+        let mut note = note;
+        note.metadata.new = false;
+        note.metadata.moved_from = None;
+        note.body[0].old_remote_message_id = Some(entry.message_id.clone());
+        note.body[0].uid = Some(uid as i64);
+        db_connection.update(&note)?;
+    }
+    Ok(())
+}
+
+fn recover_move<T>(imap_connection: &mut dyn MailService<T>,
+                   db_connection: &Box<dyn DatabaseService + Send>,
+                   entry: &JournalEntry) -> Result<()> {
+    let uid = match written_uid(imap_connection, entry)? {
+        Some(uid) => uid,
+        None => {
+            info!("{} did not get moved into {}, rolling back", entry.message_id, entry.folder);
+            return Ok(());
+        }
+    };
+
+    info!("{} got moved into {}, rolling forward", entry.message_id, entry.folder);
+    // Without MOVE the message gets copied, the original might still exist
+    imap_connection.delete_duplicates(&entry.uuid, &entry.folder, uid)?;
+
+    if let Some(mut note) = db_connection.fetch_single_note(&entry.uuid)? {
+        if note.metadata.moved_from.is_some() && note.folder() == entry.folder {
+            note.metadata.moved_from = None;
+            note.body[0].uid = Some(uid as i64);
+            db_connection.update(&note)?;
+        }
+    }
+    Ok(())
+}
+
+fn recover_delete<T>(imap_connection: &mut dyn MailService<T>,
+                     db_connection: &Box<dyn DatabaseService + Send>,
+                     entry: &JournalEntry) -> Result<()> {
+    if imap_connection.find_message(&entry.folder, &entry.message_id)?.is_some() {
+        info!("{} did not get deleted, rolling back", entry.message_id);
+        return Ok(());
+    }
+
+    info!("{} got deleted, rolling forward", entry.message_id);
+    if let Some(note) = db_connection.fetch_single_note(&entry.uuid)? {
+        if note.metadata.locally_deleted {
+            db_connection.delete(&note)?;
+        }
+    }
+    Ok(())
+}
@@ -16,6 +16,8 @@ extern crate mailparse;
 extern crate mockall;
 extern crate colored;
 extern crate regex;
+#[macro_use]
+extern crate lazy_static;
 extern crate diff;
 #[macro_use]
 extern crate log;
@@ -23,6 +25,9 @@ extern crate quoted_printable;
 #[macro_use]
 extern crate diesel_migrations;
 extern crate htmlescape;
+extern crate base64;
+extern crate toml;
+extern crate curl;
 #[cfg(target_family = "unix")]
 extern crate secret_service;
 #[cfg(target_family = "unix")]
@@ -31,9 +36,16 @@ extern crate xdg;
 #[macro_use]
 mod macros;
 mod apple_imap;
+mod imap_stream;
+mod oauth2;
 mod converter;
+mod checklist;
+mod table;
 mod profile;
+pub mod config;
 mod sync;
+mod journal;
+mod history;
 #[macro_use]
 mod util;
 pub mod error;
@@ -44,18 +56,33 @@ mod schema;
 mod builder;
 pub mod notes;
 mod merge;
+mod watch;
+mod folder;
+mod backup;
+mod front_matter;
+mod export;
+mod import;
+mod attachment;
+#[cfg(test)]
+mod fake_imap;
 
 use error::{Result, NoteError};
 
 use db::{DatabaseService};
-use error::NoteError::{NoteNotFound, InsertionError};
-use util::is_uuid;
+use error::NoteError::NoteNotFound;
+use util::{is_uuid, imap_folder_name};
 use notes::localnote::LocalNote;
+use notes::search_result::SearchResult;
+use notes::note_folder::NoteFolder;
+use notes::todo::Todo;
+use notes::revision::Revision;
+use notes::traits::identifyable_note::Subject;
 use error::{UpdateError};
 use std::collections::HashSet;
 use std::collections::hash_map::RandomState;
 use profile::Profile;
-use sync::SyncResult;
+use config::LoadedConfig;
+pub use sync::SyncResult;
 
 pub struct AppleNotes {
     profile: Profile,
@@ -71,6 +98,16 @@ impl AppleNotes {
         }
     }
 
+    /// Creates an instance that uses the database of the passed profile
+    pub fn for_profile(profile: Profile) -> AppleNotes {
+        let db_connection = Box::new(db::SqliteDBConnection::for_profile(profile.name()));
+        AppleNotes::new(profile, db_connection)
+    }
+
+    pub fn profile_name(&self) -> &str {
+        self.profile.name()
+    }
+
     /// Syncs with the imap server
     ///
     /// Returns a Result with an Array of Results.
@@ -86,6 +123,25 @@ impl AppleNotes {
         sync::sync_notes(&self.db_connection, &self.profile, dry_run).await
     }
 
+    /// Syncs over an already established connection to the imap server, e.g. a unix socket,
+    /// an ssh tunnel or an in-memory pipe. The stream has to be at the point where the
+    /// server sends its greeting, the login uses the credentials of the profile
+    pub async fn sync_notes_with_stream<S: std::io::Read + std::io::Write>(&self, stream: S, dry_run: bool) -> Result<Vec<SyncResult>> {
+        sync::sync_notes_with_stream(stream, &self.db_connection, &self.profile, dry_run).await
+    }
+
+    /// Keeps a connection to the imap server open and syncs every time the notes
+    /// changed remotely, as well as after every (re)connect. on_sync gets called
+    /// with the result of every sync. Runs until the process gets terminated
+    pub async fn watch<F>(&self, mut on_sync: F) -> Result<()>
+        where F: FnMut(Result<Vec<SyncResult>>)
+    {
+        watch::watch(&self.profile, |imap_service| {
+            on_sync(sync::sync(imap_service, &self.db_connection, false));
+            true
+        }).await
+    }
+
     /// Opens a text editor with the content of the specified note
     /// Returns the updated note object, it will not save it in the db
     /// you have to save it manually afterwards
@@ -97,25 +153,57 @@ impl AppleNotes {
     pub fn create_new_note(&self, with_subject: &str, folder: &str)
                               -> Result<LocalNote>
     {
+        self.insert_new_note(util::generate_uuid(), with_subject, folder)
+    }
+
+    /// Imports all .md and .txt files inside dir as new notes, they get uploaded with the
+    /// next sync. Files whose note already exists are skipped, so importing the same
+    /// directory twice does not duplicate notes. Either all files get imported or,
+    /// if one of them can not be imported, none of them.
+    ///
+    /// Returns the newly created notes
+    pub fn import(&self, dir: &std::path::Path) -> Result<Vec<LocalNote>> {
+        let files = import::read_notes(dir)?;
+        for file in &files {
+            folder::validate_folder_name(&file.folder)?;
+        }
 
-        if folder.chars().all(char::is_alphanumeric) == false {
-            return Err(InsertionError("Folder name has to be alphanumeric".to_string()).into())
+        let mut imported = vec![];
+        for file in files {
+            if self.db_connection.fetch_single_note(&file.uuid)?.is_some() {
+                info!("Skipping {}, it got imported already", file.path.display());
+                continue;
+            }
+            imported.push(self.build_new_note(file.uuid, &file.text, &file.folder));
         }
 
+        self.db_connection.insert_notes(&imported)?;
+        Ok(imported)
+    }
+
+    fn insert_new_note(&self, uuid: String, text: &str, folder: &str) -> Result<LocalNote> {
+
+        folder::validate_folder_name(folder)?;
+
+        let note = self.build_new_note(uuid, text, folder);
+        self.db_connection.insert_into_db(&note)
+            .and_then(|_| Ok(note))
+            .map_err(|e| e.into())
+    }
+
+    fn build_new_note(&self, uuid: String, text: &str, folder: &str) -> LocalNote {
         #[cfg(test)]
             let note = note!(
-             builder::NotesMetadataBuilder::new().with_folder(folder.to_string()).is_new(true).build(),
-             builder::BodyMetadataBuilder::new().with_text(&with_subject.clone()).build()
+             builder::NotesMetadataBuilder::new().with_uuid(&uuid).with_folder(folder.to_string()).is_new(true).build(),
+             builder::BodyMetadataBuilder::new().with_text(text).build()
            );
         #[cfg(not(test))]
             let note = note!(
-             builder::NotesMetadataBuilder::new().with_folder(folder.to_string()).is_new(true).build(),
-             builder::BodyMetadataBuilder::new(&self.profile).with_text(&with_subject.clone()).build()
+             builder::NotesMetadataBuilder::new().with_uuid(&uuid).with_folder(folder.to_string()).is_new(true).build(),
+             builder::BodyMetadataBuilder::new(&self.profile).with_text(text).build()
            );
 
-        self.db_connection.insert_into_db(&note)
-            .and_then(|_| Ok(note))
-            .map_err(|e| e.into())
+        note
     }
 
     /// Queries the database and tries to find a note with the provided search string
@@ -155,7 +243,9 @@ impl AppleNotes {
         }
     }
 
-    /// Merges notes that have > 1 bodies (right now only 2 bodies supported)
+    /// Merges notes that have > 1 bodies into a single conflict document
+    /// If the last synchronized version of the note is known it gets used as
+    /// common ancestor, so that only overlapping changes end up as conflicts.
     /// After merging it the default text editor gets opened so that the user
     /// can resolve all conflicts, after saving the note is marked as merged
     pub fn merge(&self, uuid_or_name: &String)
@@ -163,30 +253,41 @@ impl AppleNotes {
         self.find_note(&uuid_or_name)
             .and_then(|note| {
 
-                //TODO currently only supports merging for 2 notes
-                if note.needs_merge() == false || note.body.len() > 2 {
-                    return Err(UpdateError::SyncError("Note not mergeable, right now only notes with 2 bodies are mergeable".to_string()).into());
+                if note.needs_merge() == false {
+                    return Err(UpdateError::SyncError("Note not mergeable, it has only one body".to_string()).into());
                 }
 
-                let diff = merge::merge_two(&note.body[0].text.as_ref().unwrap(), &note.body[1].text.as_ref().unwrap());
+                let base_text = merge::base_text(&note);
+                let diff = merge::merge_note(&note);
+
+                // Keeps the formatting of the remote note that the merge is based on
+                let html = note.body.iter()
+                    .filter(|body| body.old_remote_message_id.is_none())
+                    .find_map(|body| body.html.clone());
+
+                // Joins all message-ids, so that every remote duplicate gets replaced with the next sync
+                let message_ids = note.body.iter()
+                    .map(|body| body.message_id.clone())
+                    .collect::<Vec<String>>()
+                    .join(",");
 
                 #[cfg(not(test))]
                     let note = note![
                 note.metadata.clone(),
-                builder::BodyMetadataBuilder::new(&self.profile).with_text(&diff).with_message_id(&format!("{},{}",&note.body[0].message_id, &note.body[1].message_id)).build()
+                builder::BodyMetadataBuilder::new(&self.profile).with_text(&diff).with_message_id(&message_ids).with_base_text(base_text).with_html(html).build()
                 ];
 
                 #[cfg(test)]
                     let note = note![
                 note.metadata.clone(),
-                builder::BodyMetadataBuilder::new().with_text(&diff).with_message_id(&format!("{},{}",&note.body[0].message_id, &note.body[1].message_id)).build()
+                builder::BodyMetadataBuilder::new().with_text(&diff).with_message_id(&message_ids).with_base_text(base_text).with_html(html).build()
                 ];
 
 
                 Ok(note)
             })
             .and_then(|note| edit::edit_note(&note, false, &self.profile).map_err(|e| e.into()))
-            .and_then(|note| self.db_connection.update(&note).map_err(|e| e.into()))
+            .and_then(|note| self.db_connection.update_with_source(&note, history::MERGE).map_err(|e| e.into()))
     }
 
     pub fn print(&self, uuid_or_name: &String) -> Result<()> {
@@ -202,6 +303,36 @@ impl AppleNotes {
             })
     }
 
+    /// Returns all previous texts of the note, oldest first
+    pub fn history(&self, uuid_or_name: &String) -> Result<Vec<Revision>> {
+        let note = self.find_note(&uuid_or_name)?;
+        let entries = self.db_connection.fetch_history(&note.metadata.uuid)?;
+        Ok(history::revisions(entries))
+    }
+
+    /// Line based diff from the text of the specified revision to the current text of the note
+    pub fn diff(&self, uuid_or_name: &String, revision: usize) -> Result<String> {
+        let note = self.find_note(&uuid_or_name)?;
+        if note.needs_merge() {
+            return Err(NoteError::NeedsMerge.into())
+        }
+
+        let entries = self.db_connection.fetch_history(&note.metadata.uuid)?;
+        let revision = history::find_revision(history::revisions(entries), revision)?;
+        let text = note.body[0].text.clone().unwrap_or_default();
+        Ok(history::diff(&revision.text, &text))
+    }
+
+    /// Replaces the text of the note with the text of the specified revision,
+    /// the note gets uploaded with the next sync
+    pub fn restore(&self, uuid_or_name: &String, revision: usize) -> Result<()> {
+        let note = self.find_note(&uuid_or_name)?;
+        let entries = self.db_connection.fetch_history(&note.metadata.uuid)?;
+        let revision = history::find_revision(history::revisions(entries), revision)?;
+        let restored = history::restore(&note, &revision, &self.profile)?;
+        self.db_connection.update(&restored).map_err(|e| e.into())
+    }
+
     /// Unflags a note, so that in will not get deleted within the next sync
     pub fn undelete_note(&self, uuid_or_name: &String)
                             -> Result<()> {
@@ -213,6 +344,36 @@ impl AppleNotes {
             .and_then(|note| self.db_connection.update(&note).map_err(|e| e.into()))
     }
 
+    /// Moves a note into the specified folder, an empty folder name refers to the default
+    /// "Notes" folder. The note gets moved remotely with the next synchronization
+    pub fn move_note(&self, uuid_or_name: &String, folder: &str) -> Result<()> {
+
+        folder::validate_folder_name(folder)?;
+
+        self.find_note(&uuid_or_name)
+            .and_then(|mut note| {
+                if note.needs_merge() {
+                    return Err(NoteError::NeedsMerge.into())
+                }
+
+                let new_folder = imap_folder_name(folder);
+
+                // New notes do not exist remotely yet, they just get added to the new folder
+                if note.metadata.new == false && note.metadata.moved_from.is_none() {
+                    note.metadata.moved_from = Some(note.metadata.subfolder.clone());
+                }
+
+                // Moving a note back to its remote folder cancels the move
+                if note.metadata.moved_from.as_ref() == Some(&new_folder) {
+                    note.metadata.moved_from = None;
+                }
+
+                note.metadata.subfolder = new_folder;
+                Ok(note)
+            })
+            .and_then(|note| self.db_connection.update(&note).map_err(|e| e.into()))
+    }
+
     /// Flags a note for deletion, flagged notes are getting deleted remotely with the next synchronization
     pub fn delete_note(&self, uuid_or_name: &String) -> Result<()> {
         self.find_note(&uuid_or_name)
@@ -223,16 +384,127 @@ impl AppleNotes {
             .and_then(|note| self.db_connection.update(&note).map_err(|e| e.into()))
     }
 
+    /// Lists all note folders that exist either locally or on the server
+    pub async fn list_folders(&self) -> Result<Vec<NoteFolder>> {
+        apple_imap::with_session(&self.profile, |imap_service| {
+            folder::list_folders(imap_service, &self.db_connection)
+        }).await
+    }
+
+    /// Creates a new, empty folder on the server, nested folders are separated with "/"
+    pub async fn create_folder(&self, name: &str) -> Result<()> {
+        apple_imap::with_session(&self.profile, |imap_service| {
+            folder::create_folder(imap_service, name)
+        }).await
+    }
+
+    /// Renames a folder including all of its subfolders, locally and on the server
+    pub async fn rename_folder(&self, old: &str, new: &str) -> Result<()> {
+        apple_imap::with_session(&self.profile, |imap_service| {
+            folder::rename_folder(imap_service, &self.db_connection, old, new)
+        }).await
+    }
+
+    /// Deletes an empty folder, fails if the folder still contains notes or subfolders
+    pub async fn delete_folder(&self, name: &str) -> Result<()> {
+        apple_imap::with_session(&self.profile, |imap_service| {
+            folder::delete_folder(imap_service, &self.db_connection, name)
+        }).await
+    }
+
+    /// Copies all note folders into a new, timestamped backup folder on the imap server.
+    /// on_progress gets called after every folder with the folder name, the count of copied
+    /// messages, the count of processed folders and the total folder count.
+    ///
+    /// Returns the name of the created backup
+    pub async fn backup<F>(&self, on_progress: F) -> Result<String>
+        where F: FnMut(&str, u32, usize, usize)
+    {
+        let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        apple_imap::with_session(&self.profile, |imap_service| {
+            backup::backup(imap_service, &timestamp, on_progress)
+        }).await
+    }
+
+    /// Lists the names of all backups that exist on the imap server
+    pub async fn list_backups(&self) -> Result<Vec<String>> {
+        apple_imap::with_session(&self.profile, |imap_service| {
+            backup::list_backups(imap_service)
+        }).await
+    }
+
+    /// Writes all notes as markdown files with front matter into dir,
+    /// works without a connection to the imap server. Returns the count of written files
+    pub fn export(&self, dir: &std::path::Path) -> Result<usize> {
+        self.db_connection.fetch_all_notes()
+            .map_err(|e| e.into())
+            .and_then(|notes| export::export_notes(&notes, dir))
+    }
+
     pub fn get_notes(&self) -> Result<HashSet<LocalNote, RandomState>> {
         self.db_connection.fetch_all_notes().map_err(|e| e.into())
     }
 
+    /// Full-text search over all note bodies, returns the matching notes ranked by relevance
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        self.db_connection.search(query).map_err(|e| e.into())
+    }
+
+    /// Returns all notes that contain unchecked checklist items, sorted by folder and subject
+    pub fn todos(&self) -> Result<Vec<Todo>> {
+        let notes = self.db_connection.fetch_all_notes()?;
+        let mut todos: Vec<Todo> = notes.into_iter()
+            .filter(|note| note.metadata.locally_deleted == false)
+            .map(|note| {
+                let open_items = note.body.iter()
+                    .flat_map(|body| checklist::open_items(body.text.as_deref().unwrap_or("")))
+                    .collect();
+                Todo { note, open_items }
+            })
+            .filter(|todo| todo.open_items.is_empty() == false)
+            .collect();
+        todos.sort_by_key(|todo| (todo.note.metadata.subfolder.clone(), todo.note.first_subject()));
+        Ok(todos)
+    }
+
     pub fn update_note(&self, note: &LocalNote) -> Result<()> {
         self.db_connection.update(note).map_err(|e| e.into())
     }
 
 }
 
-pub fn get_user_profile() -> Result<Profile> {
-    profile::load_profile()
+/// Loads the profile with the passed name, or the default profile if no name is passed
+pub fn get_user_profile(name: Option<&str>) -> Result<Profile> {
+    profile::load_profile(name)
+}
+
+/// Returns the names of all profiles inside the config file
+pub fn get_profile_names() -> Result<Vec<String>> {
+    profile::profile_names()
+}
+
+/// Reads and validates the config file, errors contain the line of the invalid entry
+pub fn check_config() -> Result<LoadedConfig> {
+    profile::load_config()
+}
+
+/// Converts the old key=value config file into a toml config file, returns the path of the new file
+pub fn migrate_config() -> Result<std::path::PathBuf> {
+    profile::migrate_config()
+}
+
+/// Returns the local notes of every profile, grouped by profile name
+pub fn get_notes_of_all_profiles() -> Result<Vec<(String, HashSet<LocalNote>)>> {
+    profile::profile_names()?.into_iter().map(|name| {
+        let apple_notes = AppleNotes::for_profile(profile::load_profile(Some(&name))?);
+        apple_notes.get_notes().map(|notes| (name, notes))
+    }).collect()
+}
+
+/// Notifies on_change every time the notes changed remotely and after every (re)connect,
+/// without syncing them. Returns as soon as on_change returns false
+pub async fn watch_remote_changes<F>(profile: &Profile, mut on_change: F) -> Result<()>
+    where F: FnMut() -> bool
+{
+    watch::watch(profile, |_| on_change()).await
 }
\ No newline at end of file
@@ -1,13 +1,321 @@
+use crate::notes::localnote::LocalNote;
 
+/// Outcome of a three-way merge
+pub struct MergeResult {
+    pub text: String,
+    /// Number of regions that got changed on both sides and
+    /// could not be merged automatically
+    pub conflicts: usize
+}
 
-pub fn merge_two(first: &str, second: &str) -> String {
-    diff::lines(first, second).iter().map( { |diff|
-        match diff {
-            diff::Result::Left(l) => { format!("< {}", l) },
-            diff::Result::Both(l, _) => format!("{}", l),
-            diff::Result::Right(r)   => format!("> {}", r)
+/// Merges local and remote by using base as common ancestor
+///
+/// Regions that got changed on only one side are taken over
+/// automatically, regions that got changed differently on
+/// both sides get wrapped in git-style conflict markers
+pub fn merge_three(base: &str, local: &str, remote: &str, local_label: &str, remote_label: &str) -> MergeResult {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let local_lines: Vec<&str> = local.lines().collect();
+    let remote_lines: Vec<&str> = remote.lines().collect();
+
+    let local_matches = matching_lines(&base_lines, &local_lines);
+    let remote_matches = matching_lines(&base_lines, &remote_lines);
+
+    let mut output: Vec<&str> = Vec::new();
+    let mut conflicts = 0;
+    let (mut b, mut l, mut r) = (0, 0, 0);
+
+    let local_marker = format!("<<<<<<< {}", local_label);
+    let remote_marker = format!(">>>>>>> {}", remote_label);
+
+    loop {
+        // Copy every line that did not change on both sides
+        while b < base_lines.len()
+            && local_matches[b] == Some(l)
+            && remote_matches[b] == Some(r) {
+            output.push(base_lines[b]);
+            b += 1;
+            l += 1;
+            r += 1;
+        }
+
+        if b == base_lines.len() && l == local_lines.len() && r == remote_lines.len() {
+            break;
+        }
+
+        // Everything up to the next line that is present on all sides is a changed chunk
+        let (next_b, next_l, next_r) = match (b..base_lines.len())
+            .find(|&i| local_matches[i].is_some() && remote_matches[i].is_some()) {
+            Some(i) => (i, local_matches[i].unwrap(), remote_matches[i].unwrap()),
+            None => (base_lines.len(), local_lines.len(), remote_lines.len())
+        };
+
+        let base_chunk = &base_lines[b..next_b];
+        let local_chunk = &local_lines[l..next_l];
+        let remote_chunk = &remote_lines[r..next_r];
+
+        if local_chunk == base_chunk {
+            output.extend(remote_chunk);
+        } else if remote_chunk == base_chunk || local_chunk == remote_chunk {
+            output.extend(local_chunk);
+        } else {
+            conflicts += 1;
+            output.push(&local_marker);
+            output.extend(local_chunk);
+            output.push("=======");
+            output.extend(remote_chunk);
+            output.push(&remote_marker);
+        }
+
+        b = next_b;
+        l = next_l;
+        r = next_r;
+    }
+
+    MergeResult {
+        text: output.iter().map(|line| format!("{}\n", line)).collect(),
+        conflicts
+    }
+}
+
+/// Merges every passed body into a single conflict document
+///
+/// Every body is a tuple of a label and the text of the body. Every body gets
+/// compared once against base, without a common ancestor the first body is used
+/// as reference. Regions that got changed in only one way are taken over, all
+/// other regions become a single conflict block with every distinct version
+/// (see resolve_region):
+///
+/// <<<<<<< labels of the first version
+/// ...
+/// ======= labels of the second version
+/// ...
+/// >>>>>>>
+pub fn merge_all(base: Option<&str>, bodies: &[(&str, &str)]) -> String {
+    match bodies {
+        [] => return String::new(),
+        [(_, text)] => return text.to_string(),
+        _ => {}
+    }
+
+    let lines: Vec<Vec<&str>> = bodies.iter().map(|(_, text)| text.lines().collect()).collect();
+    let reference: Vec<&str> = match base {
+        Some(base) => base.lines().collect(),
+        None => lines[0].clone()
+    };
+    let matches: Vec<Vec<Option<usize>>> = lines.iter()
+        .map(|other| matching_lines(&reference, other))
+        .collect();
+
+    let mut output: Vec<String> = Vec::new();
+    let mut r = 0;
+    // Current line of every body
+    let mut positions = vec![0; lines.len()];
+
+    loop {
+        // Copy every line that did not change in any body
+        while r < reference.len()
+            && matches.iter().zip(&positions).all(|(m, position)| m[r] == Some(*position)) {
+            output.push(reference[r].to_string());
+            r += 1;
+            positions.iter_mut().for_each(|position| *position += 1);
+        }
+
+        if r == reference.len() && positions.iter().zip(&lines).all(|(position, lines)| *position == lines.len()) {
+            break;
+        }
+
+        // Everything up to the next line that is present in all bodies is a changed region
+        let (next_r, next): (usize, Vec<usize>) = match (r..reference.len())
+            .find(|&i| matches.iter().all(|m| m[i].is_some())) {
+            Some(i) => (i, matches.iter().map(|m| m[i].unwrap()).collect()),
+            None => (reference.len(), lines.iter().map(|lines| lines.len()).collect())
+        };
+
+        let reference_chunk = &reference[r..next_r];
+        let chunks: Vec<&[&str]> = lines.iter().enumerate()
+            .map(|(i, lines)| &lines[positions[i]..next[i]])
+            .collect();
+
+        // If every body only replaced lines, every line can be resolved on its own
+        if chunks.iter().all(|chunk| chunk.len() == reference_chunk.len()) {
+            for k in 0..reference_chunk.len() {
+                let line_chunks: Vec<&[&str]> = chunks.iter().map(|chunk| &chunk[k..k + 1]).collect();
+                output.extend(resolve_region(base.is_some(), &reference_chunk[k..k + 1], &line_chunks, bodies));
+            }
+        } else {
+            output.extend(resolve_region(base.is_some(), reference_chunk, &chunks, bodies));
+        }
+
+        r = next_r;
+        positions = next;
+    }
+
+    output.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Returns the last synchronized text of note, that is used as common ancestor
+/// of its bodies. The ancestor of the locally edited body is preferred
+pub fn base_text(note: &LocalNote) -> Option<String> {
+    note.body.iter()
+        .filter(|body| body.old_remote_message_id.is_some())
+        .chain(note.body.iter())
+        .find_map(|body| body.base_text.clone())
+}
+
+/// Merges all bodies of note into a single conflict document, the
+/// message-ids of the bodies are used as labels, see merge_all
+pub fn merge_note(note: &LocalNote) -> String {
+    let base = base_text(note);
+    let bodies: Vec<(&str, &str)> = note.body.iter()
+        .map(|body| (body.message_id.as_str(), body.text.as_deref().unwrap_or("")))
+        .collect();
+
+    merge_all(base.as_deref(), &bodies)
+}
+
+/// Returns the lines of a changed region, chunks contains the region of every body
+///
+/// With a common ancestor bodies that did not change the region have no version of
+/// their own. If only one distinct version is left it gets taken over, otherwise all
+/// versions end up in a conflict block
+fn resolve_region(has_base: bool, reference_chunk: &[&str], chunks: &[&[&str]], bodies: &[(&str, &str)]) -> Vec<String> {
+    // Every distinct version together with the labels of the bodies containing it
+    let mut versions: Vec<(&[&str], Vec<&str>)> = Vec::new();
+    for (chunk, (label, _)) in chunks.iter().zip(bodies) {
+        if has_base && *chunk == reference_chunk {
+            continue;
+        }
+        match versions.iter_mut().find(|(version, _)| version == chunk) {
+            Some((_, labels)) => labels.push(*label),
+            None => versions.push((*chunk, vec![*label]))
         }
-    })
-        .map(|string| format!("{}\n", string))
-        .collect()
-}
\ No newline at end of file
+    }
+
+    match versions.as_slice() {
+        [] => reference_chunk.iter().map(|line| line.to_string()).collect(),
+        [(chunk, _)] => chunk.iter().map(|line| line.to_string()).collect(),
+        _ => {
+            let mut output = Vec::new();
+            for (i, (chunk, labels)) in versions.iter().enumerate() {
+                let marker = if i == 0 { "<<<<<<<" } else { "=======" };
+                output.push(format!("{} {}", marker, labels.join(",")));
+                output.extend(chunk.iter().map(|line| line.to_string()));
+            }
+            output.push(">>>>>>>".to_string());
+            output
+        }
+    }
+}
+
+/// Returns for every line of base the index of the matching
+/// line inside other, or None if the line got changed or removed
+fn matching_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; base.len()];
+    let (mut b, mut o) = (0, 0);
+    for result in diff::slice(base, other) {
+        match result {
+            diff::Result::Left(_) => b += 1,
+            diff::Result::Right(_) => o += 1,
+            diff::Result::Both(_, _) => {
+                matches[b] = Some(o);
+                b += 1;
+                o += 1;
+            }
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use crate::merge::{merge_three, merge_all};
+
+    /// Changes on different lines should get merged without conflicts
+    #[test]
+    fn merge_non_overlapping_changes() {
+        let result = merge_three(
+            "title\nfirst\nsecond\nthird",
+            "title\nFIRST\nsecond\nthird",
+            "title\nfirst\nsecond\nthird\nfourth",
+            "local",
+            "remote"
+        );
+
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "title\nFIRST\nsecond\nthird\nfourth\n");
+    }
+
+    /// Same line changed differently on both sides should result in a conflict
+    #[test]
+    fn merge_overlapping_changes() {
+        let result = merge_three(
+            "title\nfirst\nsecond",
+            "title\nlocal change\nsecond",
+            "title\nremote change\nsecond",
+            "local",
+            "remote"
+        );
+
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(result.text, "title\n<<<<<<< local\nlocal change\n=======\nremote change\n>>>>>>> remote\nsecond\n");
+    }
+
+    /// Identical changes on both sides are no conflict
+    #[test]
+    fn merge_identical_changes() {
+        let result = merge_three("a\nb", "a\nc", "a\nc", "local", "remote");
+
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "a\nc\n");
+    }
+
+    /// Three bodies that changed different lines should end up in one document
+    #[test]
+    fn merge_three_bodies() {
+        let merged = merge_all(Some("title\na\nb\nc"), &[
+            ("1", "title\nA\nb\nc"),
+            ("2", "title\na\nB\nc"),
+            ("3", "title\na\nb\nC")
+        ]);
+
+        assert_eq!(merged, "title\nA\nB\nC\n");
+    }
+
+    /// Without a common ancestor every differing region becomes one conflict block
+    /// with every distinct version of the region
+    #[test]
+    fn merge_three_bodies_without_base() {
+        let merged = merge_all(None, &[
+            ("1", "title\na\nmiddle\nb"),
+            ("2", "title\nA\nmiddle\nb"),
+            ("3", "title\na\nmiddle\nB")
+        ]);
+
+        assert_eq!(merged, "title\n\
+            <<<<<<< 1,3\na\n======= 2\nA\n>>>>>>>\n\
+            middle\n\
+            <<<<<<< 1,2\nb\n======= 3\nB\n>>>>>>>\n");
+    }
+
+    /// Two bodies use the same conflict format as more bodies
+    #[test]
+    fn merge_two_bodies_without_base() {
+        let merged = merge_all(None, &[("1", "title\na"), ("2", "title\nb")]);
+
+        assert_eq!(merged, "title\n<<<<<<< 1\na\n======= 2\nb\n>>>>>>>\n");
+    }
+
+    /// Every body gets merged against the base once, so conflict markers never nest
+    #[test]
+    fn merge_conflicting_bodies_with_base() {
+        let merged = merge_all(Some("title\na\nb"), &[
+            ("1", "title\nx\nb"),
+            ("2", "title\ny\nb"),
+            ("3", "title\nz\nb"),
+            ("4", "title\na\nB")
+        ]);
+
+        assert_eq!(merged, "title\n<<<<<<< 1\nx\n======= 2\ny\n======= 3\nz\n>>>>>>>\nB\n");
+    }
+}
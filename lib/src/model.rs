@@ -1,6 +1,11 @@
 use crate::util;
 use crate::schema::metadata;
 use crate::schema::body;
+use crate::schema::folder_sync_state;
+use crate::schema::remote_header;
+use crate::schema::attachment;
+use crate::schema::sync_journal;
+use crate::schema::body_history;
 #[cfg(test)]
 use crate::notes::localnote::LocalNote;
 use std::hash::Hasher;
@@ -37,6 +42,10 @@ pub struct NotesMetadata {
     /// edited on device2.
     pub uuid: String,
     pub mime_version: String,
+    /// Folder in which the note is stored remotely, only present
+    /// if the note got moved locally and the move did not get
+    /// synchronized yet
+    pub moved_from: Option<String>,
 }
 
 impl NotesMetadata {
@@ -49,6 +58,7 @@ impl NotesMetadata {
             date: header.date(),
             uuid: header.uuid(),
             mime_version: header.mime_version(),
+            moved_from: None,
         }
     }
 
@@ -60,7 +70,8 @@ impl NotesMetadata {
             edited: false,
             date: remote_metadata.headers.date(),
             uuid: remote_metadata.headers.uuid(),
-            mime_version: remote_metadata.headers.mime_version()
+            mime_version: remote_metadata.headers.mime_version(),
+            moved_from: None
         }
     }
 
@@ -93,6 +104,96 @@ impl std::hash::Hash for NotesMetadata {
     }
 }
 
+/// Snapshot of an imap folder, taken at the last synchronization
+///
+/// Used to only fetch the headers of messages that got added since then,
+/// if uid_validity changed all stored uids are invalid
+#[derive(Identifiable,Clone,Queryable,Insertable,Debug,PartialEq)]
+#[table_name="folder_sync_state"]
+#[primary_key(folder)]
+pub struct FolderSyncState {
+    pub folder: String,
+    pub uid_validity: i64,
+    /// Uid that the next message appended to the folder will get
+    pub uid_next: i64,
+    /// Only present if the server supports CONDSTORE
+    pub highest_modseq: Option<i64>,
+    /// Count of messages inside the folder
+    pub messages: i64
+}
+
+/// Locally cached headers of a remote message
+#[derive(Clone,Queryable,Insertable,Debug)]
+#[table_name="remote_header"]
+pub struct CachedHeader {
+    pub folder: String,
+    pub uid: i64,
+    /// Headers of the message, serialized as json
+    pub headers: String
+}
+
+/// Image or file that is embedded into a note, the content
+/// is stored inside the blob store
+#[derive(Identifiable,Clone,Queryable,Insertable,Debug,PartialEq)]
+#[table_name="attachment"]
+#[primary_key(metadata_uuid, content_id)]
+pub struct Attachment {
+    /// Content-ID of the mime part, the html of the note references
+    /// the attachment with "cid:<content_id>". It is only unique within a note
+    pub content_id: String,
+    pub metadata_uuid: String,
+    pub file_name: String,
+    pub mime_type: String,
+    /// Name of the file inside the blob store
+    pub blob: String
+}
+
+/// Action of a running synchronization, the entry gets removed once the
+/// imap server and the database are changed. Left over entries belong to
+/// an interrupted synchronization, see journal::recover
+#[derive(Identifiable,Clone,Queryable,Insertable,Debug,PartialEq)]
+#[table_name="sync_journal"]
+#[primary_key(uuid)]
+pub struct JournalEntry {
+    /// Uuid of the note the action belongs to
+    pub uuid: String,
+    /// Name of the UpdateAction
+    pub action: String,
+    /// Either journal::PLANNED or journal::REMOTE_DONE
+    pub phase: String,
+    /// Folder the message gets written into or removed from
+    pub folder: String,
+    /// Message-ID of the message the action works on
+    pub message_id: String,
+    /// Uid of the written message, known once the imap server got changed
+    pub uid: Option<i64>
+}
+
+/// Previous text of a note body, recorded every time the text gets replaced
+#[derive(Identifiable,Clone,Queryable,Debug,PartialEq)]
+#[table_name="body_history"]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub metadata_uuid: String,
+    /// Message-ID of the body that contained the text
+    pub message_id: String,
+    pub text: String,
+    /// Point in time the text got replaced, rfc2822 formatted
+    pub date: String,
+    /// One of history::LOCAL_EDIT, history::REMOTE_UPDATE or history::MERGE
+    pub source: String
+}
+
+#[derive(Insertable,Debug)]
+#[table_name="body_history"]
+pub struct NewHistoryEntry<'a> {
+    pub metadata_uuid: &'a str,
+    pub message_id: &'a str,
+    pub text: &'a str,
+    pub date: String,
+    pub source: &'a str
+}
+
 #[derive(Identifiable,Clone,Queryable,Insertable,Associations,Debug,Eq)]
 #[table_name="body"]
 #[belongs_to(NotesMetadata, foreign_key="metadata_uuid")]
@@ -115,7 +216,14 @@ pub struct Body {
     pub uid: Option<i64>,
     /// Foreign key to a Metadata Object, every Metadata
     /// Object can have n Bodies
-    pub metadata_uuid: String
+    pub metadata_uuid: String,
+    /// Text of the last synchronized version this body was
+    /// derived from. Only present if the body got edited locally,
+    /// used as common ancestor while merging conflicting bodies.
+    pub base_text: Option<String>,
+    /// Html of the message as it is stored remotely, used to keep the
+    /// formatting of all text blocks that did not change while editing
+    pub html: Option<String>
 }
 
 impl Body {
@@ -127,7 +235,9 @@ impl Body {
             message_id: format!("<{}@{}", util::generate_uuid(), profile.domain),
             text: None,
             uid,
-            metadata_uuid: metadata_reference
+            metadata_uuid: metadata_reference,
+            base_text: None,
+            html: None
         }
     }
 
@@ -138,7 +248,9 @@ impl Body {
             message_id: format!("<{}@{}", util::generate_uuid(), "test@test.de".to_string()),
             text: None,
             uid,
-            metadata_uuid: metadata_reference
+            metadata_uuid: metadata_reference,
+            base_text: None,
+            html: None
         }
     }
 
@@ -50,16 +50,25 @@ impl LocalNote {
     pub fn to_remote_metadata(&self) -> RemoteNoteMetaData {
 
         let profile = Profile {
+            name: "default".to_string(),
             username: "test".to_string(),
             password: Option::from("test".to_string()),
             imap_server: "test".to_string(),
+            port: 993,
+            security: crate::config::Security::Tls,
+            ca_file: None,
             email: "test@test.de".to_string(),
             editor: "test".to_string(),
             editor_arguments: vec![],
             secret_service_attribute: None,
             secret_service_value: None,
+            password_command: None,
+            password_file: None,
+            oauth2_token_url: None,
+            oauth2_client_id: None,
+            oauth2_client_secret: None,
             domain: "".to_string(),
-            password_type: "".to_string()
+            password_type: crate::config::PasswordType::Plain
         };
 
         RemoteNoteMetaData {
@@ -89,6 +98,28 @@ impl LocalNote {
             .count() != self.body.len()
     }
 
+    /// Checks if the note got moved to another folder remotely, or if its uid changed
+    ///
+    /// Notes that got moved locally are ignored, the local move takes precedence
+    pub fn moved_remotely(&self, remote_metadata: &RemoteNoteHeaderCollection) -> bool {
+        if self.needs_merge() || remote_metadata.len() != 1 || self.metadata.moved_from.is_some() {
+            return false;
+        }
+
+        let remote_note = remote_metadata.first().unwrap();
+
+        remote_note.folder != self.metadata.subfolder
+            || self.body[0].uid.map(|uid| uid != remote_note.uid).unwrap_or(false)
+    }
+
+    /// Returns the folder in which the note is currently stored on the imap server,
+    /// differs from the local folder if the note got moved locally
+    pub fn remote_folder(&self) -> String {
+        let folder = self.metadata.moved_from.as_ref().unwrap_or(&self.metadata.subfolder);
+        let decoded = quoted_printable::decode(folder, ParseMode::Robust).unwrap();
+        String::from_utf8(decoded).unwrap()
+    }
+
     pub fn all_old_message_ids(&self) -> Option<HashSet<String>> {
         if self.needs_merge() == false && self.content_changed_locally() {
             return Some(self.body.first().unwrap().old_remote_message_id.clone().unwrap().split(",").map(|e| e.to_string()).collect());
@@ -3,4 +3,9 @@ pub(crate) mod grouped_remote_note_headers;
 pub(crate) mod remote_note_metadata;
 pub(crate) mod remote_note_header_collection;
 pub(crate) mod note_headers;
-pub mod localnote;
\ No newline at end of file
+pub(crate) mod note_content;
+pub mod localnote;
+pub mod search_result;
+pub mod note_folder;
+pub mod todo;
+pub mod revision;
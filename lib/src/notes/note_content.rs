@@ -0,0 +1,16 @@
+/// Content of a note message, the html body and all mime parts it references
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteContent {
+    pub html: String,
+    pub attachments: Vec<RemoteAttachment>
+}
+
+/// Mime part of a note message, e.g. an inline image
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteAttachment {
+    /// Content-ID without angle brackets
+    pub content_id: String,
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>
+}
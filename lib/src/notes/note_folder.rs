@@ -0,0 +1,13 @@
+/// A folder that contains notes, either locally, remotely or on both sides
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteFolder {
+    /// Name of the folder, nested folders are separated with "/",
+    /// the default folder has an empty name
+    pub name: String,
+    /// Name of the folder on the imap server
+    pub imap_name: String,
+    /// Count of local notes inside this folder, without subfolders
+    pub note_count: usize,
+    /// Indicates if the folder exists on the imap server
+    pub remote: bool
+}
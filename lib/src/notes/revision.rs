@@ -0,0 +1,11 @@
+/// Previous text of a note, revisions are numbered per note starting
+/// with 1 for the oldest recorded text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub number: usize,
+    /// Point in time the text got replaced, rfc2822 formatted
+    pub date: String,
+    /// How the text got replaced, either "local edit", "remote update" or "merge"
+    pub source: String,
+    pub text: String
+}
@@ -0,0 +1,9 @@
+use crate::notes::localnote::LocalNote;
+
+/// A note that matched a full-text search query
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub note: LocalNote,
+    /// Excerpt of the matching text, matches are wrapped in brackets
+    pub snippet: String
+}
@@ -0,0 +1,9 @@
+use crate::notes::localnote::LocalNote;
+
+/// A note that contains unchecked checklist items
+#[derive(Clone, Debug)]
+pub struct Todo {
+    pub note: LocalNote,
+    /// Text of all unchecked items, in the order they appear in the note
+    pub open_items: Vec<String>
+}
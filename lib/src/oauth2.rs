@@ -0,0 +1,193 @@
+extern crate curl;
+extern crate serde_json;
+
+use self::curl::easy::Easy;
+use serde_derive::Deserialize;
+use imap::Authenticator;
+use crate::error::ProfileError::OAuth2Error;
+use crate::error::Result;
+#[cfg(target_family = "unix")]
+use crate::profile::Profile;
+
+/// Successful response of the token endpoint, see RFC 6749 section 5.1
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenResponse {
+    pub access_token: String,
+    /// Only present if the provider rotates refresh tokens
+    pub refresh_token: Option<String>,
+    #[allow(dead_code)]
+    pub expires_in: Option<u64>
+}
+
+/// Error response of the token endpoint, see RFC 6749 section 5.2
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>
+}
+
+/// SASL XOAUTH2 mechanism, as used by gmail and outlook
+pub(crate) struct XOAuth2<'a> {
+    pub user: &'a str,
+    pub access_token: &'a str
+}
+
+impl<'a> Authenticator for XOAuth2<'a> {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        // If the token got rejected the server sends the error as challenge,
+        // that one has to be answered with an empty response
+        if challenge.is_empty() {
+            format!("user={}\x01auth=Bearer {}\x01\x01", self.user, self.access_token)
+        } else {
+            warn!("XOAUTH2 login failed: {}", String::from_utf8_lossy(challenge));
+            String::new()
+        }
+    }
+}
+
+/// Requests a new access token with the refresh token stored in the secret service.
+/// If the provider hands out a new refresh token it replaces the stored one.
+#[cfg(target_family = "unix")]
+pub(crate) async fn access_token(profile: &Profile) -> Result<String> {
+    let token_url = profile.oauth2_token_url.as_deref()
+        .ok_or(OAuth2Error("No oauth2_token_url configured".to_string()))?;
+    let client_id = profile.oauth2_client_id.as_deref()
+        .ok_or(OAuth2Error("No oauth2_client_id configured".to_string()))?;
+
+    let refresh_token = profile.secret_service_get_pw().await?;
+    let response = refresh_access_token(token_url, client_id, profile.oauth2_client_secret.as_deref(), &refresh_token)?;
+
+    if let Some(new_refresh_token) = &response.refresh_token {
+        if new_refresh_token != &refresh_token {
+            info!("Storing rotated refresh token");
+            profile.secret_service_store_pw(new_refresh_token).await?;
+        }
+    }
+
+    Ok(response.access_token)
+}
+
+/// Exchanges the refresh token for an access token at the token endpoint
+pub(crate) fn refresh_access_token(token_url: &str,
+                                   client_id: &str,
+                                   client_secret: Option<&str>,
+                                   refresh_token: &str) -> Result<TokenResponse> {
+    info!("Requesting access token from {}", token_url);
+
+    let mut easy = Easy::new();
+
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", client_id)
+    ];
+    if let Some(client_secret) = client_secret {
+        form.push(("client_secret", client_secret));
+    }
+    let body = form.iter()
+        .map(|(key, value)| format!("{}={}", key, easy.url_encode(value.as_bytes())))
+        .collect::<Vec<String>>()
+        .join("&");
+
+    easy.url(token_url)?;
+    easy.post(true)?;
+    easy.post_fields_copy(body.as_bytes())?;
+
+    let mut response = Vec::new();
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            response.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+
+    let status = easy.response_code()?;
+    if status != 200 {
+        let message = match serde_json::from_slice::<TokenErrorResponse>(&response) {
+            Ok(error) => format!("{} {}", error.error, error.error_description.unwrap_or_default()),
+            Err(_) => String::from_utf8_lossy(&response).to_string()
+        };
+        return Err(OAuth2Error(format!("Token endpoint returned {}: {}", status, message.trim())).into());
+    }
+
+    serde_json::from_slice(&response)
+        .map_err(|e| OAuth2Error(format!("Invalid token response: {}", e)).into())
+}
+
+#[cfg(test)]
+mod oauth2_tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+    use imap::Authenticator;
+    use crate::error::ErrorCode;
+    use crate::oauth2::*;
+
+    /// Answers a single http request with the passed status and body,
+    /// the thread returns the body of the request
+    fn mock_token_endpoint(status: &'static str, body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut content_length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+                line.clear();
+            }
+            let mut request = vec![0; content_length];
+            reader.read_exact(&mut request).unwrap();
+
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn refresh_token() {
+        let (url, handle) = mock_token_endpoint("200 OK",
+            r#"{"access_token":"ya29.token","expires_in":3599,"token_type":"Bearer","refresh_token":"1//new"}"#);
+
+        let response = refresh_access_token(&url, "apnotes", Some("s3cr/t"), "1//old").unwrap();
+        assert_eq!(response.access_token, "ya29.token");
+        assert_eq!(response.refresh_token, Some("1//new".to_string()));
+
+        let request = handle.join().unwrap();
+        assert!(request.contains("grant_type=refresh_token"));
+        assert!(request.contains("refresh_token=1%2F%2Fold"));
+        assert!(request.contains("client_id=apnotes"));
+        assert!(request.contains("client_secret=s3cr%2Ft"));
+    }
+
+    #[test]
+    fn rejected_refresh_token() {
+        let (url, handle) = mock_token_endpoint("400 Bad Request",
+            r#"{"error":"invalid_grant","error_description":"Token has been expired or revoked."}"#);
+
+        let error = refresh_access_token(&url, "apnotes", None, "1//old").err().unwrap();
+        assert_eq!(error.error_code(), 9);
+        assert!(error.to_string().contains("invalid_grant"));
+        assert!(handle.join().unwrap().contains("client_secret") == false);
+    }
+
+    #[test]
+    fn xoauth2() {
+        let authenticator = XOAuth2 { user: "me@test.de", access_token: "ya29.token" };
+        assert_eq!(authenticator.process(b""), "user=me@test.de\x01auth=Bearer ya29.token\x01\x01");
+        assert_eq!(authenticator.process(br#"{"status":"401"}"#), "");
+    }
+}
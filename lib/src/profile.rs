@@ -5,10 +5,9 @@ extern crate regex;
 extern crate log;
 
 use std::collections::HashMap;
-use self::regex::Regex;
 use std::fs::File;
 use self::log::{warn};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::error::ProfileError::*;
 use std::str;
 use crate::error::Result;
@@ -21,13 +20,23 @@ use self::xdg::BaseDirectories;
 #[cfg(target_family = "unix")]
 use secret_service::{SecretService, EncryptionType};
 use crate::error::ProfileError::{NoAttributeProvided, NoEntryFound, NoValueProvided};
+use crate::config;
+use subprocess::{Exec, Redirection};
+#[cfg(target_family = "unix")]
+use crate::oauth2;
+use crate::config::{Config, LoadedConfig, PasswordType, Security, DEFAULT_PROFILE};
 
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Profile {
+    /// Name of the config section, every profile has its own database
+    pub(crate) name: String,
     pub(crate) username: String,
-    pub(crate) password_type: String,
+    pub(crate) password_type: PasswordType,
     pub(crate) imap_server: String,
+    pub(crate) port: u16,
+    pub(crate) security: Security,
+    pub(crate) ca_file: Option<PathBuf>,
     pub(crate) email: String,
     pub(crate) editor: String,
     pub(crate) editor_arguments: Vec<String>,
@@ -35,6 +44,14 @@ pub struct Profile {
     pub(crate) secret_service_attribute: Option<String>,
     #[allow(dead_code)]
     pub(crate) secret_service_value: Option<String>,
+    pub(crate) password_command: Option<String>,
+    pub(crate) password_file: Option<PathBuf>,
+    #[allow(dead_code)]
+    pub(crate) oauth2_token_url: Option<String>,
+    #[allow(dead_code)]
+    pub(crate) oauth2_client_id: Option<String>,
+    #[allow(dead_code)]
+    pub(crate) oauth2_client_secret: Option<String>,
     #[allow(dead_code)]
     pub(crate) domain: String,
 
@@ -43,26 +60,34 @@ pub struct Profile {
 
 impl Profile {
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     #[cfg(target_family = "unix")]
     pub async fn get_password(&self) -> Result<String> {
-        if self.password_type == "PLAIN" {
-            Ok(self.password.as_ref().unwrap().clone())
-        } else {
-            self.secret_service_get_pw().await
+        match self.password_type {
+            PasswordType::Plain => Ok(self.password.as_ref().unwrap().clone()),
+            PasswordType::SecretService => self.secret_service_get_pw().await,
+            // The secret service holds the refresh token, every login requests a new access token
+            PasswordType::OAuth2 => oauth2::access_token(self).await,
+            PasswordType::Command => run_password_command(self.password_command.as_ref().ok_or(PasswordCommandFailed("No password_command configured".to_string()))?),
+            PasswordType::GpgFile => decrypt_password_file(self.password_file.as_ref().ok_or(PasswordFileNotFound("No password_file configured".to_string()))?)
         }
     }
 
     #[cfg(target_family = "windows")]
     pub fn get_password(&self) -> Result<String> {
-        if self.password_type == "PLAIN" {
-            Ok(self.password.as_ref().unwrap().clone())
-        } else {
-            panic!("Password type {} not supported", self.password_type)
+        match self.password_type {
+            PasswordType::Plain => Ok(self.password.as_ref().unwrap().clone()),
+            PasswordType::Command => run_password_command(self.password_command.as_ref().ok_or(PasswordCommandFailed("No password_command configured".to_string()))?),
+            PasswordType::GpgFile => decrypt_password_file(self.password_file.as_ref().ok_or(PasswordFileNotFound("No password_file configured".to_string()))?),
+            _ => panic!("Password type {:?} not supported", self.password_type)
         }
     }
 
     #[cfg(target_family = "unix")]
-    async fn secret_service_get_pw(&self) -> Result<String> {
+    pub(crate) async fn secret_service_get_pw(&self) -> Result<String> {
         let ss = SecretService::connect(EncryptionType::Dh).await?;
 
         let collection = ss.get_default_collection().await?;
@@ -88,43 +113,116 @@ impl Profile {
 
         return Ok(str::from_utf8(&entry)?.to_string());
     }
+
+    /// Stores the secret under the configured attribute, an existing entry gets replaced
+    #[cfg(target_family = "unix")]
+    pub(crate) async fn secret_service_store_pw(&self, secret: &str) -> Result<()> {
+        let ss = SecretService::connect(EncryptionType::Dh).await?;
+
+        let collection = ss.get_default_collection().await?;
+
+        collection.ensure_unlocked().await.map_err(|_| ProfileError::AgentLocked())?;
+
+        let attribute = self.secret_service_attribute.as_ref().ok_or(NoAttributeProvided())?;
+        let value = self.secret_service_value.as_ref().ok_or(NoValueProvided())?;
+
+        let map = HashMap::from([(attribute.as_str(), value.as_str())]);
+        let label = format!("apnotes {}", self.name);
+
+        collection.create_item(&label, map, secret.as_bytes(), true, "text/plain").await?;
+        Ok(())
+    }
 }
 
+/// Runs the command with the shell and returns the first line of its output,
+/// like "pass show mail/icloud" does
+fn run_password_command(command: &str) -> Result<String> {
+    let output = Exec::shell(command)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .map_err(|e| PasswordCommandFailed(format!("Could not run \"{}\": {}", command, e)))?;
+
+    if output.exit_status.success() == false {
+        return Err(PasswordCommandFailed(format!("\"{}\" exited with {:?}: {}", command, output.exit_status, output.stderr_str().trim())).into());
+    }
+
+    first_line(&output.stdout_str())
+}
+
+/// Decrypts the file with gpg and returns its first line, files of the password store
+/// can be passed directly
+fn decrypt_password_file(path: &Path) -> Result<String> {
+    if path.exists() == false {
+        return Err(PasswordFileNotFound(path.display().to_string()).into());
+    }
+
+    let output = Exec::cmd("gpg")
+        .args(&["--batch", "--quiet", "--decrypt"])
+        .arg(path)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .map_err(|e| DecryptionFailed(format!("Could not run gpg: {}", e)))?;
+
+    if output.exit_status.success() == false {
+        return Err(DecryptionFailed(format!("{}: {}", path.display(), output.stderr_str().trim())).into());
+    }
+
+    first_line(&output.stdout_str())
+}
+
+fn first_line(output: &str) -> Result<String> {
+    match output.lines().next().map(|line| line.trim_end()) {
+        Some(line) if line.is_empty() == false => Ok(line.to_string()),
+        _ => Err(EmptyPassword().into())
+    }
+}
+
+/// Path of the toml config file, the file does not need to exist
 #[cfg(target_family = "unix")]
 pub(crate)  fn get_config_path() -> Result<PathBuf> {
-        let xdg_dir = BaseDirectories::new()?;
-        match xdg_dir.find_config_file("apnotes/config") {
-            Some(path) => Ok(path),
-            None => {
-                warn!("Could not detect config file, gonna create empty one");
-                let mut path = xdg_dir.create_config_directory("apnotes")?;
-                path.push("config");
-                File::create(&path).expect("Unable to create file");
-                Ok(path.to_path_buf())
-            }
-        }
+    let xdg_dir = BaseDirectories::new()?;
+    xdg_dir.place_config_file("apnotes/config.toml").map_err(|e| e.into())
+}
+
+/// Path of the key=value config file that was used before the toml config existed
+#[cfg(target_family = "unix")]
+pub(crate)  fn get_legacy_config_path() -> Result<PathBuf> {
+    let xdg_dir = BaseDirectories::new()?;
+    Ok(xdg_dir.get_config_home().join("apnotes/config"))
 }
 
+/// Path of the toml config file, the file does not need to exist
 #[cfg(target_family = "windows")]
 pub(crate)  fn get_config_path() -> Result<PathBuf> {
-    let config_file_path = PathBuf::from(format!("{}\\{}",env!("APPDATA"),"apnotes\\config".to_string()));
-    if config_file_path.exists() {
-        Ok(config_file_path)
+    let config_file_path = PathBuf::from(format!("{}\\{}",env!("APPDATA"),"apnotes\\config.toml".to_string()));
+    std::fs::create_dir_all(&config_file_path.parent().unwrap())?;
+    Ok(config_file_path)
+}
+
+/// Path of the key=value config file that was used before the toml config existed
+#[cfg(target_family = "windows")]
+pub(crate)  fn get_legacy_config_path() -> Result<PathBuf> {
+    Ok(PathBuf::from(format!("{}\\{}",env!("APPDATA"),"apnotes\\config".to_string())))
+}
+
+/// The default profile keeps the database name of the time before profiles existed
+fn db_file_name(base: &str, profile_name: &str) -> String {
+    if profile_name == DEFAULT_PROFILE {
+        base.to_string()
     } else {
-        warn!("Could not detect config file, gonna create empty one");
-        std::fs::create_dir(&config_file_path.parent().unwrap())?;
-        File::create(&config_file_path)?;
-        Ok(config_file_path)
+        format!("{}_{}", base, profile_name)
     }
 }
 
 #[cfg(target_family = "unix")]
-pub(crate)  fn get_db_path() -> PathBuf {
+pub(crate)  fn get_db_path(profile_name: &str) -> PathBuf {
     let xdg_dir = BaseDirectories::new().expect("Could not find xdg dirs");
     #[cfg(test)]
-        let db = "notes_db_test";
+        let db = db_file_name("notes_db_test", profile_name);
     #[cfg(not(test))]
-        let db = "notes_db";
+        let db = db_file_name("notes_db", profile_name);
     match xdg_dir.find_data_file(format!("apnotes/{}",db)) {
         Some(path) => path,
         None => {
@@ -137,9 +235,28 @@ pub(crate)  fn get_db_path() -> PathBuf {
     }
 }
 
+/// Directory in which the content of note attachments is stored, every profile
+/// has its own, so that blobs are only referenced by the database of that profile
+#[cfg(target_family = "unix")]
+pub(crate) fn get_blob_dir(profile_name: &str) -> Result<PathBuf> {
+    let xdg_dir = BaseDirectories::new()?;
+    #[cfg(test)]
+        let blobs = db_file_name("blobs_test", profile_name);
+    #[cfg(not(test))]
+        let blobs = db_file_name("blobs", profile_name);
+    xdg_dir.create_data_directory(format!("apnotes/{}", blobs)).map_err(|e| e.into())
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn get_blob_dir(profile_name: &str) -> Result<PathBuf> {
+    let blob_dir = PathBuf::from(format!("{}\\apnotes\\{}", env!("APPDATA"), db_file_name("blobs", profile_name)));
+    std::fs::create_dir_all(&blob_dir)?;
+    Ok(blob_dir)
+}
+
 #[cfg(target_family = "windows")]
-pub(crate)  fn get_db_path() -> PathBuf {
-    let db_file_path = PathBuf::from(format!("{}\\{}", env!("APPDATA"), "apnotes\\db".to_string()));
+pub(crate)  fn get_db_path(profile_name: &str) -> PathBuf {
+    let db_file_path = PathBuf::from(format!("{}\\apnotes\\{}", env!("APPDATA"), db_file_name("db", profile_name)));
     if db_file_path.exists() {
         db_file_path
     } else {
@@ -152,80 +269,98 @@ pub(crate)  fn get_db_path() -> PathBuf {
     }
 }
 
-pub(crate) fn load_profile() -> Result<Profile> {
+/// Reads the toml config file, if it does not exist the old key=value config file gets read
+#[cfg(not(test))]
+pub(crate) fn load_config() -> Result<LoadedConfig> {
+    let path = get_config_path()?;
+    if path.exists() {
+        trace!("Read config file from {}", path.display());
+        let config = config::parse_toml(&std::fs::read_to_string(&path)?)?;
+        return Ok(loaded_config(path, false, config));
+    }
+
+    let legacy_path = get_legacy_config_path()?;
+    if legacy_path.exists() {
+        warn!("{} uses the old config format, convert it with \"apnotes config migrate\"", legacy_path.display());
+        let config = config::parse_legacy(&std::fs::read_to_string(&legacy_path)?)?;
+        return Ok(loaded_config(legacy_path, true, config));
+    }
+
+    Err(InvalidConfig(format!("No config file found, create {}", path.display())).into())
+}
+
+#[cfg(test)]
+pub(crate) fn load_config() -> Result<LoadedConfig> {
+    let config = config::parse_legacy(unsafe { get_test_config() })?;
+    Ok(loaded_config(PathBuf::from("config"), true, config))
+}
+
+fn loaded_config(path: PathBuf, legacy: bool, config: Config) -> LoadedConfig {
+    LoadedConfig {
+        path,
+        legacy,
+        profiles: config.profiles.keys().cloned().collect(),
+        default_profile: config.profile(None).map(|(name, _)| name.clone()),
+        config
+    }
+}
+
+/// Converts the old key=value config file into the toml config file, returns the path of the new file
+pub(crate) fn migrate_config() -> Result<PathBuf> {
     let path = get_config_path()?;
-    let path = path.into_os_string().to_string_lossy().to_string();
+    if path.exists() {
+        return Err(InvalidConfig(format!("{} does already exist", path.display())).into());
+    }
 
-    trace!("Read config file from {}", &path);
-    #[cfg(not(test))]
-    let creds = std::fs::read_to_string(&path)?;
-    #[cfg(test)]
-    let creds = unsafe { get_test_config() };
-
-    let username_regex = Regex::new(r"username=(.*)")?;
-    let password_regex = Regex::new(r"password=(.*)")?;
-    let imap_regex = Regex::new(r"imap_server=(.*)")?;
-    let email_regex = Regex::new(r"email=(.*)")?;
-    let editor_regex = Regex::new(r"editor=(.*)")?;
-    let args_regex = Regex::new(r"editor_arguments=(.*)")?;
-    let uuid_regex = Regex::new(r".*@(.*)")?;
-    let password_type_regex = Regex::new(r"password_type=(.*)")?;
-
-    let username = get_with_regex(username_regex, &creds)?;
-    let password = get_with_regex(password_regex, &creds).map(|e| Some(e)).or_else::<ProfileError,_>(|_| Ok(None))?;
-    let imap_server = get_with_regex(imap_regex, &creds)?;
-    let email = get_with_regex(email_regex, &creds)?;
-    let editor = get_with_regex(editor_regex, &creds)?;
-    let args = get_with_regex(args_regex, &creds)?.split(" ").map(|s| s.to_string()).filter(|s| s.len() > 0).collect();
-    let domain = get_with_regex(uuid_regex, &email)?;
-    let password_type = get_with_regex(password_type_regex, &creds).or_else::<ProfileError,_>(|_| Ok("PLAIN".to_string()))?;
-
-    let (secret_service_attribute, secret_service_value) = if password_type == "SECRET_SERVICE".to_string() {
-        let secret_service_attribute_regex = Regex::new(r"secret_service_attribute=(.*)")?;
-        let secret_service_value_regex = Regex::new(r"secret_service_value=(.*)")?;
-        (
-            Some(get_with_regex(secret_service_attribute_regex, &creds)?),
-            Some(get_with_regex(secret_service_value_regex, &creds)?),
-        )
-    } else {
-        if password.is_none() {
-            return Err(NoPasswordProvided().into())
-        }
-        (None, None)
-    };
+    let legacy_path = get_legacy_config_path()?;
+    let config = config::parse_legacy(&std::fs::read_to_string(&legacy_path)?)?;
+    let content = toml::to_string(&config).map_err(|e| InvalidConfig(e.to_string()))?;
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Returns the names of all profiles inside the config file
+pub(crate) fn profile_names() -> Result<Vec<String>> {
+    Ok(load_config()?.profiles)
+}
+
+/// Loads the profile with the passed name, without a name the default profile gets loaded
+pub(crate) fn load_profile(name: Option<&str>) -> Result<Profile> {
+    profile_from_config(&load_config()?.config, name)
+}
+
+fn profile_from_config(config: &Config, name: Option<&str>) -> Result<Profile> {
+    let (name, profile) = config.profile(name)
+        .ok_or(ProfileNotFound(name.unwrap_or(DEFAULT_PROFILE).to_string()))?;
+
+    // The config is validated already, every email contains a domain
+    let domain = profile.email.rsplit_once('@').map(|(_, domain)| domain.to_string()).unwrap_or_default();
 
     Ok(
         Profile {
-            username,
-            password,
-            password_type,
-            imap_server,
-            email,
-            editor,
-            editor_arguments: args,
-            secret_service_attribute,
-            secret_service_value,
+            name: name.clone(),
+            username: profile.username.clone(),
+            password: profile.password.clone(),
+            password_type: profile.password_type,
+            imap_server: profile.imap_server.clone(),
+            port: profile.port.unwrap_or(profile.security.default_port()),
+            security: profile.security,
+            ca_file: profile.ca_file.clone(),
+            email: profile.email.clone(),
+            editor: profile.editor.clone(),
+            editor_arguments: profile.editor_arguments.clone(),
+            secret_service_attribute: profile.secret_service_attribute.clone(),
+            secret_service_value: profile.secret_service_value.clone(),
+            password_command: profile.password_command.clone(),
+            password_file: profile.password_file.clone(),
+            oauth2_token_url: profile.oauth2_token_url.clone(),
+            oauth2_client_id: profile.oauth2_client_id.clone(),
+            oauth2_client_secret: profile.oauth2_client_secret.clone(),
             domain
         }
     )
 }
 
-fn get_with_regex(regex: Regex, creds: &str) -> Result<String> {
-    match regex.captures(&creds)
-        .and_then(|captured| captured.get(1))
-        .and_then(|result| Option::from(result.as_str().to_string())) {
-        Some(e) => Ok(e),
-        None => {
-            let config_entry_name = regex.to_string().replace("=(.*)","");
-            Err(
-                NotFound(
-                    format!("Could not find entry in config file for key: \"{}\"", config_entry_name)
-                ).into()
-            )
-        }
-    }
-}
-
 #[cfg(test)]
 static mut BASIC_SECRET_SERVICE_CONFIG: &'static str = ""
 ;
@@ -238,7 +373,9 @@ unsafe fn get_test_config() -> &'static str {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use crate::profile::{load_profile, BASIC_SECRET_SERVICE_CONFIG};
+    use crate::profile::{load_profile, profile_from_config, run_password_command, decrypt_password_file, BASIC_SECRET_SERVICE_CONFIG};
+    use crate::error::ErrorCode;
+    use crate::config::{parse_legacy, PasswordType};
     #[cfg(target_family = "unix")]
     use secret_service::{SecretService, EncryptionType};
 
@@ -255,8 +392,8 @@ mod tests {
                 password=f
                 ";
 
-            let profile = load_profile();
-            assert_eq!(profile.as_ref().unwrap().password_type,"PLAIN");
+            let profile = load_profile(None);
+            assert_eq!(profile.as_ref().unwrap().password_type,PasswordType::Plain);
         }
     }
 
@@ -272,7 +409,7 @@ mod tests {
                 password_type=PLAIN
                 ";
 
-            assert!(load_profile().err().is_some());
+            assert!(load_profile(None).err().is_some());
         }
     }
 
@@ -290,11 +427,78 @@ mod tests {
                 secret_service_value=mailservice
                 ";
 
-            let profile = load_profile();
+            let profile = load_profile(None);
             assert_eq!(profile.as_ref().unwrap().secret_service_value.as_ref().unwrap(),"mailservice");
             assert_eq!(profile.as_ref().unwrap().secret_service_attribute.as_ref().unwrap(),"mail");
-            assert_eq!(profile.as_ref().unwrap().password_type,"SECRET_SERVICE");
+            assert_eq!(profile.as_ref().unwrap().password_type,PasswordType::SecretService);
         }
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_multiple_profiles() {
+        let config = "
+                [personal]
+                username=me@test.de
+                imap_server=imap.test.de
+                email=me@test.de
+                editor=vim
+                editor_arguments=
+                password=f
+
+                [work]
+                username=me@work.de
+                imap_server=imap.work.de
+                email=me@work.de
+                editor=nvim
+                editor_arguments=-R
+                password=g
+                ";
+
+        let config = parse_legacy(config).unwrap();
+        let names: Vec<&String> = config.profiles.keys().collect();
+        assert_eq!(names, vec!["personal", "work"]);
+
+        let profile = profile_from_config(&config, Some("work")).unwrap();
+        assert_eq!(profile.name, "work");
+        assert_eq!(profile.imap_server, "imap.work.de");
+        assert_eq!(profile.domain, "work.de");
+        assert_eq!(profile.editor_arguments, vec!["-R".to_string()]);
+
+        // Without a default section the first profile gets used
+        assert_eq!(profile_from_config(&config, None).unwrap().name, "personal");
+        assert!(profile_from_config(&config, Some("private")).is_err());
+    }
+
+    #[test]
+    fn test_password_command_config() {
+        let config = parse_legacy("
+                username=test@test.de
+                imap_server=test.test.de
+                email=test@test.de
+                editor=vim
+                password_type=COMMAND
+                password_command=pass show mail/test
+                ").unwrap();
+        let profile = profile_from_config(&config, None).unwrap();
+        assert_eq!(profile.password_type, PasswordType::Command);
+        assert_eq!(profile.password_command.unwrap(), "pass show mail/test");
+
+        assert!(parse_legacy("username=me\nimap_server=imap\nemail=me@test.de\neditor=vim\npassword_type=GPG_FILE")
+            .err().unwrap().to_string().contains("needs a password_file"));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_password_command() {
+        assert_eq!(run_password_command("printf 'secret\\nlogin: me\\n'").unwrap(), "secret");
+        assert_eq!(run_password_command("exit 3").err().unwrap().error_code(), 10);
+        assert_eq!(run_password_command("true").err().unwrap().error_code(), 11);
+    }
+
+    #[test]
+    fn test_missing_password_file() {
+        let error = decrypt_password_file(std::path::Path::new("/does/not/exist.gpg")).err().unwrap();
+        assert_eq!(error.error_code(), 12);
+    }
+}
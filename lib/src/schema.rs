@@ -1,3 +1,13 @@
+table! {
+    attachment (metadata_uuid, content_id) {
+        content_id -> Text,
+        metadata_uuid -> Text,
+        file_name -> Text,
+        mime_type -> Text,
+        blob -> Text,
+    }
+}
+
 table! {
     body (message_id) {
         old_remote_message_id -> Nullable<Text>,
@@ -5,6 +15,29 @@ table! {
         text -> Nullable<Text>,
         uid -> Nullable<BigInt>,
         metadata_uuid -> Text,
+        base_text -> Nullable<Text>,
+        html -> Nullable<Text>,
+    }
+}
+
+table! {
+    body_history (id) {
+        id -> Integer,
+        metadata_uuid -> Text,
+        message_id -> Text,
+        text -> Text,
+        date -> Text,
+        source -> Text,
+    }
+}
+
+table! {
+    folder_sync_state (folder) {
+        folder -> Text,
+        uid_validity -> BigInt,
+        uid_next -> BigInt,
+        highest_modseq -> Nullable<BigInt>,
+        messages -> BigInt,
     }
 }
 
@@ -17,12 +50,37 @@ table! {
         date -> Timestamp,
         uuid -> Text,
         mime_version -> Text,
+        moved_from -> Nullable<Text>,
+    }
+}
+
+table! {
+    remote_header (folder, uid) {
+        folder -> Text,
+        uid -> BigInt,
+        headers -> Text,
+    }
+}
+
+table! {
+    sync_journal (uuid) {
+        uuid -> Text,
+        action -> Text,
+        phase -> Text,
+        folder -> Text,
+        message_id -> Text,
+        uid -> Nullable<BigInt>,
     }
 }
 
 joinable!(body -> metadata (metadata_uuid));
 
 allow_tables_to_appear_in_same_query!(
+    attachment,
     body,
+    body_history,
+    folder_sync_state,
     metadata,
+    remote_header,
+    sync_journal,
 );
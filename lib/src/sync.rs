@@ -8,13 +8,14 @@ extern crate ctor;
 use self::itertools::Itertools;
 use self::log::*;
 use std::collections::HashSet;
-use crate::sync::UpdateAction::{AddLocally, UpdateRemotely, UpdateLocally, AddRemotely, DeleteLocally, DeleteRemote, Merge};
-use crate::model::{NotesMetadata, Body};
+use std::io::{Read, Write};
+use crate::sync::UpdateAction::{AddLocally, UpdateRemotely, UpdateLocally, AddRemotely, DeleteLocally, DeleteRemote, Merge, MoveRemotely};
+use crate::model::{NotesMetadata, Body, FolderSyncState};
 use crate::error::UpdateError::SyncError;
 use crate::error::UpdateError;
-use crate::apple_imap::{MailService};
+use crate::apple_imap::{MailService, MailServiceImpl};
 use crate::db::{DatabaseService};
-use crate::converter::convert2md;
+use crate::converter::{convert2md, render_html};
 use crate::notes::localnote::{LocalNote};
 use crate::notes::remote_note_metadata::RemoteNoteMetaData;
 use crate::notes::remote_note_header_collection::RemoteNoteHeaderCollection;
@@ -28,10 +29,19 @@ use colored::Colorize;
 use chrono::DateTime;
 use crate::profile::Profile;
 use crate::error::Result;
+use crate::merge;
+use crate::attachment;
+use crate::journal;
+use crate::history;
 
 pub struct SyncResult {
     pub action: String,
     pub subject: String,
+    /// Folder of the note, the target folder if the note gets moved
+    pub folder: String,
+    /// Why the action got planned, e.g. "remote message-id changed"
+    pub reason: String,
+    /// Always Ok for the planned actions of a dry run
     pub result: Result<()>
 }
 
@@ -56,6 +66,11 @@ pub enum UpdateAction<'a> {
     /// Action: delete all local bodies and replace with remote content
     UpdateLocally(&'a Vec<RemoteNoteMetaData>),
     /// Apply to all notes that:
+    ///     got moved into another folder locally
+    ///     did not change their content on both sides
+    /// Action: moves the remote message into the new folder, the uuid stays the same
+    MoveRemotely(&'a LocalNote),
+    /// Apply to all notes that:
     ///     have old_remote id set to non null string
     ///     remotes message-id != the locals message-id
     Merge(MergeMethod, &'a Vec<RemoteNoteMetaData>),
@@ -76,6 +91,7 @@ impl<'a> Display for UpdateAction<'a>
             DeleteLocally(_) => write!(f, "DeleteLocally"),
             UpdateRemotely(_) => write!(f, "UpdateRemotely"),
             UpdateLocally(_) => write!(f, "UpdateLocally"),
+            MoveRemotely(_) => write!(f, "MoveRemotely"),
             Merge(_,_) => write!(f, "Merge"),
             AddRemotely(_) => write!(f, "AddRemotely"),
             AddLocally(_) => write!(f, "AddLocally"),
@@ -83,6 +99,59 @@ impl<'a> Display for UpdateAction<'a>
     }
 }
 
+impl<'a> UpdateAction<'a> {
+    /// Subject of the note the action belongs to
+    pub fn subject(&self) -> String {
+        match self {
+            DeleteRemote(note) | DeleteLocally(note) | UpdateRemotely(note) | MoveRemotely(note) | AddRemotely(note) => note.first_subject(),
+            UpdateLocally(headers) | Merge(_, headers) | AddLocally(headers) => headers.first_subject()
+        }
+    }
+
+    /// Folder of the note the action belongs to, the target folder if the note gets moved
+    pub fn folder(&self) -> String {
+        match self {
+            DeleteRemote(note) | DeleteLocally(note) => note.remote_folder(),
+            UpdateRemotely(note) | MoveRemotely(note) | AddRemotely(note) => note.folder(),
+            UpdateLocally(headers) | Merge(_, headers) | AddLocally(headers) =>
+                headers.last().map(|header| header.folder.clone()).unwrap_or_default()
+        }
+    }
+
+    /// Describes why the action got planned, local_notes are the notes the action got planned with
+    pub fn reason(&self, local_notes: &HashSet<LocalNote>) -> String {
+        match self {
+            DeleteRemote(_) => "flagged for deletion locally".to_string(),
+            DeleteLocally(note) if note.metadata.new => "created and deleted locally".to_string(),
+            DeleteLocally(_) => "deleted on the server".to_string(),
+            UpdateRemotely(note) => match &note.metadata.moved_from {
+                Some(folder) => format!("edited locally, moved from {}", folder),
+                None => "edited locally".to_string()
+            },
+            MoveRemotely(note) => format!("moved locally from {}", note.remote_folder()),
+            AddRemotely(note) if note.metadata.new => "new local note".to_string(),
+            AddRemotely(_) => "edited locally, deleted on the server".to_string(),
+            AddLocally(_) => "new note on the server".to_string(),
+            Merge(_, _) => "changed locally and on the server".to_string(),
+            UpdateLocally(headers) if headers.len() > 1 => format!("{} unmerged versions on the server", headers.len()),
+            UpdateLocally(headers) => {
+                let local_note = local_notes.iter().find(|note| note.metadata.uuid == headers.uuid());
+                match local_note {
+                    Some(note) if note.changed_remotely(headers) == false && note.moved_remotely(headers) => {
+                        let header = headers.last().unwrap();
+                        if header.folder != note.metadata.subfolder {
+                            format!("moved on the server to {}", header.folder)
+                        } else {
+                            "uid changed on the server".to_string()
+                        }
+                    },
+                    _ => "remote message-id changed".to_string()
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug,PartialEq)]
 pub enum MergeMethod {
     AppendLocally,
@@ -90,7 +159,7 @@ pub enum MergeMethod {
 
 pub async fn sync_notes(db_connection: &Box<dyn DatabaseService + Send>, profile: &Profile, is_dry_run: bool)
                   -> Result<Vec<SyncResult>> {
-    crate::apple_imap::MailServiceImpl::new_with_login(profile).await
+    MailServiceImpl::new_with_login(profile).await
         .and_then(|mut imap_service| {
             sync(&mut imap_service, db_connection, is_dry_run).map(|result| (result,imap_service))
         })
@@ -99,6 +168,17 @@ pub async fn sync_notes(db_connection: &Box<dyn DatabaseService + Send>, profile
         })
 }
 
+/// Like sync_notes, but runs over an already established connection to the imap server.
+/// The stream has to be at the point where the server sends its greeting
+pub async fn sync_notes_with_stream<S: Read + Write>(stream: S,
+                                                     db_connection: &Box<dyn DatabaseService + Send>,
+                                                     profile: &Profile,
+                                                     is_dry_run: bool) -> Result<Vec<SyncResult>> {
+    let mut imap_service = MailServiceImpl::new_with_stream(stream, profile).await?;
+    let result = sync(&mut imap_service, db_connection, is_dry_run)?;
+    imap_service.logout().map(|_| result)
+}
+
 fn get_sync_actions<'a>(remote_note_headers: &'a GroupedRemoteNoteHeaders,
                         local_notes: &'a HashSet<LocalNote>) -> Vec<UpdateAction<'a>> {
 
@@ -118,6 +198,7 @@ fn get_sync_actions<'a>(remote_note_headers: &'a GroupedRemoteNoteHeaders,
             get_add_locally_action(rn,ln)
             .or_else(|| get_add_remotely_action(rn,ln))
             .or_else(|| get_update_remotely_action(rn,ln))
+            .or_else(|| get_move_remotely_action(rn,ln))
             .or_else(|| get_update_locally_action(rn, ln))
             .or_else(|| get_delete_locally_action(rn,ln))
             .or_else(|| get_delete_remotely_action(rn,ln))
@@ -133,6 +214,7 @@ fn get_sync_actions<'a>(remote_note_headers: &'a GroupedRemoteNoteHeaders,
     info!("Add    {:>padding$} notes remotely", acts.iter().filter(|act| matches!(act, UpdateAction::AddRemotely(_))).count(), padding=4);
     info!("Update {:>padding$} notes locally",  acts.iter().filter(|act| matches!(act, UpdateAction::UpdateLocally(_))).count(), padding=4);
     info!("Update {:>padding$} notes remotely", acts.iter().filter(|act| matches!(act, UpdateAction::UpdateRemotely(_))).count(), padding=4);
+    info!("Move   {:>padding$} notes remotely", acts.iter().filter(|act| matches!(act, UpdateAction::MoveRemotely(_))).count(), padding=4);
     info!("Merge  {:>padding$} notes", acts.iter().filter(|act| matches!(act, UpdateAction::Merge(_,_))).count(), padding=4);
 
     acts
@@ -240,6 +322,22 @@ fn get_update_remotely_action<'a>(remote_note_header: Option<&'a RemoteNoteHeade
     }
 }
 
+/// Checks if a note got moved locally, moves that also changed the content
+/// are handled by UpdateRemotely, because the updated note gets appended to the new folder
+fn get_move_remotely_action<'a>(remote_note_header: Option<&'a RemoteNoteHeaderCollection>,
+                                local_note: Option<&'a LocalNote>) -> Option<UpdateAction<'a>> {
+    match (local_note, remote_note_header) {
+        (Some(ln), Some(rn)) if
+        ln.metadata.moved_from.is_some()
+        && ln.metadata.locally_deleted == false
+        && ln.needs_merge() == false
+        && ln.content_changed_locally() == false
+        && ln.changed_remotely(rn) == false
+        => Some(MoveRemotely(ln)),
+        _ => None
+    }
+}
+
 fn get_update_locally_action<'a>(remote_note_header: Option<&'a RemoteNoteHeaderCollection>,
                                   local_note: Option<&'a LocalNote>) -> Option<UpdateAction<'a>> {
 
@@ -249,7 +347,7 @@ fn get_update_locally_action<'a>(remote_note_header: Option<&'a RemoteNoteHeader
         && ln.content_changed_locally() == false
         => {
             //Check if no merge needs to happen
-            if ln.content_changed_locally() == false && ln.changed_remotely(rn){
+            if ln.content_changed_locally() == false && (ln.changed_remotely(rn) || ln.moved_remotely(rn)) {
                 return Some(UpdateLocally(rn));
             } else {
                 return None
@@ -282,7 +380,11 @@ pub fn sync<T>(imap_session: &mut dyn MailService<T>, db_connection: &Box<dyn Da
                -> Result<Vec<SyncResult>>
 
 {
-    let headers = imap_session.fetch_headers()?;
+    if is_dry_run == false {
+        journal::recover(imap_session, db_connection)?;
+    }
+
+    let headers = fetch_headers_incrementally(imap_session, db_connection)?;
     let grouped_not_headers = collect_mergeable_notes(headers);
     let fetches = db_connection.fetch_all_notes()?;
 
@@ -291,7 +393,13 @@ pub fn sync<T>(imap_session: &mut dyn MailService<T>, db_connection: &Box<dyn Da
 
     if is_dry_run {
         info!("Dry run");
-        return Ok(Vec::new())
+        return Ok(actions.iter().map(|action| SyncResult {
+            action: action.to_string(),
+            subject: action.subject(),
+            folder: action.folder(),
+            reason: action.reason(&fetches),
+            result: Ok(())
+        }).collect())
     }
 
     let results = process_actions(imap_session, db_connection, &actions);
@@ -308,12 +416,107 @@ pub fn sync<T>(imap_session: &mut dyn MailService<T>, db_connection: &Box<dyn Da
 
     let results= results
         .into_iter()
-        .map(|(action,subject,result)| SyncResult { action: action.to_string(),subject, result } )
+        .map(|(action,subject,result)| SyncResult {
+            action: action.to_string(),
+            subject,
+            folder: action.folder(),
+            reason: action.reason(&fetches),
+            result
+        })
         .collect();
 
     Ok(results)
 }
 
+/// Fetches the headers of all remote notes
+///
+/// Only headers of messages that got added since the last synchronization are fetched,
+/// the rest is taken from the local header cache. Expunged messages are detected by
+/// comparing the message count. If the server supports CONDSTORE and the HIGHESTMODSEQ
+/// of a folder changed, the headers of all messages that changed since the last
+/// synchronization get fetched again. If the UIDVALIDITY of a folder changed or no sync
+/// state is present all headers of this folder are fetched.
+fn fetch_headers_incrementally<T>(imap_session: &mut dyn MailService<T>, db_connection: &Box<dyn DatabaseService + Send>)
+                                  -> Result<RemoteNoteHeaderCollection>
+{
+    let mut all_headers = vec![];
+
+    for folder in imap_session.list_note_folders()? {
+        let status = imap_session.folder_status(&folder)?;
+        let state = db_connection.fetch_folder_sync_state(&folder)?;
+
+        let headers = match state {
+            Some(state) if state.uid_validity == status.uid_validity as i64 => {
+                let mut headers = db_connection.fetch_cached_headers(&folder)?;
+
+                let unchanged = state.uid_next == status.uid_next as i64
+                    && state.messages == status.messages as i64
+                    && (status.highest_modseq.is_none() || state.highest_modseq == status.highest_modseq.map(|m| m as i64));
+
+                if unchanged {
+                    debug!("{} did not change since last sync", folder);
+                } else {
+                    if status.uid_next as i64 > state.uid_next {
+                        headers.extend(imap_session.fetch_headers_since(&folder, state.uid_next as u32)?);
+                    }
+
+                    if let (Some(old_modseq), Some(new_modseq)) = (state.highest_modseq, status.highest_modseq) {
+                        if new_modseq as i64 != old_modseq {
+                            // New messages got fetched already, only cached ones get refreshed
+                            let changed: HashSet<u32> = imap_session.fetch_changed_uids(&folder, old_modseq as u64)?
+                                .into_iter()
+                                .filter(|uid| (*uid as i64) < state.uid_next)
+                                .collect();
+
+                            if changed.is_empty() == false {
+                                let refreshed = imap_session.fetch_headers_by_uid(&folder, &changed)?;
+                                headers.retain(|header| changed.contains(&(header.uid as u32)) == false);
+                                headers.extend(refreshed);
+                                headers.sort_by_key(|header| header.uid);
+                            }
+                        }
+                    }
+
+                    if headers.len() != status.messages as usize {
+                        let uids = imap_session.fetch_uids(&folder)?;
+                        headers.retain(|header| uids.contains(&(header.uid as u32)));
+                    }
+                }
+                headers
+            },
+            _ => {
+                info!("Fetching all headers in {}", folder);
+                if status.messages > 0 {
+                    imap_session.fetch_headers_since(&folder, 1)?
+                } else {
+                    vec![]
+                }
+            }
+        };
+
+        // Messages might have been added between the status and the fetch command
+        let uid_next = headers.iter()
+            .map(|header| header.uid + 1)
+            .max()
+            .unwrap_or(0)
+            .max(status.uid_next as i64);
+
+        let new_state = FolderSyncState {
+            folder: folder.clone(),
+            uid_validity: status.uid_validity as i64,
+            uid_next,
+            highest_modseq: status.highest_modseq.map(|m| m as i64),
+            messages: headers.len() as i64
+        };
+
+        db_connection.update_folder_sync_state(&new_state, &headers)?;
+
+        all_headers.extend(headers);
+    }
+
+    Ok(all_headers)
+}
+
 pub fn process_actions<'a, T>(
     imap_connection: &mut dyn MailService<T>,
     db_connection: &Box<dyn DatabaseService + Send>,
@@ -323,15 +526,30 @@ pub fn process_actions<'a, T>(
     let result = actions
         .iter()
         .map(|action| {
+            // Recorded before anything gets changed, so that an interrupted action can be recovered
+            let entry = journal::entry(action);
+            if let Err(e) = db_connection.insert_journal_entry(&entry) {
+                return (action, action.subject(), Err(e.into()));
+            }
+
             let result = match action {
                 UpdateAction::DeleteRemote(note) => { process_delete_remotely(imap_connection, db_connection, action, note) },
                 UpdateAction::DeleteLocally(local_note) => process_delete_locally(db_connection, action, local_note),
                 UpdateAction::UpdateLocally(new_note_bodies) => process_update_locally(imap_connection, db_connection, action,new_note_bodies),
+                UpdateAction::MoveRemotely(local_note) => process_move_remotely(imap_connection, db_connection, action, local_note),
                 UpdateAction::Merge(_method,remote_note) => { process_merge(imap_connection, db_connection, action, remote_note) },
                 UpdateAction::AddRemotely(local_note) | UpdateAction::UpdateRemotely(local_note) => { (action, local_note.first_subject(), update_message_remotely(imap_connection, db_connection, &local_note)) }
                 UpdateAction::AddLocally(note_headers) => process_add_locally(imap_connection, db_connection, action, note_headers),
             };
-            return result;
+
+            // Failed actions stay inside the journal, the next sync checks how far they got
+            match result {
+                (action, subject, Ok(())) => {
+                    let finished = db_connection.delete_journal_entry(&entry.uuid).map_err(|e| e.into());
+                    (action, subject, finished)
+                },
+                result => result
+            }
         }
         ).collect();
 
@@ -346,7 +564,7 @@ fn process_add_locally<'a,T>(imap_connection: &mut dyn MailService<T>,
      {
 
     let result =
-        localnote_from_remote_header(imap_connection, noteheaders)
+        localnote_from_remote_header(imap_connection, db_connection, noteheaders)
             .and_then(|note| db_connection.insert_into_db(&note).map_err(|e| e.into()));
 
     (action, noteheaders.first_subject(), result)
@@ -365,7 +583,7 @@ fn process_update_locally<'a,T>(imap_connection: &mut dyn MailService<T>,
         new_note_bodies.iter().map(|e| {
             let folder = &e.folder;
             imap_connection.select(folder)
-                .and_then(|_| imap_connection.fetch_note_content(folder, e.uid)
+                .and_then(|_| fetch_note_html(imap_connection, db_connection, folder, e)
                     .map(|content| (e, content)))
                 .and_then(|(headers, content)| {
                     Ok(
@@ -375,6 +593,8 @@ fn process_update_locally<'a,T>(imap_connection: &mut dyn MailService<T>,
                             text: Some(convert2md(&content)),
                             uid: Some(headers.uid),
                             metadata_uuid: headers.headers.uuid(),
+                            base_text: None,
+                            html: Some(content),
                         }
                     )
                 })
@@ -389,7 +609,8 @@ fn process_update_locally<'a,T>(imap_connection: &mut dyn MailService<T>,
 
     let result = db_connection.replace_notes(
         &f,
-        new_note_bodies.iter().next().unwrap().headers.uuid()
+        new_note_bodies.iter().next().unwrap().headers.uuid(),
+        history::REMOTE_UPDATE
     ).map_err(|e| e.into());
 
     // Update the modification date in metadata object
@@ -400,12 +621,40 @@ fn process_update_locally<'a,T>(imap_connection: &mut dyn MailService<T>,
     let mut note = db_connection.fetch_single_note(uuid).unwrap().unwrap();
     note.metadata.date = date.unwrap().headers.date();
     let new_folder = new_note_bodies.iter().last().unwrap().folder.clone();
-    note.metadata.subfolder = new_folder;
+    // Keep local moves, the updated remote note gets moved with the next sync
+    if note.metadata.moved_from.is_some() {
+        note.metadata.moved_from = Some(new_folder);
+    } else {
+        note.metadata.subfolder = new_folder;
+    }
     db_connection.update(&note).unwrap();
 
     (action, new_note_bodies.first_subject(), result)
 }
 
+fn process_move_remotely<'a, T>(imap_connection: &mut dyn MailService<T>,
+                                   db_connection: &Box<dyn DatabaseService + Send>,
+                                   action: &'a UpdateAction,
+                                   localnote: &LocalNote)
+    -> (&'a UpdateAction<'a>, String, Result<()>)
+{
+    info!("{} got moved from {} to {}", localnote.first_subject(), localnote.remote_folder(), localnote.folder());
+
+    let result = imap_connection.create_mailbox(&localnote.metadata)
+        .and_then(|_| imap_connection.move_message(localnote))
+        .and_then(|uid| db_connection.update_journal_phase(&localnote.metadata.uuid, journal::REMOTE_DONE, Some(uid as i64))
+            .map(|_| uid)
+            .map_err(|e| e.into()))
+        .and_then(|uid| {
+            let mut note = localnote.clone();
+            note.metadata.moved_from = None;
+            note.body[0].uid = Some(uid as i64);
+            db_connection.update(&note).map_err(|e| e.into())
+        });
+
+    (action, localnote.first_subject(), result)
+}
+
 fn process_delete_remotely<'a, T>(imap_connection: &mut dyn MailService<T>,
                                      db_connection: &Box<dyn DatabaseService + Send>,
                                      action: &'a UpdateAction,
@@ -415,6 +664,8 @@ fn process_delete_remotely<'a, T>(imap_connection: &mut dyn MailService<T>,
 {
     let result = imap_connection
         .delete_message(localnote)
+        .and_then(|_| db_connection.update_journal_phase(&localnote.metadata.uuid, journal::REMOTE_DONE, None)
+            .map_err(|e| e.into()))
         .and_then(|_| db_connection.delete(&localnote)
             .map_err(|e| e.into())
         );
@@ -446,34 +697,47 @@ fn update_message_remotely<'a, T>(imap_connection: &mut dyn MailService<T>,
         .map_err(|e| e.into())
         .and_then(|_| imap_connection.select(&metadata.folder())
             .map_err(|e| e.into()))
-        .and_then(|_| imap_connection.update_message(localnote)
+        .and_then(|_| attachment::load_referenced_attachments(db_connection, localnote))
+        .and_then(|attachments| imap_connection.update_message(localnote, &attachments)
             .map_err(|e| e.into())
         )
+        .and_then(|uid| db_connection.update_journal_phase(&localnote.metadata.uuid, journal::REMOTE_DONE, Some(uid as i64))
+            .map(|_| uid)
+            .map_err(|e| e.into()))
         .and_then(|uid| {
-            let body = localnote.body.first().unwrap();
-            let note = note!(
-                            NotesMetadata {
-                                subfolder: localnote.metadata.subfolder.clone(),
-                                locally_deleted: localnote.metadata.locally_deleted,
-                                new: false,
-                                edited: false,
-                                date: localnote.metadata.date.clone(),
-                                uuid:localnote.metadata.uuid.clone(),
-                                mime_version: localnote.metadata.mime_version.clone()
-                            },
-                            Body {
-                                old_remote_message_id: None,
-                                message_id: body.message_id.clone(),
-                                text: body.text.clone(),
-                                uid: Some(uid as i64),
-                                metadata_uuid: body.metadata_uuid.clone()
-                            }
-                        );
-            db_connection.update(&note)
+            db_connection.update(&synced_note(localnote, uid))
                 .map_err(|e| e.into())
         })
 }
 
+/// The localnote after its message got uploaded with the passed uid
+pub(crate) fn synced_note(localnote: &LocalNote, uid: u32) -> LocalNote {
+    let body = localnote.body.first().unwrap();
+    note!(
+        NotesMetadata {
+            subfolder: localnote.metadata.subfolder.clone(),
+            locally_deleted: localnote.metadata.locally_deleted,
+            new: false,
+            edited: false,
+            date: localnote.metadata.date.clone(),
+            uuid:localnote.metadata.uuid.clone(),
+            mime_version: localnote.metadata.mime_version.clone(),
+            // The updated message got appended to the new folder
+            moved_from: None
+        },
+        Body {
+            old_remote_message_id: None,
+            message_id: body.message_id.clone(),
+            text: body.text.clone(),
+            uid: Some(uid as i64),
+            metadata_uuid: body.metadata_uuid.clone(),
+            base_text: None,
+            // The uploaded html is the base for the next local edit
+            html: Some(render_html(body))
+        }
+    )
+}
+
 fn process_merge<'a,T>(imap_connection: &mut dyn MailService<T>,
                                   db_connection: &Box<dyn DatabaseService + Send>,
                                   action: &'a UpdateAction,
@@ -485,37 +749,43 @@ fn process_merge<'a,T>(imap_connection: &mut dyn MailService<T>,
         UpdateAction::Merge(MergeMethod::AppendLocally, _remote_note) => {
 
             let mut append = || {
-                let note_bodies: Vec<Result<Body>> = new_notes.iter().map(|new_note| {
+                let note_bodies: Result<Vec<Body>> = new_notes.iter().map(|new_note| {
 
                     info!("Merging Note: {} by appending notebody: {}",
                              new_note.headers.uuid(),
                              new_note.headers.subject()
                     );
 
-                    let text =
-                        convert2md(&imap_connection.fetch_note_content(&new_note.folder, new_note.uid)?);
+                    let html = fetch_note_html(imap_connection, db_connection, &new_note.folder, new_note)?;
 
                     Ok(Body {
                         old_remote_message_id: None,
                         message_id: new_note.headers.message_id().clone(),
-                        text: Some(text),
+                        text: Some(convert2md(&html)),
                         uid: Some(new_note.uid as i64),
                         metadata_uuid: new_note.headers.uuid(),
+                        base_text: None,
+                        html: Some(html),
                     })
                 }).collect();
 
+                let note_bodies = match note_bodies {
+                    Ok(note_bodies) => note_bodies,
+                    Err(e) => return (action,new_notes.first_subject(),Err(e))
+                };
+
+                // Try to merge both versions without user interaction first
+                if let Some(merged_body) = merge_automatically(db_connection, &note_bodies) {
+                    let result = db_connection
+                        .replace_notes(&vec![merged_body], new_notes.uuid(), history::MERGE)
+                        .map_err(|e| e.into());
+                    return (action, new_notes.first_subject(), result);
+                }
+
                 for result in note_bodies {
-                    match result {
-                        Err(e) => {
-                            return (action,new_notes.first_subject(),Err(e));
-                        },
-                        Ok(result) => {
-                            if let Err(e) = db_connection.append_note(&result).map_err(|e| e.into()) {
-                                return (action,new_notes.first_subject(),Err(e));
-                            }
-                        }
+                    if let Err(e) = db_connection.append_note(&result).map_err(|e| e.into()) {
+                        return (action,new_notes.first_subject(),Err(e));
                     }
-
                 };
 
                 (action, new_notes.first_subject(), Ok(()))
@@ -528,16 +798,80 @@ fn process_merge<'a,T>(imap_connection: &mut dyn MailService<T>,
     }
 }
 
-fn localnote_from_remote_header<T>(imap_connection: &mut dyn MailService<T>, noteheaders: &Vec<RemoteNoteMetaData>)
+/// Three-way merges the locally edited note with the passed remote body, by
+/// using the last synchronized text as common ancestor.
+///
+/// Returns None if no ancestor is known or if both sides changed the same lines,
+/// in that case the user has to merge the note manually.
+fn merge_automatically(db_connection: &Box<dyn DatabaseService + Send>,
+                       remote_bodies: &Vec<Body>) -> Option<Body> {
+    if remote_bodies.len() != 1 {
+        return None;
+    }
+
+    let remote_body = remote_bodies.first()?;
+    let local_note = db_connection.fetch_single_note(&remote_body.metadata_uuid).ok().flatten()?;
+
+    if local_note.needs_merge() {
+        return None;
+    }
+
+    let local_body = local_note.body.first()?;
+    let base_text = local_body.base_text.as_ref()?;
+
+    let merged = merge::merge_three(
+        base_text,
+        local_body.text.as_ref()?,
+        remote_body.text.as_ref()?,
+        &local_body.message_id,
+        &remote_body.message_id
+    );
+
+    if merged.conflicts > 0 {
+        info!("{} has conflicting changes, needs to be merged manually", local_note.first_subject());
+        return None;
+    }
+
+    info!("Merged {} automatically", local_note.first_subject());
+
+    // The merged body replaces the remote note with the next sync
+    Some(Body {
+        old_remote_message_id: Some(remote_body.message_id.clone()),
+        message_id: local_body.message_id.clone(),
+        text: Some(merged.text),
+        uid: remote_body.uid,
+        metadata_uuid: local_body.metadata_uuid.clone(),
+        base_text: remote_body.text.clone(),
+        html: remote_body.html.clone(),
+    })
+}
+
+/// Fetches the html of a remote note, its attachments are saved to the blob store
+fn fetch_note_html<T>(imap_connection: &mut dyn MailService<T>,
+                      db_connection: &Box<dyn DatabaseService + Send>,
+                      folder: &str,
+                      remote_note: &RemoteNoteMetaData)
+    -> Result<String>
+{
+    let content = imap_connection.fetch_note_content(folder, remote_note.uid)?;
+    attachment::store_attachments(db_connection, &remote_note.headers.uuid(), &content.attachments)?;
+    Ok(content.html)
+}
+
+fn localnote_from_remote_header<T>(imap_connection: &mut dyn MailService<T>,
+                                   db_connection: &Box<dyn DatabaseService + Send>,
+                                   noteheaders: &Vec<RemoteNoteMetaData>)
     -> Result<LocalNote>
 
 {
     let bodies: Vec<Option<Body>> = noteheaders.into_iter().map(|single_remote_note| {
         (
             single_remote_note,
-            imap_connection.fetch_note_content(
+            fetch_note_html(
+                imap_connection,
+                db_connection,
                 &single_remote_note.folder,
-                single_remote_note.uid,
+                single_remote_note,
             )
         )
     }).map(|(remote_metadata, result)| {
@@ -549,6 +883,8 @@ fn localnote_from_remote_header<T>(imap_connection: &mut dyn MailService<T>, not
                     text: Some(convert2md(&body)),
                     uid: Some(remote_metadata.uid),
                     metadata_uuid: remote_metadata.headers.uuid(),
+                    base_text: None,
+                    html: Some(body),
                 })
             }
             Err(e) => {
@@ -570,7 +906,7 @@ fn localnote_from_remote_header<T>(imap_connection: &mut dyn MailService<T>, not
 
 ///Groups headers that have the same uuid
 /// Also sorts the returning vector based of the inner vectors length (ascending)
-// TODO check what happens if notes get moved to another folder, do they still have the same uuid?
+// Notes that got moved to another folder keep their uuid, so they are grouped as well
 pub fn collect_mergeable_notes(header_metadata: RemoteNoteHeaderCollection) -> GroupedRemoteNoteHeaders {
     let mut data_grouped: Vec<Vec<RemoteNoteMetaData>> = Vec::new();
     for (_key, group) in &header_metadata.into_iter()
@@ -1313,5 +1649,608 @@ mod sync_tests {
 
     }
 
+    /// Locally moved notes should get moved remotely
+    #[test]
+    pub fn move_remotely() {
+        let remote_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("Old".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+
+        let local_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("New".to_string()).moved_from("Notes.Old").build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
 
-}
\ No newline at end of file
+        let remote_header = set![vec![remote_note.to_remote_metadata()]];
+        let noteset = set![local_note];
+
+        let actions = get_sync_actions(&remote_header, &noteset);
+
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(actions[0], UpdateAction::MoveRemotely(_)));
+    }
+
+    /// Locally moved and edited notes get appended to the new folder
+    #[test]
+    pub fn move_remotely_edited() {
+        let remote_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("Old".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+
+        let local_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("New".to_string()).moved_from("Notes.Old").build(),
+            BodyMetadataBuilder::new().with_message_id("2").with_old_remote_message_id("1").build()
+        ];
+
+        let remote_header = set![vec![remote_note.to_remote_metadata()]];
+        let noteset = set![local_note];
+
+        let actions = get_sync_actions(&remote_header, &noteset);
+
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(actions[0], UpdateAction::UpdateRemotely(_)));
+    }
+
+    /// Notes that got moved on another device should get updated locally
+    #[test]
+    pub fn moved_on_other_device() {
+        let remote_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("New".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+
+        let local_note = note![
+            NotesMetadataBuilder::new().with_uuid("1").with_folder("Old".to_string()).build(),
+            BodyMetadataBuilder::new().with_message_id("1").build()
+        ];
+
+        let remote_header = set![vec![remote_note.to_remote_metadata()]];
+        let noteset = set![local_note];
+
+        let actions = get_sync_actions(&remote_header, &noteset);
+
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(actions[0], UpdateAction::UpdateLocally(_)));
+    }
+
+    fn cached_header(uid: i64) -> RemoteNoteMetaData {
+        use crate::builder::HeaderBuilder;
+
+        RemoteNoteMetaData {
+            headers: HeaderBuilder::new().with_subject(&format!("Note {}", uid)).build(),
+            folder: "Notes".to_string(),
+            uid
+        }
+    }
+
+    /// Only new messages should get fetched, expunged ones should get removed from the cache
+    #[test]
+    pub fn incremental_header_fetch() {
+        use crate::apple_imap::{MockMailService, FolderStatus};
+
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        let state = FolderSyncState {
+            folder: "Notes".to_string(),
+            uid_validity: 1,
+            uid_next: 3,
+            highest_modseq: None,
+            messages: 2
+        };
+        db_connection.update_folder_sync_state(&state, &vec![cached_header(1), cached_header(2)]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders().returning(|| Ok(vec!["Notes".to_string()]));
+        imap_service.expect_folder_status().returning(|_| Ok(FolderStatus {
+            uid_validity: 1,
+            uid_next: 4,
+            messages: 2,
+            highest_modseq: None
+        }));
+        imap_service.expect_fetch_headers_since()
+            .withf(|folder, min_uid| folder.to_string() == "Notes" && *min_uid == 3)
+            .times(1)
+            .returning(|_, _| Ok(vec![cached_header(3)]));
+        imap_service.expect_fetch_uids()
+            .times(1)
+            .returning(|_| Ok(vec![2, 3].into_iter().collect()));
+
+        let headers = fetch_headers_incrementally(&mut imap_service, &db_connection).unwrap();
+
+        let uids: Vec<i64> = headers.iter().map(|header| header.uid).collect();
+        assert_eq!(uids, vec![2, 3]);
+
+        let new_state = db_connection.fetch_folder_sync_state("Notes").unwrap().unwrap();
+        assert_eq!(new_state.uid_next, 4);
+        assert_eq!(new_state.messages, 2);
+        assert_eq!(db_connection.fetch_cached_headers("Notes").unwrap().len(), 2);
+    }
+
+    /// With a changed HIGHESTMODSEQ the headers of changed messages get fetched again
+    #[test]
+    pub fn header_fetch_after_modseq_change() {
+        use crate::apple_imap::{MockMailService, FolderStatus};
+        use crate::builder::HeaderBuilder;
+
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        let state = FolderSyncState {
+            folder: "Notes".to_string(),
+            uid_validity: 1,
+            uid_next: 3,
+            highest_modseq: Some(10),
+            messages: 2
+        };
+        db_connection.update_folder_sync_state(&state, &vec![cached_header(1), cached_header(2)]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders().returning(|| Ok(vec!["Notes".to_string()]));
+        imap_service.expect_folder_status().returning(|_| Ok(FolderStatus {
+            uid_validity: 1,
+            uid_next: 3,
+            messages: 2,
+            highest_modseq: Some(12)
+        }));
+        imap_service.expect_fetch_headers_since().times(0);
+        imap_service.expect_fetch_changed_uids()
+            .withf(|folder, modseq| folder.to_string() == "Notes" && *modseq == 10)
+            .times(1)
+            .returning(|_, _| Ok(vec![1].into_iter().collect()));
+        imap_service.expect_fetch_headers_by_uid()
+            .withf(|folder, uids| folder.to_string() == "Notes" && *uids == vec![1].into_iter().collect())
+            .times(1)
+            .returning(|_, _| {
+                let mut header = cached_header(1);
+                header.headers = HeaderBuilder::new().with_subject("Changed").build();
+                Ok(vec![header])
+            });
+        imap_service.expect_fetch_uids().times(0);
+
+        let headers = fetch_headers_incrementally(&mut imap_service, &db_connection).unwrap();
+
+        let uids: Vec<i64> = headers.iter().map(|header| header.uid).collect();
+        assert_eq!(uids, vec![1, 2]);
+        assert_eq!(headers[0].headers.subject(), "Changed");
+
+        let new_state = db_connection.fetch_folder_sync_state("Notes").unwrap().unwrap();
+        assert_eq!(new_state.highest_modseq, Some(12));
+        assert_eq!(new_state.uid_next, 3);
+        assert_eq!(new_state.messages, 2);
+        assert_eq!(db_connection.fetch_cached_headers("Notes").unwrap()[0].headers.subject(), "Changed");
+    }
+
+    /// A changed UIDVALIDITY invalidates all cached uids
+    #[test]
+    pub fn header_fetch_after_uid_validity_change() {
+        use crate::apple_imap::{MockMailService, FolderStatus};
+
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(crate::db::SqliteDBConnection::new());
+        db_connection.delete_everything().unwrap();
+
+        let state = FolderSyncState {
+            folder: "Notes".to_string(),
+            uid_validity: 1,
+            uid_next: 3,
+            highest_modseq: Some(10),
+            messages: 2
+        };
+        db_connection.update_folder_sync_state(&state, &vec![cached_header(1), cached_header(2)]).unwrap();
+
+        let mut imap_service = MockMailService::<()>::new();
+        imap_service.expect_list_note_folders().returning(|| Ok(vec!["Notes".to_string()]));
+        imap_service.expect_folder_status().returning(|_| Ok(FolderStatus {
+            uid_validity: 2,
+            uid_next: 2,
+            messages: 1,
+            highest_modseq: Some(1)
+        }));
+        imap_service.expect_fetch_headers_since()
+            .withf(|folder, min_uid| folder.to_string() == "Notes" && *min_uid == 1)
+            .times(1)
+            .returning(|_, _| Ok(vec![cached_header(1)]));
+        imap_service.expect_fetch_uids().times(0);
+
+        let headers = fetch_headers_incrementally(&mut imap_service, &db_connection).unwrap();
+
+        assert_eq!(headers.len(), 1);
+        let new_state = db_connection.fetch_folder_sync_state("Notes").unwrap().unwrap();
+        assert_eq!(new_state.uid_validity, 2);
+        assert_eq!(new_state.highest_modseq, Some(1));
+    }
+}
+
+/// Synchronizes against the fake imap server, so that MailServiceImpl gets tested as well
+#[cfg(test)]
+mod sync_e2e_tests {
+    use super::*;
+    use crate::builder::{NotesMetadataBuilder, BodyMetadataBuilder};
+    use crate::converter::convert_to_html;
+    use crate::db::SqliteDBConnection;
+    use crate::fake_imap::FakeImapServer;
+
+    /// Every test uses its own profile, so that it gets its own database
+    fn setup(profile_name: &str) -> (FakeImapServer, Profile, Box<dyn DatabaseService + Send>) {
+        let server = FakeImapServer::start();
+        let profile = server.profile(profile_name);
+        let db_connection: Box<dyn DatabaseService + Send> = Box::new(SqliteDBConnection::for_profile(profile_name));
+        db_connection.delete_everything().unwrap();
+        (server, profile, db_connection)
+    }
+
+    /// Message of a note inside the root folder, like another device would upload it.
+    /// Returns the message-id and the message
+    fn note_message(profile: &Profile, uuid: &str, text: &str) -> (String, String) {
+        let note = note![
+            NotesMetadataBuilder::new().with_uuid(uuid).with_folder("".to_string()).build(),
+            BodyMetadataBuilder::new().with_text(text).build()
+        ];
+        let message = attachment::build_message(note.to_header_vector(profile), &convert_to_html(&note.body[0]), &vec![]);
+        (note.body[0].message_id.clone(), message)
+    }
+
+    /// Edits the text of a note like the edit command does
+    fn edit_locally<F: Fn(&str) -> String>(db_connection: &Box<dyn DatabaseService + Send>, uuid: &str, edit: F) {
+        let mut note = db_connection.fetch_single_note(uuid).unwrap().unwrap();
+        let body = note.body[0].clone();
+        note.metadata.edited = true;
+        note.body = vec![BodyMetadataBuilder::new()
+            .with_uid(body.uid)
+            .with_metadata_uuid(uuid)
+            .with_text(&edit(body.text.as_ref().unwrap()))
+            .with_base_text(body.text.clone())
+            .with_html(body.html.clone())
+            .with_old_remote_message_id(&body.message_id)
+            .build()];
+        db_connection.update(&note).unwrap();
+    }
+
+    /// Markdown of every message inside the folder
+    fn remote_texts(server: &FakeImapServer, folder: &str) -> Vec<String> {
+        server.messages(folder).iter()
+            .map(|(_, message)| convert2md(&attachment::parse_message(message.as_bytes()).unwrap().html))
+            .collect()
+    }
+
+    fn assert_actions(results: &Vec<SyncResult>, actions: Vec<&str>) {
+        for result in results {
+            if let Err(e) = &result.result {
+                panic!("{} of {} failed: {}", result.action, result.subject, e);
+            }
+        }
+        assert_eq!(results.iter().map(|result| result.action.as_str()).collect::<Vec<&str>>(), actions);
+    }
+
+    #[tokio::test]
+    async fn add_locally() {
+        let (server, profile, db_connection) = setup("e2e_add_locally");
+        let (message_id, message) = note_message(&profile, "e2e-add-locally", "Remote note\n\nWritten on another device");
+        let uid = server.append("Notes", &message);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["AddLocally"]);
+
+        let note = db_connection.fetch_single_note("e2e-add-locally").unwrap().unwrap();
+        assert_eq!(note.metadata.subfolder, "Notes");
+        assert_eq!(note.body[0].message_id, message_id);
+        assert_eq!(note.body[0].uid, Some(uid as i64));
+        assert!(note.body[0].text.as_ref().unwrap().contains("Written on another device"));
+
+        // Nothing changed, the second sync has nothing to do
+        assert_actions(&sync_notes(&db_connection, &profile, false).await.unwrap(), vec![]);
+    }
+
+    /// The sync does not depend on tcp, any stream works
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn sync_over_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let (server, profile, db_connection) = setup("e2e_unix_socket");
+        let (_, message) = note_message(&profile, "e2e-unix-socket", "Note\n\nSynced over a socket pair");
+        server.append("Notes", &message);
+
+        let (client, server_side) = UnixStream::pair().unwrap();
+        server.serve(server_side.try_clone().unwrap(), server_side);
+
+        let results = sync_notes_with_stream(client, &db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["AddLocally"]);
+        assert!(db_connection.fetch_single_note("e2e-unix-socket").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn add_remotely() {
+        let (server, profile, db_connection) = setup("e2e_add_remotely");
+        let note = note![
+            NotesMetadataBuilder::new().with_folder("".to_string()).is_new(true).build(),
+            BodyMetadataBuilder::new().with_text("Local note\n\nWritten offline").build()
+        ];
+        db_connection.insert_into_db(&note).unwrap();
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["AddRemotely"]);
+
+        let messages = server.messages("Notes");
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].1.contains(&note.metadata.uuid));
+        assert!(remote_texts(&server, "Notes")[0].contains("Written offline"));
+
+        let synced = db_connection.fetch_single_note(&note.metadata.uuid).unwrap().unwrap();
+        assert_eq!(synced.metadata.new, false);
+        assert_eq!(synced.body[0].uid, Some(messages[0].0 as i64));
+    }
+
+    #[tokio::test]
+    async fn update_remotely() {
+        let (server, profile, db_connection) = setup("e2e_update_remotely");
+        let (_, message) = note_message(&profile, "e2e-update-remotely", "Note\n\nOriginal content");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        edit_locally(&db_connection, "e2e-update-remotely", |text| text.replace("Original", "Edited"));
+        let edited = db_connection.fetch_single_note("e2e-update-remotely").unwrap().unwrap();
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["UpdateRemotely"]);
+
+        // The old message got replaced
+        let messages = server.messages("Notes");
+        assert_eq!(messages.len(), 1);
+        assert_ne!(messages[0].0, old_uid);
+        assert!(messages[0].1.contains(&edited.body[0].message_id));
+        assert!(remote_texts(&server, "Notes")[0].contains("Edited content"));
+
+        let synced = db_connection.fetch_single_note("e2e-update-remotely").unwrap().unwrap();
+        assert_eq!(synced.metadata.edited, false);
+        assert_eq!(synced.body[0].uid, Some(messages[0].0 as i64));
+        assert_eq!(synced.body[0].old_remote_message_id, None);
+        assert!(db_connection.fetch_journal().unwrap().is_empty());
+    }
+
+    /// A dry run returns the planned actions without changing anything
+    #[tokio::test]
+    async fn dry_run() {
+        let (server, profile, db_connection) = setup("e2e_dry_run");
+        let (_, message) = note_message(&profile, "e2e-dry-run", "Note\n\nOriginal content");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        let (_, message) = note_message(&profile, "e2e-dry-run", "Note\n\nChanged on another device");
+        server.expunge("Notes", old_uid);
+        server.append("Notes", &message);
+        let new_note = note![
+            NotesMetadataBuilder::new().with_folder("".to_string()).is_new(true).build(),
+            BodyMetadataBuilder::new().with_text("Local note\n\nWritten offline").build()
+        ];
+        db_connection.insert_into_db(&new_note).unwrap();
+
+        let mut results = sync_notes(&db_connection, &profile, true).await.unwrap();
+        results.sort_by(|a, b| a.action.cmp(&b.action));
+        assert_actions(&results, vec!["AddRemotely", "UpdateLocally"]);
+
+        assert_eq!(results[0].subject, "Local note");
+        assert_eq!(results[0].reason, "new local note");
+        assert_eq!(results[1].subject, "Note");
+        assert_eq!(results[1].folder, "Notes");
+        assert_eq!(results[1].reason, "remote message-id changed");
+
+        // Nothing got changed
+        assert_eq!(server.messages("Notes").len(), 1);
+        let note = db_connection.fetch_single_note("e2e-dry-run").unwrap().unwrap();
+        assert!(note.body[0].text.as_ref().unwrap().contains("Original content"));
+        assert!(db_connection.fetch_single_note(&new_note.metadata.uuid).unwrap().unwrap().metadata.new);
+    }
+
+    /// Text that got replaced by a remote update gets uploaded again after restoring it
+    #[tokio::test]
+    async fn restore_remote_update() {
+        let (server, profile, db_connection) = setup("e2e_restore_remote_update");
+        let (_, message) = note_message(&profile, "e2e-restore", "Note\n\nOriginal content");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        let (_, message) = note_message(&profile, "e2e-restore", "Note\n\nChanged on another device");
+        server.expunge("Notes", old_uid);
+        server.append("Notes", &message);
+        assert_actions(&sync_notes(&db_connection, &profile, false).await.unwrap(), vec!["UpdateLocally"]);
+
+        let revisions = history::revisions(db_connection.fetch_history("e2e-restore").unwrap());
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].source, history::REMOTE_UPDATE);
+        assert!(revisions[0].text.contains("Original content"));
+
+        let note = db_connection.fetch_single_note("e2e-restore").unwrap().unwrap();
+        let restored = history::restore(&note, &revisions[0], &profile).unwrap();
+        assert!(restored.metadata.edited);
+        db_connection.update(&restored).unwrap();
+
+        assert_actions(&sync_notes(&db_connection, &profile, false).await.unwrap(), vec!["UpdateRemotely"]);
+        let remote = remote_texts(&server, "Notes");
+        assert_eq!(remote.len(), 1);
+        assert!(remote[0].contains("Original content"));
+
+        // The text that got replaced by restoring is part of the history as well
+        let revisions = history::revisions(db_connection.fetch_history("e2e-restore").unwrap());
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[1].source, history::LOCAL_EDIT);
+        assert!(revisions[1].text.contains("Changed on another device"));
+    }
+
+    /// Journal entry of an UpdateRemotely that got interrupted after the APPEND
+    fn interrupted_upload(db_connection: &Box<dyn DatabaseService + Send>, note: &LocalNote) {
+        db_connection.insert_journal_entry(&crate::model::JournalEntry {
+            uuid: note.metadata.uuid.clone(),
+            action: "UpdateRemotely".to_string(),
+            phase: journal::PLANNED.to_string(),
+            folder: "Notes".to_string(),
+            message_id: note.body[0].message_id.clone(),
+            uid: None
+        }).unwrap();
+    }
+
+    /// The upload reached the server, the old message and the database did not get updated
+    #[tokio::test]
+    async fn recover_interrupted_upload() {
+        let (server, profile, db_connection) = setup("e2e_recover_upload");
+        let (_, message) = note_message(&profile, "e2e-recover-upload", "Note\n\nOriginal content");
+        server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        edit_locally(&db_connection, "e2e-recover-upload", |text| text.replace("Original", "Edited"));
+        let edited = db_connection.fetch_single_note("e2e-recover-upload").unwrap().unwrap();
+        interrupted_upload(&db_connection, &edited);
+        let uid = server.append("Notes", &attachment::build_message(edited.to_header_vector(&profile), &convert_to_html(&edited.body[0]), &vec![]));
+
+        // Rolled forward, without the journal the two messages would need a merge
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec![]);
+
+        let messages = server.messages("Notes");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, uid);
+
+        let synced = db_connection.fetch_single_note("e2e-recover-upload").unwrap().unwrap();
+        assert_eq!(synced.metadata.edited, false);
+        assert_eq!(synced.body[0].message_id, edited.body[0].message_id);
+        assert_eq!(synced.body[0].uid, Some(uid as i64));
+        assert!(db_connection.fetch_journal().unwrap().is_empty());
+    }
+
+    /// The upload did not reach the server, the note gets uploaded again
+    #[tokio::test]
+    async fn recover_lost_upload() {
+        let (server, profile, db_connection) = setup("e2e_recover_lost_upload");
+        let (_, message) = note_message(&profile, "e2e-recover-lost-upload", "Note\n\nOriginal content");
+        server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        edit_locally(&db_connection, "e2e-recover-lost-upload", |text| text.replace("Original", "Edited"));
+        let edited = db_connection.fetch_single_note("e2e-recover-lost-upload").unwrap().unwrap();
+        interrupted_upload(&db_connection, &edited);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["UpdateRemotely"]);
+
+        let remote = remote_texts(&server, "Notes");
+        assert_eq!(remote.len(), 1);
+        assert!(remote[0].contains("Edited content"));
+        assert!(db_connection.fetch_journal().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_locally() {
+        let (server, profile, db_connection) = setup("e2e_update_locally");
+        let (_, message) = note_message(&profile, "e2e-update-locally", "Note\n\nOriginal content");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        // Another device replaces the message with an updated one
+        let (message_id, message) = note_message(&profile, "e2e-update-locally", "Note\n\nChanged on another device");
+        server.expunge("Notes", old_uid);
+        let uid = server.append("Notes", &message);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["UpdateLocally"]);
+
+        let note = db_connection.fetch_single_note("e2e-update-locally").unwrap().unwrap();
+        assert_eq!(note.body.len(), 1);
+        assert_eq!(note.body[0].message_id, message_id);
+        assert_eq!(note.body[0].uid, Some(uid as i64));
+        assert!(note.body[0].text.as_ref().unwrap().contains("Changed on another device"));
+    }
+
+    #[tokio::test]
+    async fn delete_remotely() {
+        let (server, profile, db_connection) = setup("e2e_delete_remotely");
+        let (_, message) = note_message(&profile, "e2e-delete-remotely", "Note\n\nTo be deleted");
+        server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        let mut note = db_connection.fetch_single_note("e2e-delete-remotely").unwrap().unwrap();
+        note.metadata.locally_deleted = true;
+        db_connection.update(&note).unwrap();
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["DeleteRemote"]);
+
+        assert!(server.messages("Notes").is_empty());
+        assert!(db_connection.fetch_single_note("e2e-delete-remotely").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_locally() {
+        let (server, profile, db_connection) = setup("e2e_delete_locally");
+        let (_, message) = note_message(&profile, "e2e-delete-locally", "Note\n\nDeleted on another device");
+        let uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+
+        server.expunge("Notes", uid);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["DeleteLocally"]);
+        assert!(db_connection.fetch_single_note("e2e-delete-locally").unwrap().is_none());
+    }
+
+    /// Changes of different lines are merged and uploaded with the next sync
+    #[tokio::test]
+    async fn merge_changed_on_both_sides() {
+        let (server, profile, db_connection) = setup("e2e_merge");
+        let (_, message) = note_message(&profile, "e2e-merge", "Note\n\nFirst line\n\nSecond line\n\nThird line");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+        let synced_text = db_connection.fetch_single_note("e2e-merge").unwrap().unwrap().body[0].text.clone().unwrap();
+
+        edit_locally(&db_connection, "e2e-merge", |text| text.replace("First line", "First line edited locally"));
+
+        let (_, message) = note_message(&profile, "e2e-merge", &synced_text.replace("Third line", "Third line edited remotely"));
+        server.expunge("Notes", old_uid);
+        server.append("Notes", &message);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["Merge"]);
+
+        let merged = db_connection.fetch_single_note("e2e-merge").unwrap().unwrap();
+        assert_eq!(merged.body.len(), 1);
+        let text = merged.body[0].text.clone().unwrap();
+        assert!(text.contains("First line edited locally"));
+        assert!(text.contains("Third line edited remotely"));
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["UpdateRemotely"]);
+
+        let remote = remote_texts(&server, "Notes");
+        assert_eq!(remote.len(), 1);
+        assert!(remote[0].contains("First line edited locally"));
+        assert!(remote[0].contains("Third line edited remotely"));
+    }
+
+    /// Changes of the same line can not be merged, both versions are kept until the user merges them
+    #[tokio::test]
+    async fn conflict() {
+        let (server, profile, db_connection) = setup("e2e_conflict");
+        let (_, message) = note_message(&profile, "e2e-conflict", "Note\n\nFirst line\n\nSecond line");
+        let old_uid = server.append("Notes", &message);
+        sync_notes(&db_connection, &profile, false).await.unwrap();
+        let synced_text = db_connection.fetch_single_note("e2e-conflict").unwrap().unwrap().body[0].text.clone().unwrap();
+
+        edit_locally(&db_connection, "e2e-conflict", |text| text.replace("Second line", "Second line, local version"));
+
+        let (_, message) = note_message(&profile, "e2e-conflict", &synced_text.replace("Second line", "Second line, remote version"));
+        server.expunge("Notes", old_uid);
+        server.append("Notes", &message);
+
+        let results = sync_notes(&db_connection, &profile, false).await.unwrap();
+        assert_actions(&results, vec!["Merge"]);
+
+        let note = db_connection.fetch_single_note("e2e-conflict").unwrap().unwrap();
+        assert!(note.needs_merge());
+
+        // The remote note stays untouched until the note got merged
+        let remote = remote_texts(&server, "Notes");
+        assert_eq!(remote.len(), 1);
+        assert!(remote[0].contains("remote version"));
+    }
+}
@@ -0,0 +1,97 @@
+use regex::Regex;
+
+/// Placeholders that mark table rows and cells before the html gets converted to markdown,
+/// they only consist of letters, so that html2runes leaves them untouched
+const HEAD_PLACEHOLDER: &str = "APNOTESTABLEHEAD";
+const ROW_PLACEHOLDER: &str = "APNOTESTABLEROW";
+const CELL_PLACEHOLDER: &str = "APNOTESTABLECELL";
+
+/// Opening tags of a table the way the notes app writes them
+pub const TABLE_START: &str = "<object><table cellspacing=\"0\" cellpadding=\"0\" style=\"border-collapse: collapse; direction: ltr\"><tbody>";
+pub const TABLE_END: &str = "</tbody></table></object>";
+pub const CELL_START: &str = "<td valign=\"top\" style=\"border-style: solid; border-width: 1.0px 1.0px 1.0px 1.0px; border-color: #ccc; padding: 3.0px 5.0px 3.0px 5.0px; min-width: 70px\"><div>";
+pub const CELL_END: &str = "</div></td>";
+
+/// Replaces every table with one line per row, the cells are separated by placeholders.
+/// The notes app does not know header rows, so the first row becomes the header of the
+/// markdown table
+pub fn tables_to_placeholders(html: &str) -> String {
+    let table_regex = Regex::new(r#"(?is)(?:<object[^>]*>\s*)?<table[^>]*>(.*?)</table>(?:\s*</object>)?"#).unwrap();
+    let row_regex = Regex::new(r#"(?is)<tr[^>]*>(.*?)</tr>"#).unwrap();
+    let cell_regex = Regex::new(r#"(?is)<t[dh][^>]*>(.*?)</t[dh]>"#).unwrap();
+    let line_break_regex = Regex::new(r#"(?is)<br[^>]*>"#).unwrap();
+    let block_regex = Regex::new(r#"(?is)</?(div|p)[^>]*>"#).unwrap();
+
+    table_regex.replace_all(html, |table: &regex::Captures| {
+        row_regex.captures_iter(&table[1]).enumerate().map(|(index, row)| {
+            let cells: Vec<String> = cell_regex.captures_iter(&row[1]).map(|cell| {
+                // Cells have to fit into a single markdown line
+                let cell = line_break_regex.replace_all(&cell[1], " ");
+                block_regex.replace_all(&cell, "").trim().to_string()
+            }).collect();
+            let placeholder = if index == 0 { HEAD_PLACEHOLDER } else { ROW_PLACEHOLDER };
+            format!("<div>{} {} {}</div>", placeholder, cells.join(&format!(" {} ", CELL_PLACEHOLDER)), CELL_PLACEHOLDER)
+        }).collect::<String>()
+    }).into_owned()
+}
+
+/// Turns the placeholders of tables_to_placeholders into GFM pipe tables, tables are
+/// surrounded by blank lines, so that they neither join a paragraph nor absorb the next line
+pub fn placeholders_to_markdown(markdown: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut inside_table = false;
+
+    for line in markdown.split('\n') {
+        let trimmed = line.trim_start();
+        let (row, is_head) = match (trimmed.strip_prefix(HEAD_PLACEHOLDER), trimmed.strip_prefix(ROW_PLACEHOLDER)) {
+            (Some(row), _) => (row, true),
+            (_, Some(row)) => (row, false),
+            _ => {
+                if inside_table && line.trim().is_empty() == false {
+                    lines.push("".to_string());
+                }
+                inside_table = false;
+                lines.push(line.to_string());
+                continue;
+            }
+        };
+
+        let cells: Vec<String> = row.split(CELL_PLACEHOLDER)
+            .map(|cell| cell.trim().replace('|', "\\|"))
+            .collect();
+        // The last placeholder terminates the row
+        let cells = &cells[..cells.len() - 1];
+
+        if is_head {
+            if lines.last().map(|last| last.trim().is_empty()).unwrap_or(true) == false {
+                lines.push("".to_string());
+            }
+            lines.push(format!("| {} |", cells.join(" | ")));
+            lines.push(format!("|{}", " --- |".repeat(cells.len())));
+        } else {
+            lines.push(format!("| {} |", cells.join(" | ")));
+        }
+        inside_table = true;
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod table_tests {
+    use crate::table::*;
+
+    #[test]
+    fn table_placeholders() {
+        let html = format!("<div>Prices</div>{}<tr>{}Item{}{}Price{}</tr><tr>{}Milk<br>1l{}{}1|2{}</tr>{}<br>",
+                           TABLE_START, CELL_START, CELL_END, CELL_START, CELL_END, CELL_START, CELL_END, CELL_START, CELL_END, TABLE_END);
+
+        let placeholders = tables_to_placeholders(&html);
+        assert_eq!(placeholders, format!(
+            "<div>Prices</div><div>{0} Item {2} Price {2}</div><div>{1} Milk 1l {2} 1|2 {2}</div><br>",
+            HEAD_PLACEHOLDER, ROW_PLACEHOLDER, CELL_PLACEHOLDER));
+
+        let markdown = format!("Prices\n{0} Item {2} Price {2}\n{1} Milk 1l {2} 1|2 {2}\nTotal", HEAD_PLACEHOLDER, ROW_PLACEHOLDER, CELL_PLACEHOLDER);
+        assert_eq!(placeholders_to_markdown(&markdown), "Prices\n\n| Item | Price |\n| --- | --- |\n| Milk 1l | 1\\|2 |\n\nTotal");
+    }
+}
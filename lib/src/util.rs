@@ -14,6 +14,33 @@ pub fn is_uuid(string: &str) -> bool {
     uuid_regex.is_match(string)
 }
 
+/// Returns the imap folder name of a note subfolder, an empty
+/// name refers to the default "Notes" folder. Nested folders
+/// are separated with "/", "todo/vacation" becomes "Notes.todo.vacation"
+pub fn imap_folder_name(folder: &str) -> String {
+    if folder.len() > 0 {
+        format!("Notes.{}", folder.replace('/', "."))
+    } else {
+        "Notes".to_string()
+    }
+}
+
+/// Reverse of imap_folder_name
+pub fn display_folder_name(imap_folder: &str) -> String {
+    if imap_folder == "Notes" {
+        "".to_string()
+    } else {
+        imap_folder.strip_prefix("Notes.")
+            .map(|folder| folder.replace('.', "/"))
+            .unwrap_or(imap_folder.to_string())
+    }
+}
+
+/// Checks if imap_folder is the passed folder or one of its subfolders
+pub fn is_inside_folder(imap_folder: &str, folder: &str) -> bool {
+    imap_folder == folder || imap_folder.starts_with(&format!("{}.", folder))
+}
+
 pub fn filter_none<S>(e: Option<S>) -> Option<S> {
     if e.is_some() {
         e
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::time::Duration;
+use std::thread::{self, sleep};
+use tokio::task::block_in_place;
+use crate::apple_imap::{MailServiceImpl, MailService, FolderStatus};
+use crate::profile::Profile;
+use crate::error::{Result, UpdateError};
+
+/// Time after which IDLE gets restarted and all note folders get polled
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Upper limit of the time between two reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Sent by the IDLE connections of the note folders
+enum IdleEvent {
+    /// IDLE returned, the folder might have changed
+    Returned(String),
+    /// The IDLE connection of the folder failed
+    Failed(String, String)
+}
+
+/// Keeps a connection to the imap server open and calls on_change every time
+/// a note folder changed. It also gets called after every (re)connect, because
+/// notes might have changed while the connection was down.
+///
+/// IDLE can only watch the selected folder, so every note folder gets its own
+/// connection that idles on it, see idle_folder. Every time one of them returns,
+/// and at least every idle timeout, all note folders are compared with STATUS.
+/// If a connection gets lost it reconnects with exponential backoff.
+///
+/// Polling and the backoff block the thread, they run with block_in_place so
+/// that the other tasks of the runtime move to another worker in the meantime.
+///
+/// Returns as soon as on_change returns false
+pub(crate) async fn watch<F>(profile: &Profile, on_change: F) -> Result<()>
+    where F: FnMut(&mut MailServiceImpl) -> bool
+{
+    watch_with_timeout(profile, IDLE_TIMEOUT, on_change).await
+}
+
+async fn watch_with_timeout<F>(profile: &Profile, idle_timeout: Duration, mut on_change: F) -> Result<()>
+    where F: FnMut(&mut MailServiceImpl) -> bool
+{
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let result = match MailServiceImpl::new_with_login(profile).await {
+            Ok(mut imap_service) => {
+                backoff = Duration::from_secs(1);
+                block_in_place(|| watch_session(profile, &mut imap_service, idle_timeout, &mut on_change))
+            }
+            Err(e) => Err(e)
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warn!("Lost connection to imap server: {}, reconnecting in {}s", e, backoff.as_secs());
+                block_in_place(|| sleep(backoff));
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+fn watch_session<F>(profile: &Profile, imap_service: &mut MailServiceImpl, idle_timeout: Duration, on_change: &mut F) -> Result<()>
+    where F: FnMut(&mut MailServiceImpl) -> bool
+{
+    let supports_idle = imap_service.supports_idle()?;
+    if supports_idle == false {
+        warn!("Server does not support IDLE, polling every {}s", idle_timeout.as_secs());
+    }
+
+    if on_change(imap_service) == false {
+        return imap_service.logout();
+    }
+
+    let mut last_status = note_folder_status(imap_service)?;
+
+    // The IDLE connections end as soon as the receiver is gone
+    let (events, receiver) = channel();
+    let mut idling: HashSet<String> = HashSet::new();
+
+    loop {
+        if supports_idle {
+            // Folders that got created in the meantime get watched as well
+            for (folder, _) in &last_status {
+                if idling.insert(folder.clone()) {
+                    idle_folder(profile.clone(), folder.clone(), idle_timeout, events.clone());
+                }
+            }
+        }
+
+        match receiver.recv_timeout(idle_timeout) {
+            Ok(IdleEvent::Failed(folder, e)) => {
+                return Err(UpdateError::SyncError(format!("IDLE on {} failed: {}", folder, e)).into());
+            }
+            Ok(IdleEvent::Returned(folder)) => debug!("IDLE on {} returned", folder),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {}
+        }
+
+        let status = note_folder_status(imap_service)?;
+        if status != last_status {
+            info!("Remote notes changed");
+            if on_change(imap_service) == false {
+                return imap_service.logout();
+            }
+            // Take the status after on_change, so that changes made by on_change itself are ignored
+            last_status = note_folder_status(imap_service)?;
+        }
+    }
+}
+
+/// Waits with IDLE for changes of folder on a connection of its own and sends an
+/// event every time IDLE returns. The thread ends after the next event, if nobody
+/// receives the events anymore
+fn idle_folder(profile: Profile, folder: String, idle_timeout: Duration, events: Sender<IdleEvent>) {
+    thread::spawn(move || {
+        // The thread does not belong to a runtime, the login needs one of its own
+        let login: Result<MailServiceImpl> = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(|e| e.into())
+            .and_then(|runtime| runtime.block_on(MailServiceImpl::new_with_login(&profile)));
+
+        let mut imap_service = match login {
+            Ok(imap_service) => imap_service,
+            Err(e) => {
+                let _ = events.send(IdleEvent::Failed(folder, e.to_string()));
+                return;
+            }
+        };
+
+        loop {
+            if let Err(e) = imap_service.idle(&folder, idle_timeout) {
+                let _ = events.send(IdleEvent::Failed(folder, e.to_string()));
+                return;
+            }
+            if events.send(IdleEvent::Returned(folder.clone())).is_err() {
+                let _ = imap_service.logout();
+                return;
+            }
+        }
+    });
+}
+
+fn note_folder_status(imap_service: &mut MailServiceImpl) -> Result<Vec<(String, FolderStatus)>> {
+    imap_service.list_note_folders()?
+        .into_iter()
+        .map(|folder| imap_service.folder_status(&folder).map(|status| (folder, status)))
+        .collect()
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use crate::fake_imap::FakeImapServer;
+    use std::time::Instant;
+
+    const MESSAGE: &str = "Subject: Note\r\nContent-Type: text/html\r\n\r\n<div>Note</div>";
+
+    /// Changes are detected by comparing the folder status after IDLE returned,
+    /// a lost connection gets re-established
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detect_changes_and_reconnect() {
+        let server = FakeImapServer::start();
+        let profile = server.profile("watch_reconnect");
+        let mut calls = 0;
+
+        watch_with_timeout(&profile, Duration::from_millis(200), |_| {
+            calls += 1;
+            match calls {
+                // Initial connect, another device adds a note while idling
+                1 => {
+                    let server = server.clone();
+                    std::thread::spawn(move || {
+                        sleep(Duration::from_millis(300));
+                        server.append("Notes", MESSAGE);
+                    });
+                    true
+                }
+                // The change got detected, the connection gets lost afterwards
+                2 => {
+                    assert_eq!(server.messages("Notes").len(), 1);
+                    server.disconnect();
+                    true
+                }
+                // Reconnected
+                _ => false
+            }
+        }).await.unwrap();
+
+        assert_eq!(calls, 3);
+    }
+
+    /// Every note folder gets watched with IDLE, so a change inside a subfolder
+    /// gets detected long before the idle timeout elapsed
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detect_changes_in_subfolder() {
+        let server = FakeImapServer::start();
+        server.create_folder("Notes.Work");
+        let profile = server.profile("watch_subfolder");
+        let started = Instant::now();
+        let mut calls = 0;
+
+        watch_with_timeout(&profile, Duration::from_secs(60), |_| {
+            calls += 1;
+            if calls == 1 {
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    // Gives the IDLE connections time to start
+                    sleep(Duration::from_millis(500));
+                    server.append("Notes.Work", MESSAGE);
+                });
+                return true;
+            }
+            false
+        }).await.unwrap();
+
+        assert_eq!(calls, 2);
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+}
@@ -0,0 +1 @@
+ALTER TABLE body DROP COLUMN base_text;
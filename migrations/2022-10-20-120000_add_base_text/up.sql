@@ -0,0 +1 @@
+ALTER TABLE body ADD base_text VARCHAR;
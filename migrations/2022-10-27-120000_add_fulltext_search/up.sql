@@ -0,0 +1,2 @@
+CREATE VIRTUAL TABLE body_fts USING fts5(message_id UNINDEXED, metadata_uuid UNINDEXED, text);
+INSERT INTO body_fts(message_id, metadata_uuid, text) SELECT message_id, metadata_uuid, text FROM body;
@@ -0,0 +1,2 @@
+DROP TABLE remote_header;
+DROP TABLE folder_sync_state;
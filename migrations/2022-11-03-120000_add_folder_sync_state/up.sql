@@ -0,0 +1,14 @@
+CREATE TABLE folder_sync_state (
+    folder VARCHAR PRIMARY KEY NOT NULL,
+    uid_validity BIGINT NOT NULL,
+    uid_next BIGINT NOT NULL,
+    highest_modseq BIGINT,
+    messages BIGINT NOT NULL
+);
+
+CREATE TABLE remote_header (
+    folder VARCHAR NOT NULL,
+    uid BIGINT NOT NULL,
+    headers VARCHAR NOT NULL,
+    PRIMARY KEY (folder, uid)
+);
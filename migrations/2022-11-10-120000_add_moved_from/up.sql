@@ -0,0 +1 @@
+ALTER TABLE metadata ADD moved_from VARCHAR;
@@ -0,0 +1 @@
+ALTER TABLE body ADD html VARCHAR;
@@ -0,0 +1,8 @@
+CREATE TABLE attachment (
+    content_id VARCHAR NOT NULL,
+    metadata_uuid VARCHAR NOT NULL,
+    file_name VARCHAR NOT NULL,
+    mime_type VARCHAR NOT NULL,
+    blob VARCHAR NOT NULL,
+    PRIMARY KEY (metadata_uuid, content_id)
+);
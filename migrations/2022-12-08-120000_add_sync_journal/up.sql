@@ -0,0 +1,8 @@
+CREATE TABLE sync_journal (
+    uuid VARCHAR PRIMARY KEY NOT NULL,
+    action VARCHAR NOT NULL,
+    phase VARCHAR NOT NULL,
+    folder VARCHAR NOT NULL,
+    message_id VARCHAR NOT NULL,
+    uid BIGINT
+);
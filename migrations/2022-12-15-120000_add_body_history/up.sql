@@ -0,0 +1,9 @@
+CREATE TABLE body_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    metadata_uuid VARCHAR NOT NULL,
+    message_id VARCHAR NOT NULL,
+    text TEXT NOT NULL,
+    date VARCHAR NOT NULL,
+    source VARCHAR NOT NULL
+);
+CREATE INDEX body_history_metadata_uuid ON body_history (metadata_uuid);